@@ -0,0 +1,173 @@
+use std::io;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::io::prelude::*;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::collation;
+
+/**
+ * Audit trail of every change to the library, one JSON entry per line in the
+ * `auditlog` file:
+ *
+ *   {"time":..,"staff":..,"operation":..,"record":..,"before":..,"after":..,"hash":..}
+ *
+ * The file is only ever appended to. The time is the real time of the change,
+ * not the `--today` date, so back-dated returns still show when they were made.
+ * Each hash covers the entry and the hash before it, an entry that is changed
+ * or removed later breaks the chain from that line on (see `verify`).
+ */
+const AUDIT_PATH: &str = "auditlog";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub staff: String,
+    pub operation: String,
+    pub record: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    #[serde(default)]
+    pub hash: String
+}
+
+// entries are kept when every given filter matches
+#[derive(Default)]
+pub struct Filter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub staff: Option<String>,
+    pub record: Option<String>
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let date = entry.time.naive_utc().date();
+        self.from.map(|from| date >= from).unwrap_or(true)
+            && self.to.map(|to| date <= to).unwrap_or(true)
+            && self.staff.as_ref().map(|s| collation::same(&entry.staff, s)).unwrap_or(true)
+            && self.record.as_ref().map(|r| collation::contains(&entry.record, r)).unwrap_or(true)
+    }
+}
+
+fn entry_hash(previous: &str, entry: &AuditEntry) -> String {
+    let unhashed = AuditEntry { hash: String::new(), ..entry.clone() };
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(serde_json::to_string(&unhashed).unwrap_or_default().as_bytes());
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_log() -> io::Result<String> {
+    let mut data = String::new();
+    match File::open(Path::new(AUDIT_PATH)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+        Ok(mut file) => {
+            file.read_to_string(&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+pub fn load_entries() -> io::Result<Vec<AuditEntry>> {
+    read_log()?.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect()
+}
+
+// the first entry that does not follow the one before it, counted from 1
+pub fn verify(entries: &[AuditEntry]) -> Option<usize> {
+    let mut previous = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry_hash(&previous, entry) != entry.hash {
+            return Some(i + 1);
+        }
+        previous = entry.hash.clone();
+    }
+    None
+}
+
+// append a change made by a staff account, with the record before and after it
+pub fn record(staff: &str, operation: &str, record: &str, before: Option<Value>, after: Option<Value>) -> io::Result<()> {
+    let previous = match read_log()?.lines().rev().find(|line| !line.trim().is_empty()) {
+        None => String::new(),
+        Some(line) => serde_json::from_str::<AuditEntry>(line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .hash
+    };
+    let mut entry = AuditEntry {
+        time: Utc::now(),
+        staff: staff.trim().to_string(),
+        operation: operation.to_string(),
+        record: record.to_string(),
+        before,
+        after,
+        hash: String::new()
+    };
+    entry.hash = entry_hash(&previous, &entry);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(AUDIT_PATH))?;
+    writeln!(&mut file, "{}", serde_json::to_string(&entry)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::clock::day;
+    use crate::library;
+
+    fn entry(time: &str, staff: &str, record: &str) -> AuditEntry {
+        AuditEntry {
+            time: time.parse().unwrap(),
+            staff: staff.to_string(),
+            operation: "issue".to_string(),
+            record: record.to_string(),
+            before: None,
+            after: Some(json!({ "borrower": "Kim" })),
+            hash: String::new()
+        }
+    }
+
+    #[test]
+    fn each_hash_follows_the_entry_before_it() {
+        let entries = library::in_data_dir("audit-chain", &[], || {
+            record("Lee", "issue", "loan Dune [0] to Kim", None, Some(json!({ "borrower": "Kim" }))).unwrap();
+            record("Lee", "return", "loan Dune [0] to Kim", Some(json!({ "borrower": "Kim" })), None).unwrap();
+            record("Ada", "add book", "book [1] Emma", None, Some(json!({ "name": "Emma" }))).unwrap();
+            load_entries().unwrap()
+        });
+        assert_eq!(entries.len(), 3);
+        assert_eq!(verify(&entries), None);
+
+        let mut changed = entries.clone();
+        changed[1].staff = "Ada".to_string();
+        assert_eq!(verify(&changed), Some(2));
+        let mut removed = entries.clone();
+        removed.remove(0);
+        assert_eq!(verify(&removed), Some(1));
+        assert_eq!(verify(&entries[..2]), None);
+    }
+
+    #[test]
+    fn filter_keeps_entries_matching_every_given_field() {
+        let entries = [
+            entry("2024-03-01T09:00:00Z", "Lee", "loan Dune [0] to Kim"),
+            entry("2024-03-02T23:59:00Z", "Ada", "loan Golang [1] to Kim"),
+            entry("2024-03-03T00:00:00Z", "lee", "book [2] Emma")
+        ];
+        let kept = |filter: &Filter| entries.iter().filter(|e| filter.matches(e)).count();
+        assert_eq!(kept(&Filter::default()), 3);
+        assert_eq!(kept(&Filter { from: Some(day(2024, 3, 2)), ..Filter::default() }), 2);
+        assert_eq!(kept(&Filter { to: Some(day(2024, 3, 2)), ..Filter::default() }), 2);
+        assert_eq!(kept(&Filter { staff: Some("LEE".to_string()), ..Filter::default() }), 2);
+        assert_eq!(kept(&Filter { record: Some("dune".to_string()), ..Filter::default() }), 1);
+        assert_eq!(kept(&Filter { staff: Some("Lee".to_string()), record: Some("loan".to_string()), ..Filter::default() }), 1);
+    }
+}
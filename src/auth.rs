@@ -0,0 +1,365 @@
+use std::io;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::io::prelude::*;
+use std::fmt;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::NaiveDate;
+use sha2::{Digest, Sha256};
+use crate::collation;
+use crate::i18n::t;
+
+/**
+ * Staff accounts and API tokens.
+ *
+ * The `staff` file keeps one `name,role,password hash` per line, the password
+ * is hashed with Argon2 and never stored. The `tokens` file keeps one
+ * `token hash,name,created` per line for the REST API, a token is only shown
+ * once when it is issued and it has the role of its staff account.
+ *
+ *   admin     everything, also staff accounts, waivers, refunds, backup, restore and purging the trash
+ *   librarian circulation, catalogue, patrons and payments
+ *   kiosk     read only, lists and searches
+ */
+const STAFF_PATH: &str = "staff";
+const TOKEN_PATH: &str = "tokens";
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+// a password no account has, checked for names without an account so that
+// signing in with an unknown name takes as long as with a wrong password
+const UNKNOWN_ACCOUNT_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$HkdPqHMrnpmkvj7i2y/cqA$Ir8fPhvx86I1kqFiLa8w04dhk6S7A994VTiArCyS7co";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Admin,
+    Librarian,
+    Kiosk
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Option<Role> {
+        match s.trim().to_lowercase().as_str() {
+            "admin" => Some(Role::Admin),
+            "librarian" => Some(Role::Librarian),
+            "kiosk" | "read-only" | "readonly" => Some(Role::Kiosk),
+            _ => None
+        }
+    }
+
+    pub fn allows(self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Librarian => !matches!(permission, Permission::Waivers | Permission::Administration),
+            Role::Kiosk => false
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Admin => "admin",
+            Role::Librarian => "librarian",
+            Role::Kiosk => "kiosk"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// what a mutating operation needs, reading is open to every role
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Permission {
+    Circulation,
+    Catalogue,
+    Patrons,
+    Payments,
+    Waivers,
+    Administration
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = match self {
+            Permission::Circulation => "permission.circulation",
+            Permission::Catalogue => "permission.catalogue",
+            Permission::Patrons => "permission.patrons",
+            Permission::Payments => "permission.payments",
+            Permission::Waivers => "permission.waivers",
+            Permission::Administration => "permission.administration"
+        };
+        write!(f, "{}", t!(key))
+    }
+}
+
+#[derive(Debug)]
+pub struct Forbidden {
+    pub name: String,
+    pub role: Role,
+    pub permission: Permission
+}
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", t!("permission.denied", name = self.name, role = self.role, action = self.permission))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Staff {
+    pub name: String,
+    pub role: Role,
+    password_hash: String
+}
+
+impl Staff {
+    pub fn is_named(&self, name: &str) -> bool {
+        collation::same(&self.name, name)
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), Forbidden> {
+        if self.role.allows(permission) {
+            return Ok(());
+        }
+        Err(Forbidden { name: self.name.clone(), role: self.role, permission })
+    }
+
+    // an account that cannot sign in, for tests of what a role may do
+    #[cfg(test)]
+    pub fn without_password(name: &str, role: Role) -> Staff {
+        Staff { name: name.to_string(), role, password_hash: String::new() }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        verify_password(&self.password_hash, password)
+    }
+}
+
+impl fmt::Display for Staff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{}", self.name, self.role, self.password_hash)
+    }
+}
+
+fn parse_staff(line: &str) -> Option<Staff> {
+    // the hash has commas of its own, so it is everything after the role
+    let slice_data: Vec<&str> = line.splitn(3, ',').collect();
+    if slice_data.len() < 3 || slice_data[0].trim().is_empty() {
+        return None;
+    }
+    Some(Staff {
+        name: slice_data[0].trim().to_string(),
+        role: Role::parse(slice_data[1])?,
+        password_hash: slice_data[2].trim().to_string()
+    })
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(t!("staff.short_password", length = MIN_PASSWORD_LENGTH));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| t!("staff.hash_failed", error = e))
+}
+
+fn verify_password(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+fn read_lines(path: &str) -> io::Result<String> {
+    let mut data = String::new();
+    match File::open(Path::new(path)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+        Ok(mut file) => {
+            file.read_to_string(&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+pub fn load_staff() -> io::Result<Vec<Staff>> {
+    Ok(read_lines(STAFF_PATH)?.lines().filter_map(parse_staff).collect())
+}
+
+pub fn save_staff(staff: &[Staff]) -> io::Result<()> {
+    let mut data = String::new();
+    for account in staff {
+        data.push_str(&account.to_string());
+    }
+    File::create(Path::new(STAFF_PATH))?.write_all(data.as_bytes())
+}
+
+pub fn find(name: &str) -> io::Result<Option<Staff>> {
+    Ok(load_staff()?.into_iter().find(|s| s.is_named(name)))
+}
+
+pub fn add_staff(name: &str, role: Role, password: &str) -> Result<Staff, String> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(',') {
+        return Err(t!("staff.invalid_name"));
+    }
+    let mut staff = load_staff().map_err(|e| e.to_string())?;
+    if staff.iter().any(|s| s.is_named(name)) {
+        return Err(t!("staff.exists", name = name));
+    }
+    let account = Staff { name: name.to_string(), role, password_hash: hash_password(password)? };
+    staff.push(account.clone());
+    save_staff(&staff).map_err(|e| e.to_string())?;
+    Ok(account)
+}
+
+pub fn set_password(name: &str, password: &str) -> Result<(), String> {
+    let mut staff = load_staff().map_err(|e| e.to_string())?;
+    let account = staff.iter_mut()
+        .find(|s| s.is_named(name))
+        .ok_or_else(|| t!("staff.no_account", name = name.trim()))?;
+    account.password_hash = hash_password(password)?;
+    save_staff(&staff).map_err(|e| e.to_string())
+}
+
+// the account and its API tokens are removed, the last admin is kept
+pub fn remove_staff(name: &str) -> Result<(), String> {
+    let mut staff = load_staff().map_err(|e| e.to_string())?;
+    let account = staff.iter()
+        .find(|s| s.is_named(name))
+        .ok_or_else(|| t!("staff.no_account", name = name.trim()))?;
+    if account.role == Role::Admin && staff.iter().filter(|s| s.role == Role::Admin).count() == 1 {
+        return Err(t!("staff.last_admin", name = account.name));
+    }
+    staff.retain(|s| !s.is_named(name));
+    save_staff(&staff).map_err(|e| e.to_string())?;
+    revoke_tokens(name).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// the account when the name and password match
+pub fn login(name: &str, password: &str) -> io::Result<Option<Staff>> {
+    match find(name)? {
+        Some(account) => Ok(Some(account).filter(|s| s.verify(password))),
+        None => {
+            verify_password(UNKNOWN_ACCOUNT_HASH, password);
+            Ok(None)
+        }
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.trim().as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// a new random API token for a staff account, only its hash is kept
+pub fn issue_token(name: &str, today: NaiveDate) -> io::Result<String> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(TOKEN_PATH))?;
+    writeln!(&mut file, "{},{},{}", token_hash(&token), name.trim(), today)?;
+    Ok(token)
+}
+
+// the staff account of an API token, tokens of removed accounts are not valid
+pub fn staff_for_token(token: &str) -> io::Result<Option<Staff>> {
+    let hash = token_hash(token);
+    let data = read_lines(TOKEN_PATH)?;
+    let name = data.lines()
+        .filter_map(|line| line.split_once(','))
+        .find(|(h, _)| *h == hash)
+        .and_then(|(_, rest)| rest.split(',').next().map(|n| n.to_string()));
+    match name {
+        None => Ok(None),
+        Some(name) => find(&name)
+    }
+}
+
+// returns the number of tokens revoked
+pub fn revoke_tokens(name: &str) -> io::Result<usize> {
+    let data = read_lines(TOKEN_PATH)?;
+    let mut kept = String::new();
+    let mut revoked = 0;
+    for line in data.lines() {
+        let owner = line.split(',').nth(1).unwrap_or("");
+        if collation::same(owner, name) {
+            revoked += 1;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if revoked > 0 {
+        File::create(Path::new(TOKEN_PATH))?.write_all(kept.as_bytes())?;
+    }
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+    use crate::library;
+
+    fn staff_file() -> String {
+        format!("Ada,admin,{}\nLee,librarian,{}\n", UNKNOWN_ACCOUNT_HASH, UNKNOWN_ACCOUNT_HASH)
+    }
+
+    #[test]
+    fn roles_allow_their_permissions() {
+        let all = [Permission::Circulation, Permission::Catalogue, Permission::Patrons,
+            Permission::Payments, Permission::Waivers, Permission::Administration];
+        assert!(all.iter().all(|p| Role::Admin.allows(*p)));
+        assert!(all.iter().all(|p| !Role::Kiosk.allows(*p)));
+        let librarian: Vec<Permission> = all.iter().copied().filter(|p| Role::Librarian.allows(*p)).collect();
+        assert_eq!(librarian, [Permission::Circulation, Permission::Catalogue, Permission::Patrons, Permission::Payments]);
+        assert_eq!(Role::parse(" Read-Only "), Some(Role::Kiosk));
+        assert!(Staff::without_password("Lee", Role::Librarian).require(Permission::Waivers).is_err());
+    }
+
+    #[test]
+    fn passwords_are_hashed_and_verified() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(!hash.contains("correct horse"));
+        assert!(verify_password(&hash, "correct horse"));
+        assert!(!verify_password(&hash, "correct horse "));
+        assert!(!verify_password("not a hash", "correct horse"));
+        assert!(hash_password("short").is_err());
+        // unknown names are checked against a real hash
+        assert!(PasswordHash::new(UNKNOWN_ACCOUNT_HASH).is_ok());
+    }
+
+    #[test]
+    fn tokens_are_looked_up_by_hash_and_revoked_with_their_account() {
+        let staff = staff_file();
+        library::in_data_dir("auth-tokens", &[("staff", &staff)], || {
+            let token = issue_token("Lee", day(2024, 3, 1)).unwrap();
+            let other = issue_token("Ada", day(2024, 3, 1)).unwrap();
+            assert!(!read_lines(TOKEN_PATH).unwrap().contains(&token));
+            assert_eq!(staff_for_token(&token).unwrap().unwrap().name, "Lee");
+            assert!(staff_for_token("not a token").unwrap().is_none());
+            assert_eq!(revoke_tokens("LEE").unwrap(), 1);
+            assert!(staff_for_token(&token).unwrap().is_none());
+            assert_eq!(staff_for_token(&other).unwrap().unwrap().name, "Ada");
+        });
+    }
+
+    #[test]
+    fn the_last_admin_is_kept() {
+        let staff = staff_file();
+        library::in_data_dir("auth-admin", &[("staff", &staff)], || {
+            assert!(login("Kim", "a password").unwrap().is_none());
+            assert!(remove_staff("Ada").is_err());
+            assert!(remove_staff("Lee").is_ok());
+            assert!(remove_staff("Lee").is_err());
+            assert_eq!(load_staff().unwrap().len(), 1);
+        });
+    }
+}
@@ -0,0 +1,368 @@
+use std::io;
+use std::fs::{self, File};
+use std::path::Path;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use crate::Book;
+use crate::hold::{self, Hold};
+use crate::i18n::t;
+use crate::ledger::{self, LedgerEntry};
+use crate::library::LibraryError;
+use crate::loan::{self, Loan};
+use crate::patron::{self, Patron};
+use crate::staging::{self, DataFile};
+use crate::trash::{self, Withdrawn};
+
+/**
+ * Backup of the whole library as a gzipped tarball of JSON files:
+ *
+ *   manifest.json  format version, creation date, record count and sha256 of every file
+ *   books.json     titles with their copies (the `librarystore` lines)
+ *   patrons.json, loans.json, holds.json, ledger.json
+ *   trash.json     withdrawn copies, from version 2
+ *
+ * Every copy keeps its line number in the library store, a restore writes
+ * the copies back on the same lines so book numbers in the REST API, the
+ * audit trail and receipts still point at the same copies.
+ *
+ * A restore checks the manifest and every checksum and parses every file
+ * before anything in the store is replaced, then replaces every file at once.
+ */
+const FORMAT: &str = "rusty-library-backup";
+const VERSION: u32 = 2;
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BookCopy {
+    pub borrowed: bool,
+    pub issue_date: NaiveDate,
+    #[serde(default)]
+    pub barcode: String,
+    // line number in the library store, counted from 0, missing in version 1 backups
+    #[serde(default)]
+    pub line: Option<usize>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BookRecord {
+    pub name: String,
+    pub author: String,
+    pub year_published: u32,
+    pub item_type: String,
+    pub isbn: String,
+    pub copies: Vec<BookCopy>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileEntry {
+    pub name: String,
+    pub records: usize,
+    pub sha256: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    pub created: NaiveDate,
+    pub files: Vec<FileEntry>
+}
+
+// the copies of a title are grouped under its first line in the library store
+pub fn titles(books: &[Book]) -> Vec<BookRecord> {
+    let mut records: Vec<BookRecord> = vec![];
+    for (line, book) in books.iter().enumerate() {
+        let copy = BookCopy { borrowed: book.borrowed, issue_date: book.issue_date, barcode: book.barcode.to_string(), line: Some(line) };
+        let same_title = records.iter_mut().find(|r| r.name == book.name && r.author == book.author
+            && r.year_published == book.year_published && r.item_type == book.item_type && r.isbn == book.isbn);
+        match same_title {
+            Some(record) => record.copies.push(copy),
+            None => records.push(BookRecord {
+                name: book.name.to_string(),
+                author: book.author.to_string(),
+                year_published: book.year_published,
+                item_type: book.item_type.to_string(),
+                isbn: book.isbn.to_string(),
+                copies: vec![copy]
+            })
+        }
+    }
+    records
+}
+
+pub struct Library {
+    pub books: Vec<BookRecord>,
+    pub patrons: Vec<Patron>,
+    pub loans: Vec<Loan>,
+    pub holds: Vec<Hold>,
+    pub ledger: Vec<LedgerEntry>,
+    pub trash: Vec<Withdrawn>
+}
+
+impl Library {
+    pub fn from_store(books: &[Book], default_loan_days: i64) -> Result<Library, LibraryError> {
+        Ok(Library {
+            books: titles(books),
+            patrons: patron::load_patrons()?,
+            loans: loan::load_loans(default_loan_days)?,
+            holds: hold::load_holds()?,
+            ledger: ledger::load_entries()?,
+            trash: trash::load_trash()?
+        })
+    }
+
+    pub fn copies(&self) -> usize {
+        self.books.iter().map(|b| b.copies.len()).sum()
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_json<T: Serialize>(name: &str, records: &[T]) -> io::Result<(FileEntry, Vec<u8>)> {
+    let data = serde_json::to_vec_pretty(records)?;
+    Ok((FileEntry { name: name.to_string(), records: records.len(), sha256: sha256_hex(&data) }, data))
+}
+
+fn append_file<W: Write>(archive: &mut tar::Builder<W>, name: &str, data: &[u8], today: NaiveDate) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(today.and_hms(0, 0, 0).timestamp().max(0) as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)
+}
+
+pub fn export(library: &Library, path: &Path, today: NaiveDate) -> io::Result<Manifest> {
+    let files = vec![
+        to_json("books.json", &library.books)?,
+        to_json("patrons.json", &library.patrons)?,
+        to_json("loans.json", &library.loans)?,
+        to_json("holds.json", &library.holds)?,
+        to_json("ledger.json", &library.ledger)?,
+        to_json("trash.json", &library.trash)?
+    ];
+    let mut manifest = Manifest { format: FORMAT.to_string(), version: VERSION, created: today, files: vec![] };
+
+    // write next to the target and rename, a failed backup never replaces a good one
+    let temp_path = path.with_extension("partial");
+    let mut archive = tar::Builder::new(GzEncoder::new(File::create(&temp_path)?, Compression::default()));
+    for (entry, data) in files {
+        append_file(&mut archive, &entry.name, &data, today)?;
+        manifest.files.push(entry);
+    }
+    append_file(&mut archive, MANIFEST, &serde_json::to_vec_pretty(&manifest)?, today)?;
+    archive.into_inner()?.finish()?;
+    fs::rename(&temp_path, path)?;
+    Ok(manifest)
+}
+
+fn parse_file<T: DeserializeOwned>(files: &HashMap<String, Vec<u8>>, manifest: &Manifest, name: &str) -> Result<Vec<T>, String> {
+    let entry = manifest.files.iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| t!("backup.not_listed", file = name))?;
+    let data = files.get(name).ok_or_else(|| t!("backup.missing", file = name))?;
+    if sha256_hex(data) != entry.sha256 {
+        return Err(t!("backup.checksum", file = name));
+    }
+    let records: Vec<T> = serde_json::from_slice(data).map_err(|e| t!("backup.invalid", file = name, error = e))?;
+    if records.len() != entry.records {
+        return Err(t!("backup.count", file = name, count = records.len(), listed = entry.records));
+    }
+    Ok(records)
+}
+
+// every text must fit in one column of the comma separated store files
+fn check_fields(file: &str, index: usize, fields: &[&str]) -> Result<(), String> {
+    match fields.iter().find(|f| f.contains(',') || f.contains('\n')) {
+        Some(field) => Err(t!("backup.comma", file = file, number = index + 1, field = field)),
+        None => Ok(())
+    }
+}
+
+fn validate(library: &Library) -> Result<(), String> {
+    for (i, b) in library.books.iter().enumerate() {
+        check_fields("books.json", i, &[&b.name, &b.author, &b.item_type, &b.isbn])?;
+        if b.name.trim().is_empty() || b.copies.is_empty() {
+            return Err(t!("backup.no_name", number = i + 1));
+        }
+        for copy in &b.copies {
+            check_fields("books.json", i, &[&copy.barcode])?;
+        }
+    }
+    for (i, p) in library.patrons.iter().enumerate() {
+        check_fields("patrons.json", i, &[&p.name, &p.category, &p.email, &p.card])?;
+    }
+    for (i, l) in library.loans.iter().enumerate() {
+        check_fields("loans.json", i, &[&l.borrower, &l.book_name, &l.clerk, &l.returned_by, &l.barcode])?;
+    }
+    for (i, h) in library.holds.iter().enumerate() {
+        check_fields("holds.json", i, &[&h.book_name, &h.patron])?;
+    }
+    for (i, e) in library.ledger.iter().enumerate() {
+        check_fields("ledger.json", i, &[&e.patron, &e.note])?;
+    }
+    for (i, w) in library.trash.iter().enumerate() {
+        check_fields("trash.json", i, &[&w.staff, &w.reason])?;
+        if w.store_line.contains('\n') || w.book().is_none() {
+            return Err(t!("backup.trash_line", number = i + 1));
+        }
+    }
+
+    // every copy on its own line, a version 1 backup has no line numbers
+    let mut lines: Vec<usize> = library.books.iter().flat_map(|b| &b.copies).filter_map(|c| c.line).collect();
+    if !lines.is_empty() {
+        lines.sort_unstable();
+        if lines.len() != library.copies() || lines.iter().enumerate().any(|(i, line)| i != *line) {
+            return Err(t!("backup.lines"));
+        }
+    }
+    Ok(())
+}
+
+// read and check a backup archive, nothing is written
+pub fn read_archive(path: &Path) -> Result<(Manifest, Library), String> {
+    let file = File::open(path).map_err(|e| t!("backup.open_failed", path = path.display(), error = e))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    let read_err = |e: io::Error| t!("backup.not_backup_because", path = path.display(), error = e);
+    for entry in archive.entries().map_err(read_err)? {
+        let mut entry = entry.map_err(read_err)?;
+        let name = entry.path().map_err(read_err)?.to_string_lossy().to_string();
+        let mut data = vec![];
+        entry.read_to_end(&mut data).map_err(read_err)?;
+        files.insert(name, data);
+    }
+
+    let manifest: Manifest = match files.get(MANIFEST) {
+        None => return Err(t!("backup.no_manifest", path = path.display(), manifest = MANIFEST)),
+        Some(data) => serde_json::from_slice(data).map_err(|e| t!("backup.invalid", file = MANIFEST, error = e))?
+    };
+    if manifest.format != FORMAT {
+        return Err(t!("backup.not_backup", path = path.display()));
+    }
+    if manifest.version > VERSION {
+        return Err(t!("backup.newer", version = manifest.version, supported = VERSION));
+    }
+
+    let library = Library {
+        books: parse_file(&files, &manifest, "books.json")?,
+        patrons: parse_file(&files, &manifest, "patrons.json")?,
+        loans: parse_file(&files, &manifest, "loans.json")?,
+        holds: parse_file(&files, &manifest, "holds.json")?,
+        ledger: parse_file(&files, &manifest, "ledger.json")?,
+        trash: match manifest.version {
+            1 => vec![],
+            _ => parse_file(&files, &manifest, "trash.json")?
+        }
+    };
+    validate(&library)?;
+    Ok((manifest, library))
+}
+
+// the library store lines of the copies, in the order they had when the backup was made
+fn store_books(records: &[BookRecord]) -> Vec<Book<'_>> {
+    let mut copies: Vec<(Option<usize>, Book)> = vec![];
+    for record in records {
+        for copy in &record.copies {
+            copies.push((copy.line, Book {
+                name: &record.name,
+                author: &record.author,
+                year_published: record.year_published,
+                borrowed: copy.borrowed,
+                issue_date: copy.issue_date,
+                item_type: &record.item_type,
+                isbn: &record.isbn,
+                barcode: &copy.barcode
+            }));
+        }
+    }
+    copies.sort_by_key(|(line, _)| *line);
+    copies.into_iter().map(|(_, book)| book).collect()
+}
+
+// replace the store files with the content of a checked backup
+pub fn restore(library: &Library, store_path: &Path) -> io::Result<()> {
+    staging::write_all(&[
+        DataFile::new(store_path, &store_books(&library.books)),
+        patron::data_file(&library.patrons),
+        loan::data_file(&library.loans),
+        hold::data_file(&library.holds),
+        ledger::data_file(&library.ledger),
+        trash::data_file(&library.trash)
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    const STORE: &str = "\
+Dune,Frank Herbert,1965,false,2024-01-01,book,,I00001
+Golang,Alan Donovan,2015,true,2024-01-02,book,,I00002
+Dune,Frank Herbert,1965,true,2024-01-03,book,,I00003
+";
+
+    fn library(books: &[Book]) -> Library {
+        let date = day(2024, 1, 5);
+        Library {
+            books: titles(books),
+            patrons: vec![],
+            loans: vec![Loan::new("Kim", "Golang", date, 14, "Lee")],
+            holds: vec![Hold::new("Dune", "Ada", date)],
+            ledger: vec![],
+            trash: vec![Withdrawn {
+                date,
+                staff: "Lee".to_string(),
+                reason: "damaged".to_string(),
+                store_line: "Emma,Jane Austen,1815,false,2024-01-04,book,,I00004".to_string()
+            }]
+        }
+    }
+
+    #[test]
+    fn copies_are_grouped_by_title_and_keep_their_line() {
+        let books = crate::to_book_list(STORE).unwrap();
+        let records = titles(&books);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].copies.iter().map(|c| c.line).collect::<Vec<_>>(), [Some(0), Some(2)]);
+        let restored: String = store_books(&records).iter().map(|b| b.to_string()).collect();
+        assert_eq!(restored, STORE);
+    }
+
+    #[test]
+    fn archive_is_read_back_with_the_trash() {
+        let books = crate::to_book_list(STORE).unwrap();
+        let path = std::env::temp_dir().join(format!("library-backup-test-{}.tar.gz", std::process::id()));
+        let manifest = export(&library(&books), &path, day(2024, 1, 6)).unwrap();
+        assert_eq!(manifest.version, VERSION);
+        let (_, read) = read_archive(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.copies(), 3);
+        assert_eq!(read.trash.len(), 1);
+        assert_eq!(read.trash[0].book().unwrap().name, "Emma");
+        let restored: String = store_books(&read.books).iter().map(|b| b.to_string()).collect();
+        assert_eq!(restored, STORE);
+    }
+
+    #[test]
+    fn copies_without_a_line_each_are_refused() {
+        let books = crate::to_book_list(STORE).unwrap();
+        let mut library = library(&books);
+        library.books[1].copies[0].line = Some(2);
+        assert!(validate(&library).is_err());
+        for copy in library.books.iter_mut().flat_map(|b| &mut b.copies) {
+            copy.line = None;
+        }
+        assert!(validate(&library).is_ok());
+    }
+}
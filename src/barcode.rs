@@ -0,0 +1,272 @@
+use crate::i18n::t;
+use crate::template;
+
+/**
+ * Barcodes read by the scanners at the circulation desk, a scanner types the
+ * code and Enter like a keyboard:
+ *
+ *   I0000001  a copy, the last column of its `librarystore` line
+ *   P0000001  a patron card, the last column of the `patrons` line
+ *
+ * New copies and patrons get the next free number, `barcodes assign` numbers
+ * the ones that were added before barcodes existed.
+ *
+ * Codes are printed as Code 128 (code set B), the ISBN of a copy can be
+ * printed as EAN-13 too. Both are drawn as SVG, see `labels` for the sheets.
+ */
+pub const ITEM_PREFIX: char = 'I';
+pub const CARD_PREFIX: char = 'P';
+
+// scanners send what is printed, a code typed by hand may be lower case
+pub fn normalize(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+pub fn is_item(code: &str) -> bool {
+    has_prefix(code, ITEM_PREFIX)
+}
+
+pub fn is_card(code: &str) -> bool {
+    has_prefix(code, CARD_PREFIX)
+}
+
+fn has_prefix(code: &str, prefix: char) -> bool {
+    code.strip_prefix(prefix).is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+pub fn code(prefix: char, number: u64) -> String {
+    format!("{}{:07}", prefix, number)
+}
+
+// the highest number in use with a prefix, 0 when there is none
+pub fn last_number<'a>(prefix: char, codes: impl Iterator<Item = &'a str>) -> u64 {
+    codes.filter(|c| has_prefix(c, prefix))
+        .filter_map(|c| c[1..].parse().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn next<'a>(prefix: char, codes: impl Iterator<Item = &'a str>) -> String {
+    code(prefix, last_number(prefix, codes) + 1)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Symbology {
+    Code128,
+    Ean13
+}
+
+impl Symbology {
+    pub fn parse(s: &str) -> Option<Symbology> {
+        match s.trim().to_lowercase().as_str() {
+            "code128" | "code-128" => Some(Symbology::Code128),
+            "ean13" | "ean-13" | "isbn" => Some(Symbology::Ean13),
+            _ => None
+        }
+    }
+}
+
+// bar and space widths of the Code 128 symbols 0 to 105, 106 is the stop symbol
+const CODE128: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112"
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+// EAN-13 digits in the left half with odd parity, even parity is the right code reversed
+const EAN_L: [&str; 10] = ["0001101", "0011001", "0010011", "0111101", "0100011",
+    "0110001", "0101111", "0111011", "0110111", "0001011"];
+// parity of the six left digits picked by the first digit, G is even
+const EAN_PARITY: [&str; 10] = ["LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG",
+    "LGGLLG", "LGGGLG", "LGLGLG", "LGLGGL", "LGGLGL"];
+
+// modules of a barcode, true for a bar
+fn push_widths(modules: &mut Vec<bool>, widths: &str) {
+    for (i, width) in widths.bytes().enumerate() {
+        for _ in 0..(width - b'0') {
+            modules.push(i % 2 == 0);
+        }
+    }
+}
+
+fn push_bits(modules: &mut Vec<bool>, bits: &str) {
+    modules.extend(bits.bytes().map(|b| b == b'1'));
+}
+
+pub fn code128(text: &str) -> Result<Vec<bool>, String> {
+    if text.is_empty() {
+        return Err(t!("barcode.no_code"));
+    }
+    let mut symbols = vec![CODE128_START_B];
+    for c in text.chars() {
+        if !(' '..='~').contains(&c) {
+            return Err(t!("barcode.not_code128", character = c));
+        }
+        symbols.push(c as usize - 32);
+    }
+    let checksum = symbols.iter().enumerate().map(|(i, s)| i.max(1) * s).sum::<usize>() % 103;
+    symbols.push(checksum);
+    symbols.push(CODE128_STOP);
+    let mut modules = vec![];
+    for symbol in symbols {
+        push_widths(&mut modules, CODE128[symbol]);
+    }
+    Ok(modules)
+}
+
+// the 13 digits of an ISBN, an ISBN-10 becomes the 978 ISBN-13 of the same book
+pub fn isbn_digits(isbn: &str) -> Option<String> {
+    let isbn = crate::import::normalize_isbn(isbn);
+    if !crate::import::is_valid_isbn(&isbn) {
+        return None;
+    }
+    match isbn.len() {
+        13 => Some(isbn),
+        _ => {
+            let digits = format!("978{}", &isbn[..9]);
+            Some(format!("{}{}", digits, ean_check_digit(&digits)))
+        }
+    }
+}
+
+fn ean_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits.chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d } else { d * 3 })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+pub fn ean13(digits: &str) -> Result<Vec<bool>, String> {
+    let values: Vec<usize> = digits.chars().filter_map(|c| c.to_digit(10)).map(|d| d as usize).collect();
+    if values.len() != 13 || digits.chars().count() != 13 {
+        return Err(t!("barcode.not_ean13", digits = digits));
+    }
+    if ean_check_digit(&digits[..12]) as usize != values[12] {
+        return Err(t!("barcode.check_digit", digits = digits));
+    }
+    let mut modules = vec![];
+    push_bits(&mut modules, "101");
+    for (digit, parity) in values[1..7].iter().zip(EAN_PARITY[values[0]].chars()) {
+        let odd = EAN_L[*digit];
+        match parity {
+            'L' => push_bits(&mut modules, odd),
+            _ => modules.extend(odd.bytes().rev().map(|b| b == b'0'))
+        }
+    }
+    push_bits(&mut modules, "01010");
+    for digit in &values[7..] {
+        modules.extend(EAN_L[*digit].bytes().map(|b| b == b'0'));
+    }
+    push_bits(&mut modules, "101");
+    Ok(modules)
+}
+
+pub fn encode(text: &str, symbology: Symbology) -> Result<Vec<bool>, String> {
+    match symbology {
+        Symbology::Code128 => code128(text),
+        Symbology::Ean13 => ean13(text)
+    }
+}
+
+// the barcode with its text under it, in module widths and a quiet zone of ten,
+// a module prints 0.33 mm wide
+pub fn to_svg(modules: &[bool], text: &str) -> String {
+    const QUIET: usize = 10;
+    const HEIGHT: usize = 50;
+    let width = modules.len() + 2 * QUIET;
+    let mut bars = String::new();
+    let mut x = 0;
+    while x < modules.len() {
+        if !modules[x] {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        while x < modules.len() && modules[x] {
+            x += 1;
+        }
+        bars.push_str(&format!("<rect x=\"{}\" y=\"0\" width=\"{}\" height=\"{}\"/>", start + QUIET, x - start, HEIGHT));
+    }
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"{wmm:.1}mm\" height=\"{hmm:.1}mm\" \
+        preserveAspectRatio=\"none\"><rect width=\"{w}\" height=\"{h}\" fill=\"#fff\"/><g fill=\"#000\">{bars}</g>\
+        <text x=\"{x}\" y=\"{y}\" font-family=\"monospace\" font-size=\"9\" text-anchor=\"middle\">{text}</text></svg>",
+        w = width, h = HEIGHT + 11, wmm = width as f64 * 0.33, hmm = (HEIGHT + 11) as f64 * 0.33, bars = bars, x = width / 2, y = HEIGHT + 9, text = template::escape_xml(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(modules: &[bool]) -> String {
+        modules.iter().map(|m| if *m { '1' } else { '0' }).collect()
+    }
+
+    fn widths(modules: &[bool]) -> String {
+        let mut widths = String::new();
+        let mut run = 1;
+        for pair in modules.windows(2) {
+            if pair[0] == pair[1] {
+                run += 1;
+            } else {
+                widths.push_str(&run.to_string());
+                run = 1;
+            }
+        }
+        widths.push_str(&run.to_string());
+        widths
+    }
+
+    #[test]
+    fn code128_has_start_b_the_checksum_and_stop() {
+        // 104 + 48*1 + 42*2 + 42*3 + 17*4 + 18*5 + 19*6 + 35*7 = 879, 879 % 103 = 55
+        let modules = code128("PJJ123C").unwrap();
+        assert_eq!(modules.len(), 11 * 9 + 13);
+        assert_eq!(widths(&modules[..11]), "211214");
+        assert_eq!(widths(&modules[11..22]), "313121");
+        assert_eq!(widths(&modules[88..99]), "311321");
+        assert_eq!(widths(&modules[99..]), "2331112");
+        assert!(code128("").is_err());
+        assert!(code128("Café").is_err());
+    }
+
+    #[test]
+    fn ean13_of_an_isbn() {
+        let expected = [
+            "101",
+            "0111011", "0001001", "0100111", "0011001", "0100001", "0011001",
+            "01010",
+            "1100110", "1110010", "1000010", "1010000", "1101100", "1000100",
+            "101"
+        ].concat();
+        assert_eq!(bits(&ean13("9780131103627").unwrap()), expected);
+        assert!(ean13("9780131103628").is_err());
+        assert!(ean13("978013110362").is_err());
+    }
+
+    #[test]
+    fn ean_check_digits() {
+        assert_eq!(ean_check_digit("978013110362"), 7);
+        assert_eq!(ean_check_digit("400638133393"), 1);
+        assert_eq!(ean_check_digit("978044117271"), 9);
+    }
+
+    #[test]
+    fn isbn10_becomes_the_978_isbn13() {
+        assert_eq!(isbn_digits("0-13-110362-8").as_deref(), Some("9780131103627"));
+        assert_eq!(isbn_digits("0-8044-2957-X").as_deref(), Some("9780804429573"));
+        assert_eq!(isbn_digits("978-0-441-17271-9").as_deref(), Some("9780441172719"));
+        assert_eq!(isbn_digits("0-13-110362-7"), None);
+    }
+}
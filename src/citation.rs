@@ -0,0 +1,207 @@
+use crate::backup::BookRecord;
+use crate::template;
+
+/**
+ * Citations of catalogue titles as BibTeX, RIS or Dublin Core XML.
+ *
+ * The item type of a title picks the entry type of each format (see
+ * ENTRY_TYPES), unknown item types are cited as generic works.
+ */
+const DUBLIN_CORE_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const OAI_DC_NAMESPACE: &str = "http://www.openarchives.org/OAI/2.0/oai_dc/";
+
+// (item type, BibTeX entry, RIS type, Dublin Core type)
+const ENTRY_TYPES: &[(&str, &str, &str, &str)] = &[
+    ("book", "book", "BOOK", "Text"),
+    ("manuscript", "unpublished", "MANSCPT", "Text"),
+    ("map", "misc", "MAP", "Image"),
+    ("score", "misc", "MUSIC", "Text"),
+    ("audiobook", "misc", "SOUND", "Sound"),
+    ("cd", "misc", "MUSIC", "Sound"),
+    ("dvd", "misc", "VIDEO", "MovingImage"),
+    ("software", "misc", "COMP", "Software")
+];
+
+#[derive(Copy, Clone)]
+pub enum CitationFormat {
+    BibTex,
+    Ris,
+    DublinCore
+}
+
+impl CitationFormat {
+    pub fn parse(s: &str) -> Option<CitationFormat> {
+        match s.trim().to_lowercase().as_str() {
+            "bibtex" | "bib" => Some(CitationFormat::BibTex),
+            "ris" => Some(CitationFormat::Ris),
+            "dc" | "dublincore" => Some(CitationFormat::DublinCore),
+            _ => None
+        }
+    }
+}
+
+fn entry_type(item_type: &str) -> (&'static str, &'static str, &'static str) {
+    ENTRY_TYPES.iter()
+        .find(|(t, ..)| *t == item_type)
+        .map(|(_, bibtex, ris, dc)| (*bibtex, *ris, *dc))
+        .unwrap_or(("misc", "GEN", "Text"))
+}
+
+// "Kim Loo Uii", 2019, "Korean language" -> "uii2019korean"
+fn bibtex_key(book: &BookRecord) -> String {
+    let word = |s: &str, last: bool| -> String {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let word = if last { words.last() } else { words.first() };
+        word.unwrap_or(&"").chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase()
+    };
+    format!("{}{}{}", word(&book.author, true), book.year_published, word(&book.name, false))
+}
+
+fn bibtex_value(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '{' | '}' | '\\' => (),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+pub fn to_bibtex(books: &[BookRecord]) -> String {
+    let mut out = String::new();
+    let mut keys: Vec<String> = vec![];
+    for book in books {
+        // same author, year and first word get a, b, c... appended
+        let base = bibtex_key(book);
+        let mut key = base.clone();
+        let mut suffix = b'a';
+        while keys.contains(&key) {
+            key = format!("{}{}", base, suffix as char);
+            suffix += 1;
+        }
+        keys.push(key.clone());
+
+        let (entry, _, _) = entry_type(&book.item_type);
+        out.push_str(&format!("@{}{{{},\n", entry, key));
+        out.push_str(&format!("  author = {{{}}},\n", bibtex_value(&book.author)));
+        out.push_str(&format!("  title = {{{}}},\n", bibtex_value(&book.name)));
+        out.push_str(&format!("  year = {{{}}}", book.year_published));
+        if !book.isbn.is_empty() {
+            out.push_str(&format!(",\n  isbn = {{{}}}", book.isbn));
+        }
+        if entry == "misc" {
+            out.push_str(&format!(",\n  howpublished = {{{}}}", bibtex_value(&book.item_type)));
+        }
+        out.push_str("\n}\n\n");
+    }
+    out
+}
+
+pub fn to_ris(books: &[BookRecord]) -> String {
+    let mut out = String::new();
+    for book in books {
+        let (_, ris, _) = entry_type(&book.item_type);
+        out.push_str(&format!("TY  - {}\n", ris));
+        out.push_str(&format!("AU  - {}\n", book.author));
+        out.push_str(&format!("TI  - {}\n", book.name));
+        out.push_str(&format!("PY  - {}\n", book.year_published));
+        if !book.isbn.is_empty() {
+            out.push_str(&format!("SN  - {}\n", book.isbn));
+        }
+        out.push_str("ER  - \n\n");
+    }
+    out
+}
+
+pub fn to_dublin_core(books: &[BookRecord]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<records xmlns:oai_dc=\"{}\" xmlns:dc=\"{}\">\n", OAI_DC_NAMESPACE, DUBLIN_CORE_NAMESPACE));
+    for book in books {
+        let (_, _, dc) = entry_type(&book.item_type);
+        out.push_str("  <oai_dc:dc>\n");
+        out.push_str(&format!("    <dc:title>{}</dc:title>\n", template::escape_xml(&book.name)));
+        out.push_str(&format!("    <dc:creator>{}</dc:creator>\n", template::escape_xml(&book.author)));
+        out.push_str(&format!("    <dc:date>{}</dc:date>\n", book.year_published));
+        out.push_str(&format!("    <dc:type>{}</dc:type>\n", dc));
+        out.push_str(&format!("    <dc:format>{}</dc:format>\n", template::escape_xml(&book.item_type)));
+        if !book.isbn.is_empty() {
+            out.push_str(&format!("    <dc:identifier>urn:isbn:{}</dc:identifier>\n", book.isbn));
+        }
+        out.push_str("  </oai_dc:dc>\n");
+    }
+    out.push_str("</records>\n");
+    out
+}
+
+pub fn write(books: &[BookRecord], format: CitationFormat) -> String {
+    match format {
+        CitationFormat::BibTex => to_bibtex(books),
+        CitationFormat::Ris => to_ris(books),
+        CitationFormat::DublinCore => to_dublin_core(books)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(name: &str, author: &str, year: u32, item_type: &str, isbn: &str) -> BookRecord {
+        BookRecord {
+            name: name.to_string(),
+            author: author.to_string(),
+            year_published: year,
+            item_type: item_type.to_string(),
+            isbn: isbn.to_string(),
+            copies: vec![]
+        }
+    }
+
+    #[test]
+    fn bibtex_escapes_values_and_cites_other_items_as_misc() {
+        let books = [
+            title("Dune & Sons_{2}", "Frank Herbert", 1965, "book", "9780441013593"),
+            title("Atlas", "Kim Loo Uii", 2019, "map", "")
+        ];
+        assert_eq!(to_bibtex(&books), concat!(
+            "@book{herbert1965dune,\n  author = {Frank Herbert},\n  title = {Dune \\& Sons\\_2},\n  year = {1965},\n  isbn = {9780441013593}\n}\n\n",
+            "@misc{uii2019atlas,\n  author = {Kim Loo Uii},\n  title = {Atlas},\n  year = {2019},\n  howpublished = {map}\n}\n\n"));
+    }
+
+    #[test]
+    fn same_author_year_and_first_word_get_a_suffix() {
+        let books = [
+            title("Dune", "Frank Herbert", 1965, "book", ""),
+            title("Dune Messiah", "Frank Herbert", 1965, "book", ""),
+            title("Dune Chronicles", "Frank Herbert", 1965, "book", "")
+        ];
+        let bibtex = to_bibtex(&books);
+        let keys: Vec<&str> = bibtex.lines()
+            .filter_map(|line| line.strip_prefix("@book{"))
+            .map(|line| line.trim_end_matches(','))
+            .collect();
+        assert_eq!(keys, ["herbert1965dune", "herbert1965dunea", "herbert1965duneb"]);
+    }
+
+    #[test]
+    fn ris_uses_the_type_of_the_item() {
+        let books = [title("Dune", "Frank Herbert", 1965, "dvd", "9780441013593"), title("Notes", "Ada", 2001, "zine", "")];
+        assert_eq!(to_ris(&books), concat!(
+            "TY  - VIDEO\nAU  - Frank Herbert\nTI  - Dune\nPY  - 1965\nSN  - 9780441013593\nER  - \n\n",
+            "TY  - GEN\nAU  - Ada\nTI  - Notes\nPY  - 2001\nER  - \n\n"));
+    }
+
+    #[test]
+    fn dublin_core_escapes_xml() {
+        let xml = to_dublin_core(&[title("Tom & Jerry <1>", "Hanna", 1940, "cd", "9780441013593")]);
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records xmlns:oai_dc="));
+        assert!(xml.contains(concat!(
+            "  <oai_dc:dc>\n    <dc:title>Tom &amp; Jerry &lt;1&gt;</dc:title>\n    <dc:creator>Hanna</dc:creator>\n",
+            "    <dc:date>1940</dc:date>\n    <dc:type>Sound</dc:type>\n    <dc:format>cd</dc:format>\n",
+            "    <dc:identifier>urn:isbn:9780441013593</dc:identifier>\n  </oai_dc:dc>\n</records>\n")));
+    }
+}
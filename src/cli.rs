@@ -0,0 +1,39 @@
+/**
+ * Command line arguments of the subcommands, e.g.
+ *
+ *   simple_library_system_cli report titles --from 2020-01-01 --format json
+ *
+ * Options take a value as `--name value` or `--name=value`, every other
+ * word is a positional argument.
+ */
+const VALUE_OPTIONS: &[&str] = &["--today", "--user", "--role", "--staff", "--record", "--from", "--to", "--period", "--format", "--top", "--map", "--search", "--port", "--for", "--date", "--copies", "--symbology"];
+
+pub fn option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            return iter.next().map(|v| v.trim().to_string());
+        } else if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+pub fn flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|a| a == name)
+}
+
+pub fn positionals(args: &[String]) -> Vec<&str> {
+    let mut positionals = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if VALUE_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            positionals.push(arg.as_str());
+        }
+    }
+    positionals
+}
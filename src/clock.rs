@@ -0,0 +1,90 @@
+use chrono::{NaiveDate, Utc};
+use crate::i18n::t;
+
+/**
+ * Source of "today" for every date decision in the library
+ * (issue date, due date, overdue days, fines and reports).
+ *
+ * SystemClock follows the real calendar, FixedClock stays on the date it
+ * is given, which is what `--today YYYY-MM-DD` uses to back-date returns
+ * and what tests use to check overdue behaviour without waiting real days.
+ */
+pub trait Clock {
+    fn today(&self) -> NaiveDate;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&self) -> NaiveDate {
+        Utc::now().naive_utc().date()
+    }
+}
+
+pub struct FixedClock {
+    pub date: NaiveDate
+}
+
+impl Clock for FixedClock {
+    fn today(&self) -> NaiveDate {
+        self.date
+    }
+}
+
+// pick the clock from the command line, `--today YYYY-MM-DD` or `--today=YYYY-MM-DD`
+pub fn from_args(args: &[String]) -> Result<Box<dyn Clock>, String> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = if arg == "--today" {
+            match iter.next() {
+                Some(v) => v.as_str(),
+                None => return Err(t!("cli.today_needs_date"))
+            }
+        } else if let Some(v) = arg.strip_prefix("--today=") {
+            v
+        } else {
+            continue;
+        };
+        return match NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+            Ok(date) => Ok(Box::new(FixedClock { date })),
+            Err(e) => Err(t!("cli.invalid_today", date = value, error = e))
+        };
+    }
+    Ok(Box::new(SystemClock))
+}
+
+// dates in tests
+#[cfg(test)]
+pub fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn fixed_clock_stays_on_its_date() {
+        let date = day(2024, 2, 29);
+        let clock = FixedClock { date };
+        assert_eq!(clock.today(), date);
+        assert_eq!(clock.today(), date);
+    }
+
+    #[test]
+    fn today_option_picks_a_fixed_clock() {
+        let date = day(2024, 1, 31);
+        assert_eq!(from_args(&args(&["--today", "2024-01-31"])).unwrap().today(), date);
+        assert_eq!(from_args(&args(&["report", "--today=2024-01-31"])).unwrap().today(), date);
+    }
+
+    #[test]
+    fn today_option_needs_a_valid_date() {
+        assert!(from_args(&args(&["--today"])).is_err());
+        assert!(from_args(&args(&["--today", "2024-02-30"])).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::cmp::Ordering;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use crate::i18n;
+
+/**
+ * Matching and sorting of titles, authors and names the way a reader expects
+ * rather than by the bytes they are stored as.
+ *
+ * Text is compared in NFKC, so a full-width "Ｊａｖａ" is "Java" and "é" typed
+ * as e and an accent is "é", and case folded. Searching also ignores accents,
+ * "Cafe" finds "Café", while Japanese voiced marks are kept, "か" does not
+ * find "が". Katakana is folded to hiragana so a search in either finds both.
+ *
+ * Sorting compares the letters first, then the accents, then the case, so
+ * "cote", "côte", "Côte" and "coté" end up together. A leading article of
+ * the language (`collation.articles` of the catalogue, "The", "A" and "An" in
+ * English) is skipped. Chinese, Japanese and Korean sort by code point, which
+ * is the syllable order of kana and Hangul but not pinyin or stroke order,
+ * a rare character of a later Unicode block sorts after all common ones.
+ */
+const VOICED_MARKS: [char; 2] = ['\u{3099}', '\u{309A}'];
+
+// katakana to the hiragana it reads as, e.g. "カ" -> "か"
+fn hiragana(c: char) -> char {
+    match c {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c
+    }
+}
+
+// case folding beyond to_lowercase, "ß" is "ss" and a final sigma is a sigma
+fn fold_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| if c == 'ς' { 'σ' } else { c })
+        .collect::<String>()
+        .replace('ß', "ss")
+}
+
+// NFKC and case folded, the form names are compared in
+pub fn normalize(text: &str) -> String {
+    fold_case(&text.trim().nfkc().collect::<String>()).nfkc().collect()
+}
+
+// normalized without accents and with katakana as hiragana, the form searches are made in
+pub fn fold(text: &str) -> String {
+    normalize(text).nfkd()
+        .filter(|c| !is_combining_mark(*c) || VOICED_MARKS.contains(c))
+        .map(hiragana)
+        .nfc()
+        .collect()
+}
+
+// the text contains what was searched, empty text is in everything
+pub fn contains(text: &str, search: &str) -> bool {
+    fold(text).contains(&fold(search))
+}
+
+// two names or titles are the same apart from case and Unicode form
+pub fn same(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+// "The Hobbit" files under H
+fn without_article(text: &str) -> &str {
+    let text = text.trim();
+    i18n::text("collation.articles", &[]).split(',')
+        .map(|article| article.trim())
+        .filter(|article| !article.is_empty())
+        .find_map(|article| text.get(..article.len())
+            .filter(|start| start.eq_ignore_ascii_case(article))
+            .and_then(|_| text[article.len()..].strip_prefix(' ')))
+        .map(|rest| rest.trim_start())
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(text)
+}
+
+// letters, then accents, then case, then the text as it is
+pub fn sort_key(text: &str) -> (String, String, String, String) {
+    let filed = without_article(text);
+    (fold(filed), normalize(filed), filed.nfkc().collect(), text.to_string())
+}
+
+pub fn compare(a: &str, b: &str) -> Ordering {
+    sort_key(a).cmp(&sort_key(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_the_same_in_any_case_or_width() {
+        assert!(same("Ｊａｖａ", " java"));
+        assert!(same("STRASSE", "straße"));
+        assert!(!same("Café", "Cafe"));
+    }
+
+    #[test]
+    fn search_ignores_accents_and_kana() {
+        assert!(contains("Café Society", "cafe"));
+        assert!(contains("カタカナ", "かた"));
+        assert!(!contains("かき", "がき"));
+    }
+
+    #[test]
+    fn titles_file_under_the_word_after_the_article() {
+        let mut titles = vec!["The Hobbit", "Golang", "A Farewell to Arms", "côte", "cote"];
+        titles.sort_by(|a, b| compare(a, b));
+        assert_eq!(titles, ["cote", "côte", "A Farewell to Arms", "Golang", "The Hobbit"]);
+    }
+
+    #[test]
+    fn chinese_sorts_by_code_point() {
+        let mut titles = vec!["红楼梦", "三国演义", "水浒传"];
+        titles.sort_by(|a, b| compare(a, b));
+        assert_eq!(titles, ["三国演义", "水浒传", "红楼梦"]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use crate::receipt::ReceiptFormat;
+
+/**
+ * Library rules read from the `libraryconfig` file, one `key = value` per line,
+ * `#` starts a comment. Every rule has a default so the file is optional.
+ *
+ *   loan_days = 14                    default loan period
+ *   loan_days.dvd = 7                 loan period of an item type
+ *   loan_days.*.staff = 28            loan period of a patron category
+ *   loan_days.dvd.staff = 14          loan period of an item type for a patron category
+ *   max_renewals = 2
+ *   fine_per_day = 50                 in cents
+ *   hold_pickup_days = 7              days a returned copy waits on the hold shelf
+ *   max_loans = 5                     books a patron can have on loan at once
+ *   max_loans.student = 3             books on loan at once for a patron category
+ *   max_balance = 1000                in cents, a higher outstanding balance blocks new loans
+ *   block_when_overdue = true         patrons with overdue books cannot borrow
+ *   due_soon_days = 2                 days before the due date a reminder is sent
+ *   smtp_host = localhost             mail relay for reminders
+ *   smtp_port = 25
+ *   smtp_from = library@localhost
+ *   notify_outbox = outbox            directory the dry run writes messages to
+ *   serve_host = 127.0.0.1            address the REST API listens on
+ *   serve_port = 8080
+ *   undo_depth = 20                   menu actions a session can undo
+ *   history_size = 500                command lines the shell keeps in `shellhistory`
+ *   receipts = text                   receipts after a loan or return, text, html, both or none
+ *   receipt_dir = receipts            directory receipts are written to
+ *   locale = ms                       language of the messages, else the one of LANG
+ *   currency = RM                     currency symbol of amounts
+ */
+const CONFIG_PATH: &str = "libraryconfig";
+
+pub const DEFAULT_LOAN_DAYS: i64 = 14;
+pub const DEFAULT_MAX_RENEWALS: i64 = 2;
+pub const DEFAULT_FINE_PER_DAY: i64 = 50;
+pub const DEFAULT_HOLD_PICKUP_DAYS: i64 = 7;
+pub const DEFAULT_MAX_LOANS: i64 = 5;
+pub const DEFAULT_MAX_BALANCE: i64 = 1000;
+pub const DEFAULT_DUE_SOON_DAYS: i64 = 2;
+pub const DEFAULT_UNDO_DEPTH: i64 = 20;
+pub const DEFAULT_HISTORY_SIZE: i64 = 500;
+
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, String>
+}
+
+impl Config {
+    pub fn parse(data: &str) -> Config {
+        let mut values = HashMap::new();
+        for line in data.lines() {
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line
+            };
+            if let Some((key, value)) = line.split_once('=') {
+                values.insert(key.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+        Config { values }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(&key.to_lowercase()).map(|v| v.as_str())
+    }
+
+    pub fn get_i64(&self, key: &str, default: i64) -> i64 {
+        self.get(key).and_then(|v| v.parse().ok()).unwrap_or(default)
+    }
+
+    // most specific rule wins: type + category, type, category, default
+    pub fn loan_days(&self, item_type: &str, category: &str) -> i64 {
+        let item_type = item_type.trim().to_lowercase();
+        let category = category.trim().to_lowercase();
+        [
+            format!("loan_days.{}.{}", item_type, category),
+            format!("loan_days.{}", item_type),
+            format!("loan_days.*.{}", category)
+        ].iter()
+            .find_map(|key| self.get(key).and_then(|v| v.parse().ok()))
+            .unwrap_or_else(|| self.get_i64("loan_days", DEFAULT_LOAN_DAYS))
+    }
+
+    pub fn max_renewals(&self) -> i64 {
+        self.get_i64("max_renewals", DEFAULT_MAX_RENEWALS)
+    }
+
+    pub fn fine_per_day(&self) -> i64 {
+        self.get_i64("fine_per_day", DEFAULT_FINE_PER_DAY)
+    }
+
+    pub fn hold_pickup_days(&self) -> i64 {
+        self.get_i64("hold_pickup_days", DEFAULT_HOLD_PICKUP_DAYS)
+    }
+
+    pub fn max_loans(&self, category: &str) -> i64 {
+        self.get(&format!("max_loans.{}", category.trim().to_lowercase()))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| self.get_i64("max_loans", DEFAULT_MAX_LOANS))
+    }
+
+    pub fn max_balance(&self) -> i64 {
+        self.get_i64("max_balance", DEFAULT_MAX_BALANCE)
+    }
+
+    pub fn block_when_overdue(&self) -> bool {
+        self.get("block_when_overdue").map(|v| v != "false").unwrap_or(true)
+    }
+
+    pub fn due_soon_days(&self) -> i64 {
+        self.get_i64("due_soon_days", DEFAULT_DUE_SOON_DAYS)
+    }
+
+    pub fn smtp_host(&self) -> &str {
+        self.get("smtp_host").unwrap_or("localhost")
+    }
+
+    pub fn smtp_port(&self) -> u16 {
+        self.get("smtp_port").and_then(|v| v.parse().ok()).unwrap_or(25)
+    }
+
+    pub fn smtp_from(&self) -> &str {
+        self.get("smtp_from").unwrap_or("library@localhost")
+    }
+
+    pub fn notify_outbox(&self) -> &str {
+        self.get("notify_outbox").unwrap_or("outbox")
+    }
+
+    pub fn serve_host(&self) -> &str {
+        self.get("serve_host").unwrap_or("127.0.0.1")
+    }
+
+    pub fn serve_port(&self) -> u16 {
+        self.get("serve_port").and_then(|v| v.parse().ok()).unwrap_or(8080)
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.get_i64("undo_depth", DEFAULT_UNDO_DEPTH).max(0) as usize
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.get_i64("history_size", DEFAULT_HISTORY_SIZE).max(0) as usize
+    }
+
+    pub fn receipt_formats(&self) -> Vec<ReceiptFormat> {
+        match self.get("receipts").unwrap_or("text") {
+            "both" => vec![ReceiptFormat::Text, ReceiptFormat::Html],
+            value => ReceiptFormat::parse(value).into_iter().collect()
+        }
+    }
+
+    pub fn receipt_dir(&self) -> &str {
+        self.get("receipt_dir").unwrap_or("receipts")
+    }
+
+    pub fn currency(&self) -> &str {
+        self.get("currency").unwrap_or("$")
+    }
+}
+
+// a missing config file means every rule uses its default
+pub fn load() -> io::Result<Config> {
+    let mut file = match File::open(Path::new(CONFIG_PATH)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+        Ok(file) => file
+    };
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    Ok(Config::parse(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_rules_use_the_defaults() {
+        let config = Config::parse("");
+        assert_eq!(config.loan_days("book", "adult"), DEFAULT_LOAN_DAYS);
+        assert_eq!(config.max_renewals(), DEFAULT_MAX_RENEWALS);
+        assert_eq!(config.fine_per_day(), DEFAULT_FINE_PER_DAY);
+        assert_eq!(config.hold_pickup_days(), DEFAULT_HOLD_PICKUP_DAYS);
+        assert!(config.block_when_overdue());
+    }
+
+    #[test]
+    fn most_specific_loan_period_wins() {
+        let config = Config::parse("loan_days = 21 # three weeks\nloan_days.dvd = 7\nloan_days.*.staff = 28\nLoan_Days.DVD.Staff = 14");
+        assert_eq!(config.loan_days("book", "adult"), 21);
+        assert_eq!(config.loan_days("DVD", "adult"), 7);
+        assert_eq!(config.loan_days("book", "staff"), 28);
+        assert_eq!(config.loan_days("dvd", "staff"), 14);
+    }
+
+    #[test]
+    fn bad_numbers_fall_back_to_the_default() {
+        let config = Config::parse("fine_per_day = fifty\nmax_renewals = 3");
+        assert_eq!(config.fine_per_day(), DEFAULT_FINE_PER_DAY);
+        assert_eq!(config.max_renewals(), 3);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use chrono::NaiveDate;
+use crate::config::Config;
+use crate::i18n::t;
+use crate::ledger;
+use crate::loan::Loan;
+
+/**
+ * Rules checked before a book is issued to a patron:
+ * the number of open loans of the patron category, the outstanding balance
+ * in the ledger and whether the patron still has overdue books.
+ */
+#[derive(Debug)]
+pub enum LoanRefusal {
+    TooManyLoans { loans: usize, max: i64 },
+    OutstandingBalance { balance: i64, max: i64 },
+    OverdueItems(usize)
+}
+
+impl fmt::Display for LoanRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoanRefusal::TooManyLoans { loans, max } => 
+                write!(f, "{}", t!("refusal.loans", loans = loans, max = max)),
+            LoanRefusal::OutstandingBalance { balance, max } => 
+                write!(f, "{}", t!("refusal.balance", 
+                    balance = ledger::format_money(*balance), max = ledger::format_money(*max))),
+            LoanRefusal::OverdueItems(count) => 
+                write!(f, "{}", t!("refusal.overdue", count = count))
+        }
+    }
+}
+
+pub fn check(loans: &[Loan], borrower: &str, category: &str, balance: i64, config: &Config, today: NaiveDate) -> Result<(), LoanRefusal> {
+    let open_loans: Vec<&Loan> = loans.iter().filter(|l| l.is_open() && l.is_for(borrower)).collect();
+
+    let max_loans = config.max_loans(category);
+    if open_loans.len() as i64 >= max_loans {
+        return Err(LoanRefusal::TooManyLoans { loans: open_loans.len(), max: max_loans });
+    }
+
+    let max_balance = config.max_balance();
+    if balance > max_balance {
+        return Err(LoanRefusal::OutstandingBalance { balance, max: max_balance });
+    }
+
+    let overdue = open_loans.iter().filter(|l| l.is_overdue(today)).count();
+    if config.block_when_overdue() && overdue > 0 {
+        return Err(LoanRefusal::OverdueItems(overdue));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn loans(count: usize) -> Vec<Loan> {
+        (0..count).map(|i| Loan::new("Kim", &format!("Book {}", i), day(2024, 1, 1), 14, "Lee")).collect()
+    }
+
+    #[test]
+    fn patron_category_sets_the_loan_limit() {
+        let config = Config::parse("max_loans = 3\nmax_loans.staff = 5");
+        let today = day(2024, 1, 10);
+        assert!(check(&loans(2), "Kim", "student", 0, &config, today).is_ok());
+        assert!(matches!(check(&loans(3), "kim", "student", 0, &config, today),
+            Err(LoanRefusal::TooManyLoans { loans: 3, max: 3 })));
+        assert!(check(&loans(3), "Kim", "staff", 0, &config, today).is_ok());
+        assert!(check(&loans(3), "Ada", "student", 0, &config, today).is_ok());
+    }
+
+    #[test]
+    fn balance_over_the_limit_blocks_loans() {
+        let config = Config::default();
+        let today = day(2024, 1, 10);
+        assert!(check(&[], "Kim", "", 1000, &config, today).is_ok());
+        assert!(matches!(check(&[], "Kim", "", 1001, &config, today),
+            Err(LoanRefusal::OutstandingBalance { balance: 1001, max: 1000 })));
+    }
+
+    #[test]
+    fn overdue_books_block_loans_unless_turned_off() {
+        let today = day(2024, 1, 16);
+        assert!(matches!(check(&loans(1), "Kim", "", 0, &Config::default(), today),
+            Err(LoanRefusal::OverdueItems(1))));
+        assert!(check(&loans(1), "Kim", "", 0, &Config::parse("block_when_overdue = false"), today).is_ok());
+        let due_day = day(2024, 1, 15);
+        assert!(check(&loans(1), "Kim", "", 0, &Config::default(), due_day).is_ok());
+    }
+}
@@ -0,0 +1,178 @@
+use std::io;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::io::prelude::*;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use chrono::{Duration, NaiveDate};
+use crate::collation;
+use crate::library::{self, LibraryError};
+use crate::staging::{self, DataFile};
+
+/**
+ * Holds on borrowed titles, one per line in the `holds` file:
+ *
+ *   book name,patron,placed date,pickup until
+ *
+ * The file order is the queue order, first in first out per title.
+ * `pickup until` is empty while the patron is waiting, once a copy is
+ * returned it is set aside for the first patron until that date.
+ */
+const HOLD_PATH: &str = "holds";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hold {
+    pub book_name: String,
+    pub patron: String,
+    pub placed_date: NaiveDate,
+    pub pickup_until: Option<NaiveDate>
+}
+
+impl Hold {
+    pub fn new(book_name: &str, patron: &str, placed_date: NaiveDate) -> Hold {
+        Hold {
+            book_name: book_name.trim().to_string(),
+            patron: patron.trim().to_string(),
+            placed_date,
+            pickup_until: None
+        }
+    }
+
+    pub fn is_for(&self, book_name: &str) -> bool {
+        collation::same(&self.book_name, book_name)
+    }
+
+    pub fn is_by(&self, patron: &str) -> bool {
+        collation::same(&self.patron, patron)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.pickup_until.is_some()
+    }
+}
+
+impl fmt::Display for Hold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pickup_until = self.pickup_until.map(|d| d.to_string()).unwrap_or_default();
+        writeln!(f, "{},{},{},{}", self.book_name, self.patron, self.placed_date, pickup_until)
+    }
+}
+
+fn parse_hold(line: &str) -> Option<Hold> {
+    let slice_data: Vec<&str> = line.split(',').collect();
+    if slice_data.len() < 3 {
+        return None;
+    }
+    let mut hold = Hold::new(slice_data[0], slice_data[1], 
+        NaiveDate::parse_from_str(slice_data[2].trim(), "%Y-%m-%d").ok()?);
+    hold.pickup_until = slice_data.get(3)
+        .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok());
+    Some(hold)
+}
+
+pub fn load_holds() -> Result<Vec<Hold>, LibraryError> {
+    library::read_records(HOLD_PATH, parse_hold)
+}
+
+// the whole `holds` file, for writes staged together with other files
+pub fn data_file(holds: &[Hold]) -> DataFile {
+    DataFile::new(HOLD_PATH, holds)
+}
+
+pub fn save_holds(holds: &[Hold]) -> io::Result<()> {
+    staging::write_all(&[data_file(holds)])
+}
+
+pub fn place(hold: &Hold) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(HOLD_PATH))?;
+    write!(&mut file, "{}", hold)
+}
+
+// number of copies of a title sitting on the hold shelf for other patrons
+pub fn set_aside_for_others(holds: &[Hold], book_name: &str, patron: &str) -> usize {
+    holds.iter().filter(|h| h.is_for(book_name) && h.is_ready() && !h.is_by(patron)).count()
+}
+
+// another patron is waiting in the queue of this title
+pub fn is_reserved_by_other(holds: &[Hold], book_name: &str, patron: &str) -> bool {
+    holds.iter().any(|h| h.is_for(book_name) && !h.is_by(patron))
+}
+
+// a returned copy goes to the first waiting patron of the title,
+// returns the hold it was set aside for
+pub fn set_aside(holds: &mut [Hold], book_name: &str, today: NaiveDate, pickup_days: i64) -> Option<Hold> {
+    let hold = holds.iter_mut().find(|h| h.is_for(book_name) && !h.is_ready())?;
+    hold.pickup_until = Some(today + Duration::days(pickup_days));
+    Some(hold.clone())
+}
+
+// drop holds that were not picked up in time and pass their copy to the next
+// patron in the queue, returns the expired holds
+pub fn expire(holds: &mut Vec<Hold>, today: NaiveDate, pickup_days: i64) -> Vec<Hold> {
+    let (expired, active): (Vec<Hold>, Vec<Hold>) = holds.drain(..)
+        .partition(|h| h.pickup_until.map(|d| d < today).unwrap_or(false));
+    *holds = active;
+    for hold in &expired {
+        set_aside(holds, &hold.book_name, today, pickup_days);
+    }
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn queue() -> Vec<Hold> {
+        vec![
+            Hold::new("Foundation", "Kim", day(2024, 1, 1)),
+            Hold::new("Foundation", "Ada", day(2024, 1, 2)),
+            Hold::new("Golang", "Lee", day(2024, 1, 3))
+        ]
+    }
+
+    #[test]
+    fn returned_copy_goes_to_the_first_in_the_queue() {
+        let mut holds = queue();
+        let today = day(2024, 1, 10);
+        let hold = set_aside(&mut holds, "Foundation", today, 7).unwrap();
+        assert_eq!(hold.patron, "Kim");
+        assert_eq!(hold.pickup_until, Some(day(2024, 1, 17)));
+        assert_eq!(set_aside_for_others(&holds, "Foundation", "Ada"), 1);
+        assert!(is_reserved_by_other(&holds, "Foundation", "Kim"));
+    }
+
+    #[test]
+    fn hold_is_for_the_title_in_any_case_or_width() {
+        let holds = queue();
+        assert!(holds[0].is_for("ＦＯＵＮＤＡＴＩＯＮ "));
+        assert!(!holds[0].is_for("Golang"));
+        assert!(is_reserved_by_other(&holds, "foundation", "Kim"));
+    }
+
+    #[test]
+    fn hold_is_kept_on_the_last_pickup_day() {
+        let mut holds = queue();
+        set_aside(&mut holds, "Foundation", day(2024, 1, 10), 7);
+        let expired = expire(&mut holds, day(2024, 1, 17), 7);
+        assert!(expired.is_empty());
+        assert_eq!(holds.len(), 3);
+    }
+
+    #[test]
+    fn expired_hold_passes_the_copy_to_the_next_patron() {
+        let mut holds = queue();
+        set_aside(&mut holds, "Foundation", day(2024, 1, 10), 7);
+        let today = day(2024, 1, 18);
+        let expired = expire(&mut holds, today, 7);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].patron, "Kim");
+        let next = holds.iter().find(|h| h.is_for("Foundation")).unwrap();
+        assert_eq!(next.patron, "Ada");
+        assert_eq!(next.pickup_until, Some(day(2024, 1, 25)));
+        assert!(!holds.iter().find(|h| h.is_for("Golang")).unwrap().is_ready());
+    }
+}
@@ -0,0 +1,199 @@
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use chrono::NaiveDate;
+use unicode_width::UnicodeWidthStr;
+use crate::config::Config;
+use crate::template;
+
+/**
+ * Messages of the menu, the desks, the terminal UI, the subcommands and the
+ * REST API in the language of the branch. A language is a catalogue of `key = value` lines, `{name}` in a
+ * value is filled in like in the templates and `\n` starts a new line:
+ *
+ *   menu.title = Welcome to Rusty Library
+ *   borrow.done = {book} has been borrowed by {patron}, due on {due}
+ *
+ * English (en), Malay (ms) and Chinese (zh) are built in. A file
+ * `locales/<language>` next to the data files is used instead of the built in
+ * one, so the library can change a translation or add a language, and a key
+ * a catalogue lacks falls back to English.
+ *
+ * The language is `locale` in `libraryconfig`, else the one of `LC_ALL`,
+ * `LC_MESSAGES` or `LANG` (`ms_MY.UTF-8` is Malay), else English. Dates are
+ * shown with `date.format` of the catalogue and money with `money.format`
+ * and `money.decimal` and the `currency` of `libraryconfig`, dates are typed
+ * in as YYYY-MM-DD in every language. Data written for other programs, the
+ * csv and json of the reports and exports, the audit trail and the data
+ * files, keeps the same format in every language, amounts there are
+ * `ledger::plain_money`.
+ */
+const LOCALE_DIR: &str = "locales";
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en")),
+    ("ms", include_str!("../locales/ms")),
+    ("zh", include_str!("../locales/zh"))
+];
+
+struct Catalogue {
+    currency: String,
+    messages: HashMap<String, String>,
+    english: HashMap<String, String>
+}
+
+static CATALOGUE: OnceLock<Catalogue> = OnceLock::new();
+
+// `key = value` lines, `#` starts a comment line
+fn parse(data: &str) -> HashMap<String, String> {
+    data.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().replace("\\n", "\n")))
+        .collect()
+}
+
+fn load(language: &str) -> io::Result<Option<HashMap<String, String>>> {
+    match File::open(Path::new(LOCALE_DIR).join(language)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(BUILTIN.iter()
+            .find(|(name, _)| *name == language)
+            .map(|(_, data)| parse(data))),
+        Err(e) => Err(e),
+        Ok(mut file) => {
+            let mut data = String::new();
+            file.read_to_string(&mut data)?;
+            Ok(Some(parse(&data)))
+        }
+    }
+}
+
+// "ms_MY.UTF-8" -> "ms", "C" and "POSIX" are no language
+fn language_of(locale: &str) -> Option<String> {
+    let language = locale.split(['_', '.', '@', '-']).next().unwrap_or("").trim().to_lowercase();
+    match language.as_str() {
+        "" | "c" | "posix" => None,
+        _ => Some(language)
+    }
+}
+
+pub fn configured_language(config: &Config) -> String {
+    config.get("locale").and_then(language_of)
+        .or_else(|| ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| language_of(&value)))
+        .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string())
+}
+
+// pick the language once at start, a language without a catalogue is English
+pub fn init(config: &Config) -> io::Result<()> {
+    let english = load(DEFAULT_LANGUAGE)?.unwrap_or_default();
+    let language = configured_language(config);
+    let messages = load(&language)?;
+    let missing = messages.is_none();
+    let messages = messages.unwrap_or_else(|| english.clone());
+    let currency = config.currency().to_string();
+    // a second init keeps the first catalogue
+    let _ = CATALOGUE.set(Catalogue { currency, messages, english });
+    if missing {
+        eprintln!("{}", text("cli.no_catalogue", &[("language", language), ("dir", LOCALE_DIR.to_string())]));
+    }
+    Ok(())
+}
+
+// English with the default currency when init was not called, e.g. by the tests
+fn catalogue() -> &'static Catalogue {
+    CATALOGUE.get_or_init(|| {
+        let english = parse(BUILTIN[0].1);
+        Catalogue { currency: "$".to_string(), messages: english.clone(), english }
+    })
+}
+
+// the message of a key with its placeholders filled in, the key itself when no catalogue has it
+pub fn text(key: &str, values: &[(&str, String)]) -> String {
+    let catalogue = catalogue();
+    match catalogue.messages.get(key).or_else(|| catalogue.english.get(key)) {
+        None => key.to_string(),
+        Some(message) => template::render(message, values)
+    }
+}
+
+// t!("key") or t!("key", name = value, ...)
+macro_rules! t {
+    ($key:expr) => {
+        $crate::i18n::text($key, &[])
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::text($key, &[$((stringify!($name), $value.to_string())),+])
+    };
+}
+pub(crate) use t;
+
+pub fn date(date: NaiveDate) -> String {
+    date.format(&text("date.format", &[])).to_string()
+}
+
+// 150 -> "$1.50" in English, "RM1,50" with `currency = RM` and a decimal comma
+pub fn money(cents: i64) -> String {
+    let amount = format!("{}{}{:02}", cents.abs() / 100, text("money.decimal", &[]), cents.abs() % 100);
+    let money = text("money.format", &[("currency", catalogue().currency.clone()), ("amount", amount)]);
+    if cents < 0 { format!("-{}", money) } else { money }
+}
+
+// anything but the catalogue's words for yes is a no
+pub fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    !answer.is_empty() && text("answer.yes", &[]).split(',').any(|yes| yes.trim().to_lowercase() == answer)
+}
+
+// columns a text takes on a terminal, Chinese characters take two
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+// the text padded with spaces to a number of terminal columns
+pub fn pad(text: &str, columns: usize) -> String {
+    format!("{}{}", text, " ".repeat(columns.saturating_sub(width(text))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "{book} by {patron}" -> ["book", "patron"]
+    fn placeholders(message: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = message.split('{').skip(1).filter_map(|part| part.split_once('}')).map(|(name, _)| name).collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn every_language_has_every_english_message() {
+        let english = parse(BUILTIN[0].1);
+        for (language, data) in &BUILTIN[1..] {
+            let messages = parse(data);
+            for (key, message) in &english {
+                let translated = messages.get(key).unwrap_or_else(|| panic!("{} has no {}", language, key));
+                assert_eq!(placeholders(translated), placeholders(message), "{} of {}", key, language);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_key_is_shown_as_itself() {
+        assert_eq!(text("no.such.key", &[]), "no.such.key");
+        assert_eq!(t!("return.done", book = "Dune", patron = "Kim"), "Dune has been returned by Kim");
+    }
+
+    #[test]
+    fn language_comes_from_the_locale_name() {
+        assert_eq!(language_of("ms_MY.UTF-8").as_deref(), Some("ms"));
+        assert_eq!(language_of("zh-CN").as_deref(), Some("zh"));
+        assert_eq!(language_of("C"), None);
+    }
+}
@@ -0,0 +1,415 @@
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use chrono::{Datelike, NaiveDate};
+use crate::Book;
+use crate::barcode;
+use crate::collation;
+use crate::i18n::t;
+use crate::marc;
+use crate::staging::{self, DataFile};
+
+/**
+ * Bulk import of books from a CSV file (first line is the header), a JSON
+ * array of objects or MARC 21 records (binary or MARCXML, see `marc`).
+ *
+ * Columns are found by their usual names (title, author, year, isbn...) or
+ * mapped with `--map name=Title,year=Published`. Every row is validated and
+ * checked for duplicates (same ISBN, or same title + author + year) against
+ * the library store and the rows before it, nothing is written on a dry run.
+ */
+const FIELDS: &[(&str, &[&str])] = &[
+    ("name", &["name", "title", "book name"]),
+    ("author", &["author", "authors", "creator"]),
+    ("year", &["year", "year_published", "published year", "published"]),
+    ("issue_date", &["issue_date", "issue date", "acquired", "date"]),
+    ("item_type", &["item_type", "item type", "type"]),
+    ("isbn", &["isbn", "isbn13", "isbn10"]),
+    ("copies", &["copies", "quantity"])
+];
+
+// copies one row can add, a typo like 1000 instead of 10 is caught before it fills the store
+const MAX_COPIES: usize = 100;
+
+#[derive(Copy, Clone)]
+pub enum SourceFormat {
+    Csv,
+    Json,
+    Marc,
+    MarcXml
+}
+
+impl SourceFormat {
+    pub fn parse(s: &str) -> Option<SourceFormat> {
+        match s.trim() {
+            "csv" => Some(SourceFormat::Csv),
+            "json" => Some(SourceFormat::Json),
+            "marc" | "mrc" => Some(SourceFormat::Marc),
+            "marcxml" | "xml" => Some(SourceFormat::MarcXml),
+            _ => None
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<SourceFormat> {
+        SourceFormat::parse(&path.extension()?.to_str()?.to_lowercase())
+    }
+
+    // number of the first record in the file, a csv file starts with its header
+    pub fn first_row(self) -> usize {
+        match self {
+            SourceFormat::Csv => 2,
+            _ => 1
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportRow {
+    pub row: usize,
+    pub name: String,
+    pub author: String,
+    pub year_published: u32,
+    pub issue_date: NaiveDate,
+    pub item_type: String,
+    pub isbn: String,
+    pub copies: usize
+}
+
+impl ImportRow {
+    // a new, not borrowed copy
+    pub fn book<'a>(&'a self, barcode: &'a str) -> Book<'a> {
+        Book {
+            name: &self.name,
+            author: &self.author,
+            year_published: self.year_published,
+            borrowed: false,
+            issue_date: self.issue_date,
+            item_type: &self.item_type,
+            isbn: &self.isbn,
+            barcode
+        }
+    }
+}
+
+pub enum Problem {
+    Invalid(String),
+    Duplicate(String)
+}
+
+pub struct ImportReport {
+    pub rows: usize,
+    pub accepted: Vec<ImportRow>,
+    pub rejected: Vec<(usize, Problem)>
+}
+
+// "978-0-13-110362-7" -> "9780131103627"
+pub fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+pub fn is_valid_isbn(isbn: &str) -> bool {
+    let chars: Vec<char> = isbn.chars().collect();
+    match chars.len() {
+        10 => {
+            let mut sum = 0;
+            for (i, c) in chars.iter().enumerate() {
+                let value = match c.to_digit(10) {
+                    Some(d) => d,
+                    None if i == 9 && *c == 'X' => 10,
+                    None => return false
+                };
+                sum += value * (10 - i as u32);
+            }
+            sum % 11 == 0
+        },
+        13 => {
+            let mut sum = 0;
+            for (i, c) in chars.iter().enumerate() {
+                match c.to_digit(10) {
+                    Some(d) => sum += if i % 2 == 0 { d } else { d * 3 },
+                    None => return false
+                }
+            }
+            sum % 10 == 0
+        },
+        _ => false
+    }
+}
+
+// split csv text into rows of fields, quoted fields may hold commas, quotes ("") and new lines
+fn parse_csv(data: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => (),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ => field.push(c)
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.trim().is_empty()));
+    rows
+}
+
+// every record as column name -> value, column names are lower case
+pub fn read_records(data: &[u8], format: SourceFormat) -> Result<Vec<HashMap<String, String>>, String> {
+    let text = || std::str::from_utf8(data).map_err(|e| t!("import.not_utf8", error = e));
+    match format {
+        SourceFormat::Marc => Ok(marc::read_binary(data)?.iter().map(marc::to_import_record).collect()),
+        SourceFormat::MarcXml => Ok(marc::read_xml(text()?)?.iter().map(marc::to_import_record).collect()),
+        SourceFormat::Csv => {
+            let mut rows = parse_csv(text()?).into_iter();
+            let header: Vec<String> = match rows.next() {
+                Some(header) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+                None => return Ok(vec![])
+            };
+            Ok(rows.map(|row| header.iter().cloned().zip(row).collect()).collect())
+        },
+        SourceFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(text()?).map_err(|e| t!("import.invalid_json", error = e))?;
+            let items = value.as_array().ok_or_else(|| t!("import.not_array"))?;
+            let mut records = vec![];
+            for (i, item) in items.iter().enumerate() {
+                let object = item.as_object().ok_or_else(|| t!("import.not_object", number = i + 1))?;
+                records.push(object.iter()
+                    .map(|(k, v)| (k.trim().to_lowercase(), match v {
+                        serde_json::Value::String(s) => s.clone(),
+                        serde_json::Value::Null => String::new(),
+                        other => other.to_string()
+                    }))
+                    .collect());
+            }
+            Ok(records)
+        }
+    }
+}
+
+// "name=Title,year=Pub Year" -> field -> source column
+pub fn parse_mapping(spec: &str) -> Result<HashMap<String, String>, String> {
+    let mut mapping = HashMap::new();
+    for pair in spec.split(',').filter(|p| !p.trim().is_empty()) {
+        let (field, column) = pair.split_once('=').ok_or_else(|| t!("import.invalid_mapping", pair = pair))?;
+        let field = field.trim().to_lowercase();
+        if !FIELDS.iter().any(|(f, _)| *f == field) {
+            let names: Vec<&str> = FIELDS.iter().map(|(f, _)| *f).collect();
+            return Err(t!("import.unknown_field", field = field, fields = names.join(", ")));
+        }
+        mapping.insert(field, column.trim().to_lowercase());
+    }
+    Ok(mapping)
+}
+
+fn lookup<'r>(record: &'r HashMap<String, String>, mapping: &HashMap<String, String>, field: &str) -> &'r str {
+    let value = match mapping.get(field) {
+        Some(column) => record.get(column),
+        None => FIELDS.iter()
+            .find(|(f, _)| *f == field)
+            .and_then(|(_, aliases)| aliases.iter().find_map(|a| record.get(*a)))
+    };
+    value.map(|v| v.trim()).unwrap_or("")
+}
+
+fn validate(row: usize, record: &HashMap<String, String>, mapping: &HashMap<String, String>, today: NaiveDate) -> Result<ImportRow, String> {
+    let text = |field: &str| -> Result<String, String> {
+        let value = lookup(record, mapping, field);
+        if value.contains(',') || value.contains('\n') {
+            return Err(t!("error.comma", field = field));
+        }
+        Ok(value.to_string())
+    };
+
+    let name = text("name")?;
+    let author = text("author")?;
+    if name.is_empty() || author.is_empty() {
+        return Err(t!("import.name_author"));
+    }
+    let year = text("year")?;
+    let year_published: u32 = year.parse().map_err(|_| t!("import.year", year = year))?;
+    if year_published as i32 > today.year() {
+        return Err(t!("import.future_year", year = year_published));
+    }
+    let issue_date = match text("issue_date")?.as_str() {
+        "" => today,
+        date => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| t!("import.issue_date", date = date))?
+    };
+    let item_type = match text("item_type")?.as_str() {
+        "" => crate::DEFAULT_ITEM_TYPE.to_string(),
+        t => t.to_lowercase()
+    };
+    let isbn = normalize_isbn(&text("isbn")?);
+    if !isbn.is_empty() && !is_valid_isbn(&isbn) {
+        return Err(t!("error.isbn", isbn = isbn));
+    }
+    let copies = match text("copies")?.as_str() {
+        "" => 1,
+        c => c.parse().ok().filter(|c| *c > 0).ok_or_else(|| t!("import.copies", copies = c))?
+    };
+    if copies > MAX_COPIES {
+        return Err(t!("import.too_many_copies", copies = copies, limit = MAX_COPIES));
+    }
+    Ok(ImportRow { row, name, author, year_published, issue_date, item_type, isbn, copies })
+}
+
+fn same_book(isbn: &str, name: &str, author: &str, year: u32, other: (&str, &str, &str, u32)) -> bool {
+    let (other_isbn, other_name, other_author, other_year) = other;
+    (!isbn.is_empty() && isbn == other_isbn)
+        || (collation::same(name, other_name)
+            && collation::same(author, other_author)
+            && year == other_year)
+}
+
+// validate every record and sort them into rows to import and rejected rows,
+// first_row is the number of the first record in the file (2 below a csv header)
+pub fn plan(records: &[HashMap<String, String>], mapping: &HashMap<String, String>, existing: &[Book],
+    first_row: usize, today: NaiveDate) -> ImportReport {
+    let mut report = ImportReport { rows: records.len(), accepted: vec![], rejected: vec![] };
+    for (i, record) in records.iter().enumerate() {
+        let row = i + first_row;
+        let book = match validate(row, record, mapping, today) {
+            Err(reason) => {
+                report.rejected.push((row, Problem::Invalid(reason)));
+                continue;
+            },
+            Ok(book) => book
+        };
+        let in_store = existing.iter()
+            .find(|b| same_book(&book.isbn, &book.name, &book.author, book.year_published,
+                (&normalize_isbn(b.isbn), b.name, b.author, b.year_published)));
+        let in_file = report.accepted.iter()
+            .find(|b| same_book(&book.isbn, &book.name, &book.author, book.year_published,
+                (&b.isbn, &b.name, &b.author, b.year_published)));
+        if let Some(b) = in_store {
+            report.rejected.push((row, Problem::Duplicate(t!("import.in_store", book = b.name))));
+        } else if let Some(b) = in_file {
+            report.rejected.push((row, Problem::Duplicate(t!("import.same_book", book = book.name, row = b.row))));
+        } else {
+            report.accepted.push(book);
+        }
+    }
+    report
+}
+
+// append the accepted rows to the library store, it is written through `staging`
+// so a failed import leaves it untouched, the copies are numbered from the first barcode
+pub fn commit(rows: &[ImportRow], store_path: &Path, first_barcode: u64) -> io::Result<()> {
+    let mut data = String::new();
+    if let Ok(mut file) = File::open(store_path) {
+        file.read_to_string(&mut data)?;
+    }
+    if !data.is_empty() && !data.ends_with('\n') {
+        data.push('\n');
+    }
+    let mut number = first_barcode;
+    for row in rows {
+        for _ in 0..row.copies {
+            data.push_str(&row.book(&barcode::code(barcode::ITEM_PREFIX, number)).to_string());
+            number += 1;
+        }
+    }
+    staging::write_all(&[DataFile { path: store_path.to_path_buf(), data }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn record(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn quoted_csv_fields_keep_commas_quotes_and_new_lines() {
+        let rows = parse_csv("title,author\r\n\"Dune, Messiah\",Frank Herbert\n\"The \"\"Hobbit\"\"\",\"J. R. R.\nTolkien\"\n\n,\n");
+        assert_eq!(rows, vec![
+            vec!["title".to_string(), "author".to_string()],
+            vec!["Dune, Messiah".to_string(), "Frank Herbert".to_string()],
+            vec!["The \"Hobbit\"".to_string(), "J. R. R.\nTolkien".to_string()]
+        ]);
+        assert_eq!(parse_csv("title\nEmma").len(), 2);
+    }
+
+    #[test]
+    fn isbn_check_digits_are_checked() {
+        assert!(is_valid_isbn(&normalize_isbn("978-0-13-110362-7")));
+        assert!(!is_valid_isbn("9780131103628"));
+        assert!(is_valid_isbn(&normalize_isbn("0-8044-2957-x")));
+        assert!(is_valid_isbn("0131103628"));
+        assert!(!is_valid_isbn("0131103627"));
+        assert!(!is_valid_isbn("013110362"));
+        assert!(!is_valid_isbn("97801311036X7"));
+    }
+
+    #[test]
+    fn rows_are_validated() {
+        let today = day(2024, 3, 1);
+        let no_mapping = HashMap::new();
+        let valid = |fields: &[(&str, &str)]| validate(2, &record(fields), &no_mapping, today);
+        let row = valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "1965"), ("isbn", "978-0-441-17271-9")]).unwrap();
+        assert_eq!((row.copies, row.issue_date, row.item_type.as_str(), row.isbn.as_str()), (1, today, "book", "9780441172719"));
+        assert!(valid(&[("title", "Dune"), ("year", "1965")]).is_err());
+        assert!(valid(&[("title", "Dune, Messiah"), ("author", "Frank Herbert"), ("year", "1969")]).is_err());
+        assert!(valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "2025")]).is_err());
+        assert!(valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "1965"), ("isbn", "9780441172710")]).is_err());
+        assert!(valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "1965"), ("copies", "0")]).is_err());
+        assert!(valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "1965"), ("copies", "100")]).is_ok());
+        assert!(valid(&[("title", "Dune"), ("author", "Frank Herbert"), ("year", "1965"), ("copies", "101")]).is_err());
+
+        let mapping = parse_mapping("name=Book,year=Pub Year").unwrap();
+        let row = validate(2, &record(&[("book", "Emma"), ("author", "Jane Austen"), ("pub year", "1815")]), &mapping, today).unwrap();
+        assert_eq!((row.name.as_str(), row.year_published), ("Emma", 1815));
+    }
+
+    #[test]
+    fn duplicates_are_found_by_isbn_or_by_title_author_and_year() {
+        let store = "Dune,Frank Herbert,1965,false,2024-01-01,book,9780441172719,I00001\n";
+        let existing = crate::to_book_list(store).unwrap();
+        let records = [
+            record(&[("title", "Dune (Ace)"), ("author", "F. Herbert"), ("year", "1990"), ("isbn", "978-0-441-17271-9")]),
+            record(&[("title", "EMMA"), ("author", "jane austen"), ("year", "1815")]),
+            record(&[("title", "Emma"), ("author", "Jane Austen"), ("year", "1815")]),
+            record(&[("title", "Emma"), ("author", "Jane Austen"), ("year", "1816")])
+        ];
+        let report = plan(&records, &HashMap::new(), &existing, 2, day(2024, 3, 1));
+        let accepted: Vec<usize> = report.accepted.iter().map(|r| r.row).collect();
+        let rejected: Vec<usize> = report.rejected.iter().map(|(row, _)| *row).collect();
+        assert_eq!(accepted, [3, 5]);
+        assert_eq!(rejected, [2, 4]);
+        assert!(report.rejected.iter().all(|(_, p)| matches!(p, Problem::Duplicate(_))));
+    }
+
+    #[test]
+    fn commit_appends_numbered_copies_to_the_store() {
+        let path = std::env::temp_dir().join(format!("library-import-{}", std::process::id()));
+        std::fs::write(&path, "Dune,Frank Herbert,1965,false,2024-01-01,book,,I0000001").unwrap();
+        let row = validate(2, &record(&[("title", "Emma"), ("author", "Jane Austen"), ("year", "1815"), ("copies", "2")]),
+            &HashMap::new(), day(2024, 3, 1)).unwrap();
+        commit(&[row], &path, 2).unwrap();
+        let data = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let barcodes: Vec<&str> = crate::to_book_list(&data).unwrap().iter().map(|b| b.barcode).collect();
+        assert_eq!(barcodes, ["I0000001", "I0000002", "I0000003"]);
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use crate::Book;
+use crate::barcode::{self, Symbology};
+use crate::i18n::t;
+use crate::patron::Patron;
+use crate::template::{self, escape_xml};
+
+/**
+ * Printable sheets of barcode labels for copies and library cards for patrons.
+ *
+ * A sheet is an HTML page for A4 label stock, 3 x 7 labels of 63.5 x 38.1 mm
+ * for copies and cards of 85.6 x 54 mm, printed from the browser. The page is
+ * `templates/label_sheet.html` with {title}, {kind} and {labels}. A copy label
+ * has a spine mark (item type and author), the title and the barcode, the
+ * barcode is the copy barcode in Code 128 or the ISBN in EAN-13.
+ */
+const SHEET_TEMPLATE: &str = "label_sheet.html";
+
+// item type and the first letters of the author's last name, e.g. "BOOK ASI"
+fn spine_mark(book: &Book) -> String {
+    let surname = book.author.split_whitespace().last().unwrap_or("");
+    format!("{} {}", book.item_type, surname.chars().take(3).collect::<String>()).to_uppercase()
+}
+
+// the code printed for a copy
+pub fn item_code(book: &Book, symbology: Symbology) -> Result<String, String> {
+    match symbology {
+        Symbology::Code128 if book.barcode.is_empty() => Err(t!("labels.no_barcode", book = book.name)),
+        Symbology::Code128 => Ok(book.barcode.to_string()),
+        Symbology::Ean13 => barcode::isbn_digits(book.isbn).ok_or_else(|| t!("labels.no_isbn", book = book.name))
+    }
+}
+
+pub fn item_svg(book: &Book, symbology: Symbology) -> Result<String, String> {
+    let code = item_code(book, symbology)?;
+    Ok(barcode::to_svg(&barcode::encode(&code, symbology)?, &code))
+}
+
+pub fn card_svg(patron: &Patron) -> Result<String, String> {
+    if patron.card.is_empty() {
+        return Err(t!("labels.no_card", patron = patron.name));
+    }
+    Ok(barcode::to_svg(&barcode::code128(&patron.card)?, &patron.card))
+}
+
+fn sheet(title: &str, kind: &str, labels: &str) -> io::Result<String> {
+    let page = template::load(SHEET_TEMPLATE, include_str!("../templates/label_sheet.html"))?;
+    Ok(template::render(&page, &[("title", escape_xml(title)), ("kind", kind.to_string()), ("labels", labels.to_string())]))
+}
+
+// the sheet and the copies that could not be printed
+pub fn item_sheet(books: &[Book], symbology: Symbology) -> io::Result<(String, Vec<String>)> {
+    let mut labels = String::new();
+    let mut problems = vec![];
+    for book in books {
+        match item_svg(book, symbology) {
+            Err(err) => problems.push(err),
+            Ok(svg) => labels.push_str(&format!(
+                "<div class=\"label\"><div class=\"spine\">{}</div><div class=\"title\">{}</div><div class=\"meta\">{}, {}</div>{}</div>\n",
+                escape_xml(&spine_mark(book)), escape_xml(book.name), escape_xml(book.author), book.year_published, svg))
+        }
+    }
+    Ok((sheet(&t!("labels.items_title"), "items", &labels)?, problems))
+}
+
+// the sheet and the patrons that could not be printed
+pub fn card_sheet(patrons: &[Patron]) -> io::Result<(String, Vec<String>)> {
+    let mut labels = String::new();
+    let mut problems = vec![];
+    for patron in patrons {
+        match card_svg(patron) {
+            Err(err) => problems.push(err),
+            Ok(svg) => labels.push_str(&format!(
+                "<div class=\"label\"><div class=\"library\">{}</div><div class=\"name\">{}</div><div class=\"meta\">{}</div>{}</div>\n",
+                escape_xml(&t!("labels.library")), escape_xml(&patron.name), escape_xml(&patron.category), svg))
+        }
+    }
+    Ok((sheet(&t!("labels.cards_title"), "cards", &labels)?, problems))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE: &str = "\
+Dune,Frank Herbert,1965,false,2024-01-01,book,0441172717,I0000001
+Emma,Jane Austen,1815,false,2024-01-02,book,,
+Akira,Otomo,1982,true,2024-01-03,comic,9781935429005,I0000003
+";
+
+    fn patron(name: &str, card: &str) -> Patron {
+        Patron { name: name.to_string(), category: "adult".to_string(), email: String::new(), notify: true, card: card.to_string() }
+    }
+
+    #[test]
+    fn item_code_is_the_barcode_or_the_isbn() {
+        let books = crate::to_book_list(STORE).unwrap();
+        assert_eq!(item_code(&books[0], Symbology::Code128).unwrap(), "I0000001");
+        assert_eq!(item_code(&books[0], Symbology::Ean13).unwrap(), "9780441172719");
+        assert!(item_code(&books[1], Symbology::Code128).is_err());
+        assert!(item_code(&books[1], Symbology::Ean13).is_err());
+    }
+
+    #[test]
+    fn spine_mark_is_the_item_type_and_the_surname() {
+        let books = crate::to_book_list(STORE).unwrap();
+        assert_eq!(spine_mark(&books[0]), "BOOK HER");
+        assert_eq!(spine_mark(&books[2]), "COMIC OTO");
+    }
+
+    #[test]
+    fn sheets_list_what_could_not_be_printed() {
+        let books = crate::to_book_list(STORE).unwrap();
+        let (page, problems) = item_sheet(&books, Symbology::Code128).unwrap();
+        assert_eq!(page.matches("class=\"label\"").count(), 2);
+        assert_eq!(problems, [t!("labels.no_barcode", book = "Emma")]);
+        assert!(page.contains("Rusty Library labels"));
+
+        let (page, problems) = card_sheet(&[patron("Kim", "P0000001"), patron("Lee", "")]).unwrap();
+        assert_eq!(page.matches("class=\"label\"").count(), 1);
+        assert_eq!(problems, [t!("labels.no_card", patron = "Lee")]);
+    }
+}
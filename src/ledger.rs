@@ -0,0 +1,213 @@
+use std::io;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::io::prelude::*;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDate;
+use crate::collation;
+use crate::i18n;
+use crate::library::{self, LibraryError};
+use crate::staging::DataFile;
+
+/**
+ * Patron account ledger.
+ *
+ * Every fine charged, payment taken, fine waived or money refunded is kept
+ * as one line in the `ledger` file:
+ *
+ *   patron,date,kind,amount,note
+ *
+ * amount is stored in cents so partial payments add up exactly.
+ * The outstanding balance of a patron is fines + refunds - payments - waivers.
+ */
+const LEDGER_PATH: &str = "ledger";
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Fine,
+    Payment,
+    Waiver,
+    Refund
+}
+
+impl EntryKind {
+    pub fn parse(s: &str) -> Option<EntryKind> {
+        match s.trim() {
+            "fine" => Some(EntryKind::Fine),
+            "payment" => Some(EntryKind::Payment),
+            "waiver" => Some(EntryKind::Waiver),
+            "refund" => Some(EntryKind::Refund),
+            _ => None
+        }
+    }
+
+    // sign of the entry on the patron balance
+    pub fn sign(self) -> i64 {
+        match self {
+            EntryKind::Fine | EntryKind::Refund => 1,
+            EntryKind::Payment | EntryKind::Waiver => -1
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            EntryKind::Fine => "fine",
+            EntryKind::Payment => "payment",
+            EntryKind::Waiver => "waiver",
+            EntryKind::Refund => "refund"
+        };
+        f.pad(s)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub patron: String,
+    pub date: NaiveDate,
+    pub kind: EntryKind,
+    pub amount: i64,
+    pub note: String
+}
+
+impl LedgerEntry {
+    pub fn new(patron: &str, date: NaiveDate, kind: EntryKind, amount: i64, note: &str) -> LedgerEntry {
+        LedgerEntry {
+            patron: patron.trim().to_string(),
+            date,
+            kind,
+            amount,
+            // the file is comma separated, keep the note on one field
+            note: note.trim().replace(',', ";").replace('\n', " ")
+        }
+    }
+
+    pub fn is_for(&self, patron: &str) -> bool {
+        collation::same(&self.patron, patron)
+    }
+}
+
+impl fmt::Display for LedgerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{},{}", self.patron, self.date, self.kind, self.amount, self.note)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<LedgerEntry> {
+    let slice_data: Vec<&str> = line.splitn(5, ',').collect();
+    if slice_data.len() < 4 {
+        return None;
+    }
+    Some(LedgerEntry {
+        patron: slice_data[0].trim().to_string(),
+        date: NaiveDate::parse_from_str(slice_data[1].trim(), "%Y-%m-%d").ok()?,
+        kind: EntryKind::parse(slice_data[2])?,
+        amount: slice_data[3].trim().parse().ok()?,
+        note: slice_data.get(4).map(|n| n.trim().to_string()).unwrap_or_default()
+    })
+}
+
+// read every entry in the ledger, a missing ledger file is an empty ledger
+pub fn load_entries() -> Result<Vec<LedgerEntry>, LibraryError> {
+    library::read_records(LEDGER_PATH, parse_entry)
+}
+
+// the whole `ledger` file, for writes staged together with other files
+pub fn data_file(entries: &[LedgerEntry]) -> DataFile {
+    DataFile::new(LEDGER_PATH, entries)
+}
+
+pub fn record(entry: &LedgerEntry) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(LEDGER_PATH))?;
+    write!(&mut file, "{}", entry)
+}
+
+pub fn entries_for(patron: &str) -> Result<Vec<LedgerEntry>, LibraryError> {
+    Ok(load_entries()?.into_iter().filter(|e| e.is_for(patron)).collect())
+}
+
+// outstanding balance in cents, negative means the patron is in credit
+pub fn balance_of(entries: &[LedgerEntry]) -> i64 {
+    entries.iter().map(|e| e.kind.sign() * e.amount).sum()
+}
+
+pub fn balance(patron: &str) -> Result<i64, LibraryError> {
+    Ok(balance_of(&entries_for(patron)?))
+}
+
+// 150 -> "$1.50", in the currency and language of the library
+pub fn format_money(cents: i64) -> String {
+    i18n::money(cents)
+}
+
+// 150 -> "$1.50" in every language, for csv, json and other data
+pub fn plain_money(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{}${}.{:02}", sign, cents.abs() / 100, cents.abs() % 100)
+}
+
+// "1.5", "$1.50", "RM1,50" or "2" -> cents, refuses negative, malformed and too large amounts
+pub fn parse_money(s: &str) -> Option<i64> {
+    let s = s.trim().trim_start_matches(|c: char| !c.is_ascii_digit() && c != '-' && c != '+' && c != '.' && c != ',');
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let mut parts = s.splitn(2, ['.', ',']);
+    let dollars = parts.next().filter(|d| is_digits(d))?;
+    let dollars: i64 = dollars.parse().ok()?;
+    let cents: i64 = match parts.next() {
+        None => 0,
+        Some(c) if c.len() <= 2 && is_digits(c) => c.parse::<i64>().ok()? * if c.len() == 1 { 10 } else { 1 },
+        Some(_) => return None
+    };
+    dollars.checked_mul(100)?.checked_add(cents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    #[test]
+    fn parses_dollars_and_cents() {
+        assert_eq!(parse_money("2"), Some(200));
+        assert_eq!(parse_money("1.5"), Some(150));
+        assert_eq!(parse_money(" $1.50\n"), Some(150));
+        assert_eq!(parse_money("RM1,05"), Some(105));
+    }
+
+    #[test]
+    fn refuses_signs_and_malformed_cents() {
+        assert_eq!(parse_money("1.-5"), None);
+        assert_eq!(parse_money("1.+5"), None);
+        assert_eq!(parse_money("-1.50"), None);
+        assert_eq!(parse_money("+1.50"), None);
+        assert_eq!(parse_money("1.505"), None);
+        assert_eq!(parse_money("1."), None);
+        assert_eq!(parse_money(".50"), None);
+        assert_eq!(parse_money("1.5a"), None);
+    }
+
+    #[test]
+    fn refuses_amounts_that_overflow() {
+        assert_eq!(parse_money(&i64::MAX.to_string()), None);
+        assert_eq!(parse_money("92233720368547758.07"), Some(i64::MAX));
+        assert_eq!(parse_money("92233720368547758.08"), None);
+    }
+
+    #[test]
+    fn balance_adds_fines_and_refunds_and_takes_off_payments_and_waivers() {
+        let today = day(2024, 3, 1);
+        let entries = vec![
+            LedgerEntry::new("Kim", today, EntryKind::Fine, 500, "late"),
+            LedgerEntry::new("Kim", today, EntryKind::Payment, 200, ""),
+            LedgerEntry::new("Kim", today, EntryKind::Waiver, 100, ""),
+            LedgerEntry::new("Kim", today, EntryKind::Refund, 50, "")
+        ];
+        assert_eq!(balance_of(&entries), 250);
+    }
+}
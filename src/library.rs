@@ -0,0 +1,736 @@
+use std::io;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::io::prelude::*;
+use std::fmt;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::{Book, DEFAULT_ITEM_TYPE};
+use crate::audit;
+use crate::barcode;
+use crate::collation;
+use crate::auth::{Forbidden, Permission, Staff};
+use crate::config::Config;
+use crate::eligibility::{self, LoanRefusal};
+use crate::hold::{self, Hold};
+use crate::i18n::t;
+use crate::import;
+use crate::ledger::{self, EntryKind, LedgerEntry};
+use crate::loan::{self, Loan, RenewError};
+use crate::patron::{self, Patron};
+use crate::staging::{self, DataFile};
+use crate::trash::{self, Withdrawn};
+
+/**
+ * Library operations shared by the interactive menu and the REST API.
+ *
+ * Every operation checks the permission of the staff account, its input and
+ * the library rules before it writes the data files, a refusal comes back as
+ * a LibraryError with nothing written. The files an operation changes are
+ * written together through `staging`, so a failed write changes none of them.
+ * Every change is then added to the audit trail, when that fails the change
+ * stays made and the error says it is missing from the trail.
+ * Copies are addressed by their line number in the library store, counted from 0
+ * as in the book lists of the menu, or found by their barcode with `copy_by_barcode`.
+ */
+const STORE_PATH: &str = "librarystore";
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(io::Error),
+    NotFound(String),
+    Invalid(String),
+    Conflict(String),
+    Refused(LoanRefusal),
+    RenewRefused(RenewError),
+    Forbidden(Forbidden),
+    // a data file that cannot be read back, e.g. a short line in the library store
+    Damaged(String),
+    // the change was written but its audit entry was not
+    Unaudited(io::Error)
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LibraryError::Io(err) => write!(f, "{}", t!("error.io", error = err)),
+            LibraryError::NotFound(what) | LibraryError::Invalid(what) | LibraryError::Conflict(what) => write!(f, "{}", what),
+            LibraryError::Damaged(what) => write!(f, "{}", t!("error.damaged", what = what)),
+            LibraryError::Unaudited(err) => write!(f, "{}", t!("error.unaudited", error = err)),
+            LibraryError::Refused(reason) => write!(f, "{}", t!("error.refused", reason = reason)),
+            LibraryError::RenewRefused(reason) => write!(f, "{}", t!("error.renew_refused", reason = reason)),
+            LibraryError::Forbidden(reason) => write!(f, "{}", t!("error.forbidden", reason = reason))
+        }
+    }
+}
+
+impl From<Forbidden> for LibraryError {
+    fn from(err: Forbidden) -> LibraryError {
+        LibraryError::Forbidden(err)
+    }
+}
+
+impl From<io::Error> for LibraryError {
+    fn from(err: io::Error) -> LibraryError {
+        LibraryError::Io(err)
+    }
+}
+
+// a new book, or the new details of a copy
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookInput {
+    pub name: String,
+    pub author: String,
+    pub year_published: u32,
+    pub issue_date: Option<NaiveDate>,
+    pub item_type: Option<String>,
+    pub isbn: Option<String>
+}
+
+pub struct Returned {
+    pub loan: Loan,
+    pub days_overdue: i64,
+    pub fine: i64,
+    // hold the copy has been set aside for
+    pub set_aside: Option<Hold>
+}
+
+pub fn read_store() -> io::Result<String> {
+    let mut data = String::new();
+    match File::open(Path::new(STORE_PATH)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+        Ok(mut file) => {
+            file.read_to_string(&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+// every record in one of the other data files, a missing file has none and a line
+// that cannot be read is an error that names it, so the next write cannot drop it
+pub fn read_records<T>(path: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Vec<T>, LibraryError> {
+    let mut data = String::new();
+    match File::open(Path::new(path)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+        Ok(mut file) => {
+            file.read_to_string(&mut data)?;
+        }
+    }
+    let mut records = vec![];
+    for (number, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse(line) {
+            Some(record) => records.push(record),
+            None => return Err(LibraryError::Damaged(t!("error.data_line", line = number + 1, file = path)))
+        }
+    }
+    Ok(records)
+}
+
+// add a change that is already written to the audit trail
+fn audited(staff: &Staff, operation: &str, record: &str, before: Option<Value>, after: Option<Value>) -> Result<(), LibraryError> {
+    audit::record(&staff.name, operation, record, before, after).map_err(LibraryError::Unaudited)
+}
+
+fn store_file(books: &[Book]) -> DataFile {
+    DataFile::new(STORE_PATH, books)
+}
+
+fn write_books(books: &[Book]) -> io::Result<()> {
+    staging::write_all(&[store_file(books)])
+}
+
+fn check_text(field: &str, value: &str, required: bool) -> Result<(), LibraryError> {
+    let value = value.trim();
+    if required && value.is_empty() {
+        return Err(LibraryError::Invalid(t!("error.required", field = t!(field))));
+    }
+    if value.contains(',') || value.contains('\n') {
+        return Err(LibraryError::Invalid(t!("error.comma", field = t!(field))));
+    }
+    Ok(())
+}
+
+// the input with its defaults filled in, as (issue date, item type, isbn)
+fn check_book(input: &BookInput, today: NaiveDate) -> Result<(NaiveDate, String, String), LibraryError> {
+    check_text("error.field.name", &input.name, true)?;
+    check_text("error.field.author", &input.author, true)?;
+    let item_type = match input.item_type.as_deref().map(str::trim) {
+        None | Some("") => DEFAULT_ITEM_TYPE.to_string(),
+        Some(t) => t.to_lowercase()
+    };
+    check_text("error.field.item_type", &item_type, true)?;
+    let isbn = import::normalize_isbn(input.isbn.as_deref().unwrap_or(""));
+    if !isbn.is_empty() && !import::is_valid_isbn(&isbn) {
+        return Err(LibraryError::Invalid(t!("error.isbn", isbn = isbn)));
+    }
+    Ok((input.issue_date.unwrap_or(today), item_type, isbn))
+}
+
+// "book [3] Dune", how the audit trail names a copy
+fn book_record(index: usize, book: &Book) -> String {
+    format!("book [{}] {}", index, book.name)
+}
+
+fn book_at<'a, 'b>(books: &'a mut [Book<'b>], index: usize) -> Result<&'a mut Book<'b>, LibraryError> {
+    books.get_mut(index).ok_or_else(|| LibraryError::NotFound(t!("error.no_book", number = index)))
+}
+
+// add a copy to the library store, returns its number
+pub fn add_book(input: &BookInput, today: NaiveDate, staff: &Staff) -> Result<usize, LibraryError> {
+    staff.require(Permission::Catalogue)?;
+    let (issue_date, item_type, isbn) = check_book(input, today)?;
+    let data = read_store()?;
+    let books = crate::to_book_list(&data)?;
+    let copies = books.len();
+    let code = barcode::code(barcode::ITEM_PREFIX, next_item_number(&books)?);
+    let book = Book {
+        name: input.name.trim(),
+        author: input.author.trim(),
+        year_published: input.year_published,
+        borrowed: false,
+        issue_date,
+        item_type: &item_type,
+        isbn: &isbn,
+        barcode: &code
+    };
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(STORE_PATH))?;
+    write!(&mut file, "{}", book)?;
+    audited(staff, "add book", &book_record(copies, &book), None, Some(json!(book)))?;
+    Ok(copies)
+}
+
+// new details of a copy, its borrow status is kept
+pub fn update_book(index: usize, input: &BookInput, today: NaiveDate, staff: &Staff) -> Result<(), LibraryError> {
+    staff.require(Permission::Catalogue)?;
+    let (issue_date, item_type, isbn) = check_book(input, today)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let book = book_at(&mut books, index)?;
+    if book.borrowed && book.name != input.name.trim() {
+        return Err(LibraryError::Conflict(t!("error.renaming_loan", book = book.name)));
+    }
+    let before = json!(book);
+    book.name = input.name.trim();
+    book.author = input.author.trim();
+    book.year_published = input.year_published;
+    book.issue_date = issue_date;
+    book.item_type = &item_type;
+    book.isbn = &isbn;
+    let (record, after) = (book_record(index, book), json!(book));
+    write_books(&books)?;
+    audited(staff, "edit book", &record, Some(before), Some(after))?;
+    Ok(())
+}
+
+// take a copy out of the library store into the trash, it can be restored until it is purged
+pub fn withdraw_book(index: usize, reason: &str, today: NaiveDate, staff: &Staff) -> Result<Withdrawn, LibraryError> {
+    staff.require(Permission::Catalogue)?;
+    check_text("error.field.reason", reason, true)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let book = *book_at(&mut books, index)?;
+    if book.borrowed {
+        return Err(LibraryError::Conflict(t!("error.withdrawing_loan", book = book.name)));
+    }
+    // patrons waiting for a title keep its last copy
+    let waiting = hold::load_holds()?.iter().filter(|h| h.is_for(book.name)).count();
+    if waiting > 0 && books.iter().filter(|b| b.name == book.name).count() == 1 {
+        return Err(LibraryError::Conflict(t!("error.last_copy", book = book.name, count = waiting)));
+    }
+    let withdrawn = Withdrawn {
+        date: today,
+        staff: staff.name.clone(),
+        reason: reason.trim().to_string(),
+        store_line: book.to_string().trim_end().to_string()
+    };
+    let mut trash = trash::load_trash()?;
+    trash.push(withdrawn.clone());
+    books.remove(index);
+    staging::write_all(&[store_file(&books), trash::data_file(&trash)])?;
+    audited(staff, "withdraw book", &book_record(index, &book), Some(json!(book)), Some(withdrawn.to_json()))?;
+    Ok(withdrawn)
+}
+
+fn trash_at(trash: &[Withdrawn], index: usize) -> Result<&Withdrawn, LibraryError> {
+    trash.get(index).ok_or_else(|| LibraryError::NotFound(t!("error.not_in_trash", number = index)))
+}
+
+// put a withdrawn copy back at the end of the library store, returns its number
+pub fn restore_book(trash_index: usize, staff: &Staff) -> Result<usize, LibraryError> {
+    staff.require(Permission::Catalogue)?;
+    let mut trash = trash::load_trash()?;
+    let withdrawn = trash_at(&trash, trash_index)?.clone();
+    let book = withdrawn.book()
+        .ok_or_else(|| LibraryError::Damaged(t!("error.trash_line", number = trash_index)))?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let copies = books.len();
+    books.push(book);
+    trash.remove(trash_index);
+    staging::write_all(&[store_file(&books), trash::data_file(&trash)])?;
+    audited(staff, "restore book", &book_record(copies, &book), Some(withdrawn.to_json()), Some(json!(book)))?;
+    Ok(copies)
+}
+
+// drop one withdrawn copy for good, or the whole trash, returns how many were purged
+pub fn purge(trash_index: Option<usize>, staff: &Staff) -> Result<usize, LibraryError> {
+    staff.require(Permission::Administration)?;
+    let mut trash = trash::load_trash()?;
+    let purged: Vec<Withdrawn> = match trash_index {
+        Some(index) => {
+            trash_at(&trash, index)?;
+            vec![trash.remove(index)]
+        },
+        None => std::mem::take(&mut trash)
+    };
+    trash::save_trash(&trash)?;
+    for withdrawn in &purged {
+        let name = withdrawn.book().map(|b| b.name.to_string()).unwrap_or_default();
+        audited(staff, "purge book", &format!("book {}", name), Some(withdrawn.to_json()), None)?;
+    }
+    Ok(purged.len())
+}
+
+fn load_loans(config: &Config) -> Result<Vec<Loan>, LibraryError> {
+    loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY))
+}
+
+// lend a copy after checking the patron limits and the hold shelf
+pub fn issue(index: usize, borrower: &str, issue_date: NaiveDate, today: NaiveDate,
+    config: &Config, staff: &Staff) -> Result<Loan, LibraryError> {
+    staff.require(Permission::Circulation)?;
+    check_text("error.field.borrower", borrower, true)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let book = *book_at(&mut books, index)?;
+    if book.borrowed {
+        return Err(LibraryError::Conflict(t!("error.already_on_loan", book = book.name, number = index)));
+    }
+
+    let category = patron::category_of(borrower)?;
+    let mut loans = load_loans(config)?;
+    let balance = ledger::balance(borrower)?;
+    eligibility::check(&loans, borrower, &category, balance, config, today).map_err(LibraryError::Refused)?;
+
+    // copies on the hold shelf can only go to the patron they are set aside for
+    let mut holds = hold::load_holds()?;
+    hold::expire(&mut holds, today, config.hold_pickup_days());
+    let free_copies = books.iter().filter(|b| b.name == book.name && !b.borrowed).count();
+    if hold::set_aside_for_others(&holds, book.name, borrower) >= free_copies {
+        return Err(LibraryError::Conflict(t!("error.set_aside", book = book.name)));
+    }
+    holds.retain(|h| !(h.is_for(book.name) && h.is_by(borrower)));
+
+    let mut loan = Loan::new(borrower, book.name, issue_date, config.loan_days(book.item_type, &category), &staff.name);
+    loan.barcode = book.barcode.to_string();
+    loans.push(loan.clone());
+    books[index].borrowed = true;
+    staging::write_all(&[store_file(&books), loan::data_file(&loans), hold::data_file(&holds)])?;
+    audited(staff, "issue", &loan_record(index, &loan), None, Some(json!(loan)))?;
+    Ok(loan)
+}
+
+// "loan Dune [3] to Kim Loo Uii"
+fn loan_record(index: usize, loan: &Loan) -> String {
+    format!("loan {} [{}] to {}", loan.book_name, index, loan.borrower)
+}
+
+// take a copy back, charge the late fine and set the copy aside for the next hold
+pub fn return_book(index: usize, borrower: &str, today: NaiveDate, config: &Config, staff: &Staff) -> Result<Returned, LibraryError> {
+    staff.require(Permission::Circulation)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let book = *book_at(&mut books, index)?;
+    if !book.borrowed {
+        return Err(LibraryError::Conflict(t!("error.not_on_loan", book = book.name, number = index)));
+    }
+    let mut loans = load_loans(config)?;
+    // the loan is kept as history, only its return is recorded, it has to be the loan
+    // of this very copy unless the copy or the loan is from before barcodes
+    let open = |l: &Loan| l.is_open() && l.is_for(borrower) && l.book_name == book.name;
+    let position = loans.iter().position(|l| open(l) && !book.barcode.is_empty() && l.barcode == book.barcode)
+        .or_else(|| loans.iter().position(|l| open(l) && (book.barcode.is_empty() || l.barcode.is_empty())))
+        .ok_or_else(|| LibraryError::NotFound(t!("error.no_loan", patron = borrower.trim(), book = book.name)))?;
+    let loan = &mut loans[position];
+    let days_overdue = loan.days_overdue(today);
+    let fine = days_overdue * config.fine_per_day();
+    let before = json!(loan);
+    loan.close(today, fine, &staff.name);
+    let loan = loan.clone();
+    books[index].borrowed = false;
+
+    let mut holds = hold::load_holds()?;
+    hold::expire(&mut holds, today, config.hold_pickup_days());
+    let set_aside = hold::set_aside(&mut holds, book.name, today, config.hold_pickup_days());
+    let mut files = vec![store_file(&books), loan::data_file(&loans), hold::data_file(&holds)];
+    if fine > 0 {
+        let mut entries = ledger::load_entries()?;
+        entries.push(LedgerEntry::new(borrower, today, EntryKind::Fine, fine, &format!("late return of {}", book.name)));
+        files.push(ledger::data_file(&entries));
+    }
+    staging::write_all(&files)?;
+    audited(staff, "return", &loan_record(index, &loan), Some(before), Some(json!(loan)))?;
+    Ok(Returned { loan, days_overdue, fine, set_aside })
+}
+
+// the copy of a title a patron has on loan, the one with the barcode of their loan
+pub fn copy_on_loan(books: &[Book], book_name: &str, borrower: &str, config: &Config) -> Result<usize, LibraryError> {
+    let no_loan = || LibraryError::NotFound(t!("error.no_loan", patron = borrower.trim(), book = book_name));
+    let loans = load_loans(config)?;
+    let loan = loans.iter()
+        .find(|l| l.is_open() && l.is_for(borrower) && l.book_name == book_name)
+        .ok_or_else(no_loan)?;
+    let on_loan = |b: &&Book| b.borrowed && b.name == loan.book_name;
+    books.iter()
+        .position(|b| on_loan(&b) && !loan.barcode.is_empty() && b.barcode == loan.barcode)
+        .or_else(|| books.iter().position(|b| on_loan(&b) && (loan.barcode.is_empty() || b.barcode.is_empty())))
+        .ok_or_else(no_loan)
+}
+
+// first free copy barcode number, withdrawn copies keep theirs in the trash
+pub fn next_item_number(books: &[Book]) -> Result<u64, LibraryError> {
+    let trash = trash::load_trash()?;
+    let withdrawn: Vec<Book> = trash.iter().filter_map(|w| w.book()).collect();
+    Ok(barcode::last_number(barcode::ITEM_PREFIX, books.iter().chain(&withdrawn).map(|b| b.barcode)) + 1)
+}
+
+// line number of the copy with a barcode
+pub fn copy_by_barcode(code: &str) -> Result<usize, LibraryError> {
+    let code = barcode::normalize(code);
+    crate::to_book_list(&read_store()?)?
+        .iter()
+        .position(|b| !code.is_empty() && b.barcode == code)
+        .ok_or_else(|| LibraryError::NotFound(t!("error.unknown_barcode", code = code)))
+}
+
+pub fn patron_by_card(code: &str) -> Result<Patron, LibraryError> {
+    let code = barcode::normalize(code);
+    patron::load_patrons()?
+        .into_iter()
+        .find(|p| !code.is_empty() && p.card == code)
+        .ok_or_else(|| LibraryError::NotFound(t!("error.unknown_card", code = code)))
+}
+
+// take a scanned copy back from whoever has it on loan
+pub fn check_in(code: &str, today: NaiveDate, config: &Config, staff: &Staff) -> Result<Returned, LibraryError> {
+    staff.require(Permission::Circulation)?;
+    let index = copy_by_barcode(code)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let book = *book_at(&mut books, index)?;
+    if !book.borrowed {
+        return Err(LibraryError::Conflict(t!("error.not_on_loan", book = book.name, number = index)));
+    }
+    let loans = load_loans(config)?;
+    // loans made before copies had barcodes only go by the title
+    let borrower = match loans.iter().find(|l| l.is_open() && l.barcode == book.barcode) {
+        Some(loan) => loan.borrower.clone(),
+        None => {
+            let mut borrowers: Vec<&str> = loans.iter()
+                .filter(|l| l.is_open() && l.barcode.is_empty() && l.book_name == book.name)
+                .map(|l| l.borrower.as_str())
+                .collect();
+            borrowers.sort();
+            borrowers.dedup();
+            match borrowers.as_slice() {
+                [] => return Err(LibraryError::NotFound(t!("error.no_open_loan", book = book.name))),
+                [borrower] => borrower.to_string(),
+                _ => return Err(LibraryError::Conflict(t!("error.many_borrowers", book = book.name, borrowers = borrowers.join(", "))))
+            }
+        }
+    };
+    return_book(index, &borrower, today, config, staff)
+}
+
+// copies and patrons added before barcodes existed get the next free ones,
+// returns how many copies and cards got one
+pub fn assign_barcodes(staff: &Staff) -> Result<(usize, usize), LibraryError> {
+    staff.require(Permission::Catalogue)?;
+    staff.require(Permission::Patrons)?;
+    let data = read_store()?;
+    let mut books = crate::to_book_list(&data)?;
+    let first = next_item_number(&books)?;
+    let codes: Vec<String> = (first..).take(books.iter().filter(|b| b.barcode.is_empty()).count())
+        .map(|n| barcode::code(barcode::ITEM_PREFIX, n))
+        .collect();
+    let mut changes = vec![];
+    let mut new_codes = codes.iter();
+    for (index, book) in books.iter_mut().enumerate().filter(|(_, b)| b.barcode.is_empty()) {
+        let before = json!(book);
+        book.barcode = new_codes.next().map(|c| c.as_str()).unwrap_or_default();
+        changes.push((book_record(index, book), before, json!(book)));
+    }
+    if !changes.is_empty() {
+        write_books(&books)?;
+    }
+    for (record, before, after) in &changes {
+        audited(staff, "assign barcode", record, Some(before.clone()), Some(after.clone()))?;
+    }
+
+    let mut patrons = patron::load_patrons()?;
+    let mut number = barcode::last_number(barcode::CARD_PREFIX, patrons.iter().map(|p| p.card.as_str()));
+    let mut cards = vec![];
+    for patron in patrons.iter_mut().filter(|p| p.card.is_empty()) {
+        let before = json!(patron);
+        number += 1;
+        patron.card = barcode::code(barcode::CARD_PREFIX, number);
+        cards.push((format!("patron {}", patron.name), before, json!(patron)));
+    }
+    if !cards.is_empty() {
+        patron::save_patrons(&patrons)?;
+    }
+    for (record, before, after) in &cards {
+        audited(staff, "assign card", record, Some(before.clone()), Some(after.clone()))?;
+    }
+    Ok((changes.len(), cards.len()))
+}
+
+// item type of a title in the library store, titles that are not found are plain books
+pub fn item_type_of(book_name: &str) -> Result<String, LibraryError> {
+    Ok(crate::to_book_list(&read_store()?)?
+        .iter()
+        .find(|b| b.name == book_name)
+        .map(|b| b.item_type.to_string())
+        .unwrap_or_else(|| DEFAULT_ITEM_TYPE.to_string()))
+}
+
+// push the due date of an open loan by another loan period
+pub fn renew(borrower: &str, book_name: &str, today: NaiveDate, config: &Config, staff: &Staff) -> Result<Loan, LibraryError> {
+    staff.require(Permission::Circulation)?;
+    let mut loans = load_loans(config)?;
+    let loan = loans.iter_mut()
+        .find(|l| l.is_open() && l.is_for(borrower) && collation::same(&l.book_name, book_name))
+        .ok_or_else(|| LibraryError::NotFound(t!("error.no_loan", patron = borrower.trim(), book = book_name.trim())))?;
+
+    // the loan period depends on the item type and the patron category
+    let category = patron::category_of(borrower)?;
+    let loan_days = config.loan_days(&item_type_of(&loan.book_name)?, &category);
+    let reserved = hold::is_reserved_by_other(&hold::load_holds()?, &loan.book_name, borrower);
+    let before = json!(loan);
+    loan::renew(loan, loan_days, config.max_renewals(), reserved, today).map_err(LibraryError::RenewRefused)?;
+    let loan = loan.clone();
+    loan::save_loans(&loans)?;
+    let record = format!("loan {} to {}", loan.book_name, loan.borrower);
+    audited(staff, "renew", &record, Some(before), Some(json!(loan)))?;
+    Ok(loan)
+}
+
+fn check_patron(patron: &Patron) -> Result<Patron, LibraryError> {
+    check_text("error.field.name", &patron.name, true)?;
+    check_text("error.field.category", &patron.category, false)?;
+    check_text("error.field.email", &patron.email, false)?;
+    check_text("error.field.card", &patron.card, false)?;
+    if !patron.email.trim().is_empty() && !patron.email.contains('@') {
+        return Err(LibraryError::Invalid(t!("error.email", email = patron.email.trim())));
+    }
+    Ok(Patron {
+        name: patron.name.trim().to_string(),
+        category: match patron.category.trim() {
+            "" => patron::DEFAULT_CATEGORY.to_string(),
+            c => c.to_lowercase()
+        },
+        email: patron.email.trim().to_string(),
+        notify: patron.notify,
+        card: barcode::normalize(&patron.card)
+    })
+}
+
+fn check_card(patrons: &[Patron], patron: &Patron) -> Result<(), LibraryError> {
+    match patrons.iter().find(|p| !p.is_named(&patron.name) && !patron.card.is_empty() && p.card == patron.card) {
+        Some(holder) => Err(LibraryError::Conflict(t!("error.card_taken", card = patron.card, patron = holder.name))),
+        None => Ok(())
+    }
+}
+
+pub fn register_patron(patron: &Patron, staff: &Staff) -> Result<Patron, LibraryError> {
+    staff.require(Permission::Patrons)?;
+    let mut patron = check_patron(patron)?;
+    let patrons = patron::load_patrons()?;
+    if patrons.iter().any(|p| p.is_named(&patron.name)) {
+        return Err(LibraryError::Conflict(t!("error.registered", patron = patron.name)));
+    }
+    check_card(&patrons, &patron)?;
+    // a new card unless the patron brought a printed one
+    if patron.card.is_empty() {
+        patron.card = barcode::next(barcode::CARD_PREFIX, patrons.iter().map(|p| p.card.as_str()));
+    }
+    patron::register(&patron)?;
+    audited(staff, "register patron", &format!("patron {}", patron.name), None, Some(json!(patron)))?;
+    Ok(patron)
+}
+
+// new category, e-mail or notify setting of a registered patron, the name is kept
+pub fn update_patron(name: &str, patron: &Patron, staff: &Staff) -> Result<Patron, LibraryError> {
+    staff.require(Permission::Patrons)?;
+    let mut patrons = patron::load_patrons()?;
+    let index = patrons.iter()
+        .position(|p| p.is_named(name))
+        .ok_or_else(|| LibraryError::NotFound(t!("patrons.not_registered", patron = name.trim())))?;
+    let mut updated = check_patron(&Patron { name: patrons[index].name.clone(), ..patron.clone() })?;
+    if updated.card.is_empty() {
+        updated.card = patrons[index].card.clone();
+    }
+    check_card(&patrons, &updated)?;
+    let before = json!(patrons[index]);
+    patrons[index] = updated.clone();
+    patron::save_patrons(&patrons)?;
+    audited(staff, "edit patron", &format!("patron {}", updated.name), Some(before), Some(json!(updated)))?;
+    Ok(updated)
+}
+
+// patrons with books on loan or money owed stay registered
+pub fn delete_patron(name: &str, config: &Config, staff: &Staff) -> Result<(), LibraryError> {
+    staff.require(Permission::Patrons)?;
+    let mut patrons = patron::load_patrons()?;
+    let registered = patrons.iter()
+        .find(|p| p.is_named(name))
+        .cloned()
+        .ok_or_else(|| LibraryError::NotFound(t!("patrons.not_registered", patron = name.trim())))?;
+    if load_loans(config)?.iter().any(|l| l.is_open() && l.is_for(name)) {
+        return Err(LibraryError::Conflict(t!("error.has_loans", patron = name.trim())));
+    }
+    let balance = ledger::balance(name)?;
+    if balance != 0 {
+        return Err(LibraryError::Conflict(t!("error.has_balance", patron = name.trim(), balance = ledger::format_money(balance))));
+    }
+    patrons.retain(|p| !p.is_named(name));
+    patron::save_patrons(&patrons)?;
+    audited(staff, "delete patron", &format!("patron {}", registered.name), Some(json!(registered)), None)?;
+    Ok(())
+}
+
+// join the hold queue of a title where every copy is on loan, returns the queue position
+pub fn place_hold(book_name: &str, patron: &str, today: NaiveDate, staff: &Staff) -> Result<usize, LibraryError> {
+    staff.require(Permission::Circulation)?;
+    check_text("error.field.patron", patron, true)?;
+    let data = read_store()?;
+    let books = crate::to_book_list(&data)?;
+    let copies: Vec<&Book> = books.iter().filter(|b| collation::same(b.name, book_name)).collect();
+    if copies.is_empty() {
+        return Err(LibraryError::NotFound(t!("error.no_title", book = book_name.trim())));
+    }
+    // a copy on the hold shelf for another patron is no more available than one on loan
+    let holds = hold::load_holds()?;
+    let on_shelf = copies.iter().filter(|b| !b.borrowed).count();
+    if on_shelf > hold::set_aside_for_others(&holds, book_name, patron) {
+        return Err(LibraryError::Conflict(t!("error.available", book = book_name.trim())));
+    }
+    if holds.iter().any(|h| h.is_for(book_name) && h.is_by(patron)) {
+        return Err(LibraryError::Conflict(t!("error.already_held", patron = patron.trim(), book = book_name.trim())));
+    }
+    let placed = Hold::new(copies[0].name, patron, today);
+    hold::place(&placed)?;
+    audited(staff, "place hold", &hold_record(&placed), None, Some(json!(placed)))?;
+    Ok(holds.iter().filter(|h| h.is_for(book_name)).count() + 1)
+}
+
+// "hold Dune for Kim Loo Uii"
+fn hold_record(hold: &Hold) -> String {
+    format!("hold {} for {}", hold.book_name, hold.patron)
+}
+
+pub fn cancel_hold(book_name: &str, patron: &str, staff: &Staff) -> Result<(), LibraryError> {
+    staff.require(Permission::Circulation)?;
+    let mut holds = hold::load_holds()?;
+    let cancelled = holds.iter()
+        .find(|h| h.is_for(book_name) && h.is_by(patron))
+        .cloned()
+        .ok_or_else(|| LibraryError::NotFound(t!("error.no_hold", patron = patron.trim(), book = book_name.trim())))?;
+    holds.retain(|h| !(h.is_for(book_name) && h.is_by(patron)));
+    hold::save_holds(&holds)?;
+    audited(staff, "cancel hold", &hold_record(&cancelled), Some(json!(cancelled)), None)?;
+    Ok(())
+}
+
+// payments and waivers cannot exceed what is owed, refunds cannot exceed the credit,
+// returns the new balance
+pub fn record_account_entry(patron: &str, kind: EntryKind, amount: i64, note: &str, today: NaiveDate,
+    staff: &Staff) -> Result<i64, LibraryError> {
+    staff.require(match kind {
+        EntryKind::Payment => Permission::Payments,
+        _ => Permission::Waivers
+    })?;
+    check_text("error.field.patron", patron, true)?;
+    check_text("error.field.note", note, false)?;
+    if kind == EntryKind::Fine {
+        return Err(LibraryError::Invalid(t!("error.fine_charged")));
+    }
+    if amount <= 0 {
+        return Err(LibraryError::Invalid(t!("error.amount")));
+    }
+    let balance = ledger::balance(patron)?;
+    let limit = match kind {
+        EntryKind::Refund => -balance,
+        _ => balance
+    };
+    if amount > limit {
+        return Err(LibraryError::Conflict(t!("error.amount_over", amount = ledger::format_money(amount),
+            limit = ledger::format_money(limit.max(0)), kind = t!(&format!("ledger.{}", kind)))));
+    }
+    let entry = LedgerEntry::new(patron, today, kind, amount, note.trim());
+    ledger::record(&entry)?;
+    let new_balance = balance + kind.sign() * amount;
+    audited(staff, &kind.to_string(), &format!("account {}", entry.patron),
+        Some(json!({ "balance": balance })), Some(json!({ "entry": entry, "balance": new_balance })))?;
+    Ok(new_balance)
+}
+
+// run a test against its own data files, the data files are found in the working
+// directory so tests that use them take turns
+#[cfg(test)]
+pub fn in_data_dir<T>(name: &str, files: &[(&str, &str)], test: impl FnOnce() -> T) -> T {
+    use std::sync::Mutex;
+    static DATA_DIR: Mutex<()> = Mutex::new(());
+    let _turn = DATA_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = std::env::temp_dir().join(format!("library-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (path, data) in files {
+        std::fs::write(dir.join(path), data).unwrap();
+    }
+    let cwd = std::env::current_dir().unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(test));
+    std::env::set_current_dir(cwd).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+    use crate::clock::day;
+
+    const STORE: &str = "Dune,Frank Herbert,1965,true,2024-01-01,book,,I0000001\n";
+
+    #[test]
+    fn titles_are_matched_whatever_their_case_to_renew() {
+        let loans = "Kim,Dune,2024-03-01,2024-03-15,0,,0,Lee,,I0000001\n";
+        let staff = Staff::without_password("Lee", Role::Librarian);
+        let loan = in_data_dir("renew-collation", &[("librarystore", STORE), ("borrower", loans), ("patrons", "Kim\n")], || {
+            renew("kim", "  DUNE ", day(2024, 3, 10), &Config::default(), &staff)
+        }).unwrap();
+        assert_eq!((loan.book_name.as_str(), loan.renewals), ("Dune", 1));
+        assert_eq!(loan.due_date, day(2024, 3, 29));
+    }
+
+    #[test]
+    fn titles_are_matched_whatever_their_case_to_hold() {
+        let staff = Staff::without_password("Lee", Role::Librarian);
+        let (position, holds) = in_data_dir("hold-collation", &[("librarystore", STORE), ("patrons", "Ada\n")], || {
+            (place_hold("dune", "Ada", day(2024, 3, 10), &staff), hold::load_holds())
+        });
+        assert_eq!(position.unwrap(), 1);
+        assert_eq!(holds.unwrap()[0].book_name, "Dune");
+    }
+}
@@ -0,0 +1,233 @@
+use std::io;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use chrono::{Duration, NaiveDate};
+use crate::collation;
+use crate::i18n::t;
+use crate::library::{self, LibraryError};
+use crate::staging::{self, DataFile};
+
+/**
+ * Every loan ever made, one per line in the `borrower` file:
+ *
+ *   borrower,book name,issue date,due date,renewals,return date,fine,clerk,returned by,barcode
+ *
+ * A loan is open while its return date is empty. Returned loans are kept
+ * as history instead of being removed from the file.
+ * Lines written before due dates existed only have the first three columns,
+ * their due date is the issue date plus the default loan period.
+ * The barcode of the copy is empty for loans made before copies had one.
+ */
+const LOAN_PATH: &str = "borrower";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Loan {
+    pub borrower: String,
+    pub book_name: String,
+    pub issue_date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub renewals: i64,
+    pub return_date: Option<NaiveDate>,
+    pub fine: i64,
+    pub clerk: String,
+    pub returned_by: String,
+    #[serde(default)]
+    pub barcode: String
+}
+
+impl Loan {
+    pub fn new(borrower: &str, book_name: &str, issue_date: NaiveDate, loan_days: i64, clerk: &str) -> Loan {
+        Loan {
+            borrower: borrower.trim().to_string(),
+            book_name: book_name.trim().to_string(),
+            issue_date,
+            due_date: issue_date + Duration::days(loan_days),
+            renewals: 0,
+            return_date: None,
+            fine: 0,
+            clerk: clerk.trim().to_string(),
+            returned_by: String::new(),
+            barcode: String::new()
+        }
+    }
+
+    pub fn is_for(&self, borrower: &str) -> bool {
+        collation::same(&self.borrower, borrower)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.return_date.is_none()
+    }
+
+    // days late counted up to the return date for returned loans
+    pub fn days_overdue(&self, today: NaiveDate) -> i64 {
+        let until = self.return_date.unwrap_or(today);
+        until.signed_duration_since(self.due_date).num_days().max(0)
+    }
+
+    pub fn close(&mut self, return_date: NaiveDate, fine: i64, returned_by: &str) {
+        self.return_date = Some(return_date);
+        self.fine = fine;
+        self.returned_by = returned_by.trim().to_string();
+    }
+
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.days_overdue(today) > 0
+    }
+}
+
+impl fmt::Display for Loan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{},{},{},{},{},{},{}", 
+            self.borrower, 
+            self.book_name, 
+            self.issue_date, 
+            self.due_date, 
+            self.renewals,
+            self.return_date.map(|d| d.to_string()).unwrap_or_default(),
+            self.fine,
+            self.clerk,
+            self.returned_by,
+            self.barcode)
+    }
+}
+
+#[derive(Debug)]
+pub enum RenewError {
+    MaxRenewals(i64),
+    Overdue(i64),
+    Reserved
+}
+
+impl fmt::Display for RenewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenewError::MaxRenewals(max) => write!(f, "{}", t!("renew.max_renewals", max = max)),
+            RenewError::Overdue(days) => write!(f, "{}", t!("renew.overdue", days = days)),
+            RenewError::Reserved => write!(f, "{}", t!("renew.reserved"))
+        }
+    }
+}
+
+// extend the due date by another loan period, counted from the current due date
+pub fn renew(loan: &mut Loan, loan_days: i64, max_renewals: i64, reserved: bool, today: NaiveDate) -> Result<NaiveDate, RenewError> {
+    if reserved {
+        return Err(RenewError::Reserved);
+    }
+    if loan.is_overdue(today) {
+        return Err(RenewError::Overdue(loan.days_overdue(today)));
+    }
+    if loan.renewals >= max_renewals {
+        return Err(RenewError::MaxRenewals(max_renewals));
+    }
+    loan.due_date += Duration::days(loan_days);
+    loan.renewals += 1;
+    Ok(loan.due_date)
+}
+
+fn parse_loan(line: &str, default_loan_days: i64) -> Option<Loan> {
+    let slice_data: Vec<&str> = line.split(',').collect();
+    if slice_data.len() < 3 {
+        return None;
+    }
+    let issue_date = NaiveDate::parse_from_str(slice_data[2].trim(), "%Y-%m-%d").ok()?;
+    let mut loan = Loan::new(slice_data[0], slice_data[1], issue_date, default_loan_days, 
+        slice_data.get(7).unwrap_or(&""));
+    if let Some(due) = slice_data.get(3) {
+        loan.due_date = NaiveDate::parse_from_str(due.trim(), "%Y-%m-%d").ok()?;
+    }
+    loan.renewals = slice_data.get(4).and_then(|r| r.trim().parse().ok()).unwrap_or_default();
+    loan.return_date = slice_data.get(5)
+        .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok());
+    loan.fine = slice_data.get(6).and_then(|r| r.trim().parse().ok()).unwrap_or_default();
+    loan.returned_by = slice_data.get(8).map(|r| r.trim().to_string()).unwrap_or_default();
+    loan.barcode = slice_data.get(9).map(|b| b.trim().to_string()).unwrap_or_default();
+    Some(loan)
+}
+
+// every loan, open and returned, in the order they were issued
+pub fn load_loans(default_loan_days: i64) -> Result<Vec<Loan>, LibraryError> {
+    library::read_records(LOAN_PATH, |line| parse_loan(line, default_loan_days))
+}
+
+// the whole `borrower` file, for writes staged together with other files
+pub fn data_file(loans: &[Loan]) -> DataFile {
+    DataFile::new(LOAN_PATH, loans)
+}
+
+pub fn save_loans(loans: &[Loan]) -> io::Result<()> {
+    staging::write_all(&[data_file(loans)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    #[test]
+    fn due_date_is_the_loan_period_after_the_issue_date() {
+        let today = day(2024, 2, 20);
+        let loan = Loan::new("Kim", "Golang", today, 14, "Lee");
+        assert_eq!(loan.due_date, day(2024, 3, 5));
+        assert!(loan.is_open());
+    }
+
+    #[test]
+    fn overdue_days_count_from_the_due_date() {
+        let loan = Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee");
+        assert_eq!(loan.days_overdue(day(2024, 1, 15)), 0);
+        assert!(!loan.is_overdue(day(2024, 1, 15)));
+        assert_eq!(loan.days_overdue(day(2024, 1, 16)), 1);
+        assert_eq!(loan.days_overdue(day(2024, 2, 14)), 30);
+    }
+
+    #[test]
+    fn returned_loans_stop_counting_on_the_return_date() {
+        let mut loan = Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee");
+        loan.close(day(2024, 1, 20), 250, "Ada");
+        assert_eq!(loan.days_overdue(day(2024, 6, 1)), 5);
+        assert_eq!(loan.fine, 250);
+        assert!(!loan.is_open());
+    }
+
+    #[test]
+    fn renewal_extends_from_the_due_date_up_to_the_limit() {
+        let today = day(2024, 1, 10);
+        let mut loan = Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee");
+        assert_eq!(renew(&mut loan, 14, 2, false, today).unwrap(), day(2024, 1, 29));
+        assert_eq!(renew(&mut loan, 14, 2, false, today).unwrap(), day(2024, 2, 12));
+        assert!(matches!(renew(&mut loan, 14, 2, false, today), Err(RenewError::MaxRenewals(2))));
+        assert_eq!(loan.renewals, 2);
+        assert_eq!(loan.due_date, day(2024, 2, 12));
+    }
+
+    #[test]
+    fn overdue_or_reserved_loans_are_not_renewed() {
+        let mut loan = Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee");
+        assert!(matches!(renew(&mut loan, 14, 2, false, day(2024, 1, 18)), Err(RenewError::Overdue(3))));
+        assert!(matches!(renew(&mut loan, 14, 2, true, day(2024, 1, 10)), Err(RenewError::Reserved)));
+        assert_eq!(loan.renewals, 0);
+    }
+
+    #[test]
+    fn old_lines_get_a_due_date_from_the_default_period() {
+        let loan = parse_loan("Kim,Golang,2024-01-01", 21).unwrap();
+        assert_eq!(loan.due_date, day(2024, 1, 22));
+        assert!(loan.is_open());
+        let line = Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee").to_string();
+        assert_eq!(parse_loan(line.trim(), 21).unwrap().due_date, day(2024, 1, 15));
+    }
+
+    #[test]
+    fn a_damaged_line_is_reported_instead_of_dropped() {
+        let path = std::env::temp_dir().join(format!("library-loans-{}", std::process::id()));
+        std::fs::write(&path, "Kim,Golang,2024-01-01\n\nLee,Dune,2024-02-30\n").unwrap();
+        let loaded = library::read_records(path.to_str().unwrap(), |line| parse_loan(line, 14));
+        std::fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(LibraryError::Damaged(what)) => assert!(what.starts_with("line 3 of")),
+            other => panic!("expected a damaged line, got {:?}", other.map(|l| l.len()))
+        }
+        assert!(library::read_records("no-such-loans-file", |line| parse_loan(line, 14)).unwrap().is_empty());
+    }
+}
@@ -1,10 +1,55 @@
 use std::io;
-use std::fs::{File, OpenOptions};
+use std::io::IsTerminal;
+use std::fs::File;
 use std::path::Path;
 use std::io::prelude::*;
-use chrono::{NaiveDate, Utc, DateTime};
-use chrono::format::ParseError;
+use chrono::NaiveDate;
 use std::process;
+use std::fmt;
+use serde::Serialize;
+
+mod audit;
+mod auth;
+mod backup;
+mod barcode;
+mod citation;
+mod cli;
+mod clock;
+mod collation;
+mod config;
+mod eligibility;
+mod hold;
+mod i18n;
+mod import;
+mod labels;
+mod ledger;
+mod library;
+mod loan;
+mod marc;
+mod notify;
+mod overdue;
+mod patron;
+mod receipt;
+mod server;
+mod shell;
+mod smtp;
+mod staging;
+mod stats;
+mod template;
+mod trash;
+mod tui;
+mod undo;
+
+use auth::{Permission, Role, Staff};
+use clock::Clock;
+use config::Config;
+use i18n::t;
+use library::LibraryError;
+use ledger::EntryKind;
+use loan::Loan;
+use patron::Patron;
+use receipt::Receipt;
+use shell::{Command, Shell};
 
 /**
  * TODO:
@@ -13,32 +58,63 @@ use std::process;
  * [y] create new book entry
  * [y] issue book to borrower
  * [y] collect book from borrower
- * [y] check late payment is needed during book return (14 days consider late, fix rate $0.50)
+ * [y] check late payment is needed during book return (fine per day past the due date, see libraryconfig)
  * [y] delete book
+ * [y] due date from loan period and renew a book
+ * [y] hold queue for borrowed books
+ * [y] borrowing limits, refuse loans over the balance limit or with overdue books
+ * [y] keep returned loans as history of a book or a patron
+ * [y] overdue report and reminder notices
+ * [y] e-mail reminders for due soon, overdue and hold ready
+ * [y] circulation statistics, also as `report` subcommand with json/csv output
+ * [y] bulk import of books from csv/json with `import` subcommand
+ * [y] backup and restore of the whole library with `backup` and `restore` subcommands
+ * [y] MARC 21 and MARCXML catalogue records, `import` and `export` subcommands
+ * [y] BibTeX, RIS and Dublin Core citations from the search result or `export`
+ * [y] JSON REST API with `serve`, over the same library operations as the menu
+ * [y] staff accounts with admin, librarian and kiosk roles, login for the menu and API tokens
+ * [y] audit trail of every change, queried with the `audit` subcommand
+ * [y] withdrawn books go to the trash with a reason, restore or purge them later
+ * [y] undo and redo the changes of a menu session
+ * [y] full-screen terminal UI with `tui`, a filterable book table and forms
+ * [y] menu command line with history, tab completion and typed commands like `borrow`
+ * [y] barcodes for copies and library cards, scan checkout and checkin at the desk
+ * [y] printable Code 128 / EAN-13 labels and library cards with `labels`
+ * [y] loan and return receipts as text for thermal printers or HTML, `receipt` prints one again
+ * [y] messages in English, Malay and Chinese from catalogues in `locales`, picked by config or LANG
+ * [y] search ignores case, accents and full-width forms, titles and authors sort by collation
  * 
  * using file to store all the related data
  * display a management menu
  */
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 struct Book<'b> {
     name: &'b str,
     author: &'b str,
     year_published: u32,
     borrowed: bool,
-    issue_date: NaiveDate
+    issue_date: NaiveDate,
+    item_type: &'b str,
+    isbn: &'b str,
+    barcode: &'b str
 }
 
-impl<'b> Book<'b> {
-    pub fn to_string(&self) -> String {
-        format!("{}", format!("{},{},{},{},{}\n", 
-        &self.name.trim(), 
-        &self.author.trim(), 
-        &self.year_published.to_string(), 
-        &self.borrowed, 
-        &self.issue_date.to_string()))
+impl<'b> fmt::Display for Book<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{},{},{},{},{}", 
+        self.name.trim(), 
+        self.author.trim(), 
+        self.year_published, 
+        self.borrowed, 
+        self.issue_date,
+        self.item_type,
+        self.isbn,
+        self.barcode)
     }
 }
 
+const DEFAULT_ITEM_TYPE: &str = "book";
+
 // struct Borrower<'a> {
 //     name: &'a str,
 //     book_name: &'a str, 
@@ -55,537 +131,2486 @@ impl<'b> Book<'b> {
 // }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match config::load() {
+        Err(err) => {
+            eprintln!("{}", t!("cli.config_unreadable", error = err));
+            process::exit(1);
+        },
+        Ok(config) => config
+    };
+    if let Err(err) = i18n::init(&config) {
+        eprintln!("{}", t!("cli.catalogue_unreadable", error = err));
+        process::exit(1);
+    }
+    let clock = match clock::from_args(&args) {
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+        Ok(clock) => clock
+    };
+
+    match cli::positionals(&args).first() {
+        None => (),
+        Some(&"report") => process::exit(run_report(&args, clock.as_ref(), &config)),
+        Some(&"import") => process::exit(authorized(&args, Permission::Catalogue)
+            .map_or(1, |staff| run_import(&args, clock.as_ref(), &staff))),
+        Some(&"export") => process::exit(run_export(&args)),
+        Some(&"backup") => process::exit(authorized(&args, Permission::Administration)
+            .map_or(1, |_| run_backup(&args, clock.as_ref(), &config))),
+        Some(&"restore") => process::exit(authorized(&args, Permission::Administration)
+            .map_or(1, |staff| run_restore(&args, &config, &staff))),
+        Some(&"serve") => process::exit(run_serve(&args, clock.as_ref(), &config)),
+        Some(&"staff") => process::exit(run_staff(&args, clock.as_ref())),
+        Some(&"audit") => process::exit(authorized(&args, Permission::Administration)
+            .map_or(1, |_| run_audit(&args))),
+        Some(&"trash") => process::exit(sign_in(&args, 1).map_or(1, |staff| run_trash(&args, &staff))),
+        Some(&"desk") => process::exit(authorized(&args, Permission::Circulation)
+            .map_or(1, |staff| run_desk(&args, clock.as_ref(), &config, &staff))),
+        Some(&"barcodes") => process::exit(sign_in(&args, 1).map_or(1, |staff| run_barcodes(&args, &staff))),
+        Some(&"labels") => process::exit(run_labels(&args)),
+        Some(&"receipt") => process::exit(run_receipt(&args, clock.as_ref(), &config)),
+        Some(&"tui") => process::exit(sign_in(&args, 1).map_or(1, |staff| run_tui(clock.as_ref(), &config, &staff))),
+        Some(command) => {
+            eprintln!("{}", t!("cli.unknown_command", command = command));
+            process::exit(1);
+        }
+    }
+
+    let staff = match shell_sign_in(&args) {
+        None => process::exit(1),
+        Some(staff) => staff
+    };
+    println!("#[ {} ]#\n", t!("session.signed_in", name = staff.name, role = staff.role));
+
+    let mut history = undo::History::new(config.undo_depth());
+    let mut shell = Shell::new(config.history_size());
     loop {
-        match menu() {
-            Ok(num) => {
-                match num {
-                    0 => process::exit(0),
-                    1 => list_all_book(),
-                    2 => search_a_book(),
-                    3 => create_a_book(),
-                    4 => borrow_a_book(),
-                    5 => return_a_book(),
-                    6 => delete_a_book(),
-                    _ => println!("Please enter from the option provided")
+        match menu(&mut shell) {
+            Ok((line, command)) => {
+                if let Some(Err(err)) = command_permission(&command).map(|p| staff.require(p)) {
+                    println!("{}\n", capitalize(&err.to_string()));
+                    continue;
+                }
+                // the files an option changes are kept so it can be undone
+                let pending = match changes_files(&command) {
+                    false => None,
+                    true => history.begin()
+                        .map_err(|err| println!("{}", t!("undo.not_kept", error = err)))
+                        .ok()
+                };
+                let label = match command {
+                    Command::Option(num) => t!("menu.option_label", num = num),
+                    _ => line
+                };
+                match &command {
+                    Command::Option(num) => match num {
+                        0 => process::exit(0),
+                        1 => list_all_book(),
+                        2 => search_a_book(),
+                        3 => create_a_book(clock.as_ref(), &staff),
+                        4 => borrow_a_book(clock.as_ref(), &config, &staff),
+                        5 => return_a_book(clock.as_ref(), &config, &staff),
+                        6 => withdraw_a_book(clock.as_ref(), &staff),
+                        7 => patron_account(clock.as_ref(), &staff),
+                        8 => renew_a_book(clock.as_ref(), &config, &staff),
+                        9 => register_patron(&staff),
+                        10 => view_holds(clock.as_ref(), &config),
+                        11 => loan_history(clock.as_ref(), &config),
+                        12 => overdue_report(clock.as_ref(), &config),
+                        13 => send_reminders(clock.as_ref(), &config, &staff),
+                        14 => circulation_statistics(clock.as_ref(), &config),
+                        15 => view_trash(&staff),
+                        16 => undo_actions(&mut history, &staff, false),
+                        17 => undo_actions(&mut history, &staff, true),
+                        18 => checkout_desk(clock.as_ref(), &config, &staff),
+                        19 => checkin_desk(clock.as_ref(), &config, &staff),
+                        _ => println!("{}", t!("menu.invalid_option"))
+                    },
+                    Command::Help => shell::print_help(),
+                    Command::Search(text) => show_search_result(text),
+                    circulation => if let Err(err) = run_command(circulation, clock.as_ref(), &config, &staff) {
+                        println!("{}\n", capitalize(&err));
+                    }
+                }
+                if let Some(pending) = pending {
+                    if let Err(err) = history.record(pending, &label) {
+                        println!("{}", t!("undo.not_kept", error = err));
+                    }
                 }
             },            
-            Err(_) => {
-                println!("You have entered an invalid input!!");
+            Err(err) => {
+                println!("{}", capitalize(&err));
                 continue
             }
         };        
     }
 }
 
-fn menu() -> Result<u8, i8> {
-    println!("#----------------------------#");
-    println!("#  Welcome to Rusty Library  #"); 
-    println!("#----------------------------#");
-    println!("#  Please input your option  #");
-    println!("#  [1] list all book         #");
-    println!("#  [2] search a book         #");
-    println!("#  [3] create a book         #");
-    println!("#  [4] borrow a book         #");
-    println!("#  [5] return a book         #");
-    println!("#  [6] delete a book         #");
-    println!("#  [0] exit program          #");
-    println!("#----------------------------#");
+// menu options in the order they are listed, exit last
+const MENU_OPTIONS: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 0];
+
+// menu options that change the data files
+const UNDOABLE_OPTIONS: &[u8] = &[3, 4, 5, 6, 7, 8, 9, 13, 15, 18, 19];
+
+// permission a menu option needs before it asks anything, the library operations check it again
+fn menu_permission(option: u8) -> Option<Permission> {
+    match option {
+        3 | 6 => Some(Permission::Catalogue),
+        4 | 5 | 8 | 13 | 18 | 19 => Some(Permission::Circulation),
+        9 => Some(Permission::Patrons),
+        _ => None
+    }
+}
+
+fn command_permission(command: &Command) -> Option<Permission> {
+    match command {
+        Command::Option(num) => menu_permission(*num),
+        Command::Help | Command::Search(_) => None,
+        Command::Borrow { .. } | Command::Return { .. } | Command::Renew { .. } | Command::Hold { .. } => Some(Permission::Circulation)
+    }
+}
+
+fn changes_files(command: &Command) -> bool {
+    match command {
+        Command::Option(num) => UNDOABLE_OPTIONS.contains(num),
+        Command::Help | Command::Search(_) => false,
+        Command::Borrow { .. } | Command::Return { .. } | Command::Renew { .. } | Command::Hold { .. } => true
+    }
+}
+
+// hidden on a terminal, a plain line when the input is piped in, the prompt
+// goes to stderr so it never mixes with the output of a subcommand
+fn read_password(prompt: &str) -> io::Result<String> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(format!("{} ", prompt));
+    }
+    eprintln!("{}", prompt);
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read_new_password() -> Option<String> {
+    let password = read_password(&t!("prompt.new_password")).ok()?;
+    let repeated = read_password(&t!("prompt.new_password_again")).ok()?;
+    if password != repeated {
+        println!("{}", t!("staff.passwords_differ"));
+        return None;
+    }
+    Some(password)
+}
+
+// staff name from `--user NAME` or asked for, then the password
+fn sign_in(args: &[String], attempts: usize) -> Option<Staff> {
+    for _ in 0..attempts {
+        let name = match cli::option(args, "--user") {
+            Some(name) => name,
+            None => {
+                println!("{}", t!("prompt.staff_name"));
+                let mut name = String::new();
+                if let Err(err) = io::stdin().read_line(&mut name) {
+                    println!("{}", t!("staff.name_unreadable", error = err));
+                    return None;
+                }
+                name.trim().to_string()
+            }
+        };
+        let password = match read_password(&t!("prompt.password")) {
+            Err(err) => {
+                println!("{}", t!("staff.password_unreadable", error = err));
+                return None;
+            },
+            Ok(password) => password
+        };
+        match auth::login(&name, &password) {
+            Err(err) => {
+                println!("{}", t!("staff.unreadable", error = err));
+                return None;
+            },
+            Ok(Some(staff)) => return Some(staff),
+            Ok(None) => println!("{}", t!("staff.wrong_password"))
+        }
+    }
+    None
+}
+
+// the menu asks for the first admin account when there is none yet
+fn shell_sign_in(args: &[String]) -> Option<Staff> {
+    match auth::load_staff() {
+        Err(err) => {
+            println!("{}", t!("staff.unreadable", error = err));
+            None
+        },
+        Ok(staff) if staff.is_empty() => {
+            println!("{}", t!("staff.first_admin"));
+            println!("{}", t!("prompt.staff_name"));
+            let mut name = String::new();
+            if let Err(err) = io::stdin().read_line(&mut name) {
+                println!("{}", t!("staff.name_unreadable", error = err));
+                return None;
+            }
+            let password = read_new_password()?;
+            match auth::add_staff(&name, Role::Admin, &password) {
+                Err(err) => {
+                    println!("{}", t!("staff.not_created", error = err));
+                    None
+                },
+                Ok(staff) => Some(staff)
+            }
+        },
+        Ok(_) => sign_in(args, 3)
+    }
+}
+
+// subcommands that change the library sign in with `--user NAME` first
+fn authorized(args: &[String], permission: Permission) -> Option<Staff> {
+    let staff = sign_in(args, 1)?;
+    match staff.require(permission) {
+        Err(err) => {
+            eprintln!("{}", capitalize(&err.to_string()));
+            None
+        },
+        Ok(_) => Some(staff)
+    }
+}
+
+fn menu(shell: &mut Shell) -> Result<(String, Command), String> {
+    let mut lines = vec![t!("menu.choose")];
+    lines.extend(MENU_OPTIONS.iter().map(|option| format!("[{}] {}", option, t!(&format!("menu.option.{}", option)))));
+    lines.push(t!("menu.type_help"));
+    let title = t!("menu.title");
+    // as wide as the longest line of the language
+    let columns = lines.iter().chain([&title]).map(|line| i18n::width(line)).max().unwrap_or(0) + 2;
+    let rule = format!("#{}#", "-".repeat(columns + 2));
+    println!("{}", rule);
+    println!("#  {}#", i18n::pad(&title, columns));
+    println!("{}", rule);
+    for line in &lines {
+        println!("#  {}#", i18n::pad(line, columns));
+    }
+    println!("{}", rule);
     
-    // read user input
-    let mut inp = String::new();
-    io::stdin()
-        .read_line(&mut inp)
-        .expect("Failed to read user input");
-    match inp.trim().parse() {
-        Ok (num) => Ok(num),
-        Err(_) => Err(-1)
-    }    
+    // read user input, the end of input (e.g. piped script) exits like [0]
+    let line = match shell.read_line("> ") {
+        Err(err) => return Err(t!("menu.input_unreadable", error = err)),
+        Ok(None) => return Ok((String::new(), Command::Option(0))),
+        Ok(Some(line)) => line.trim().to_string()
+    };
+    let command = shell::parse(&line)?;
+    Ok((line, command))
 }
 
 // list all book
 fn list_all_book() {
-    let data_path = Path::new("librarystore");
-    let mut file = match File::open(&data_path) {
-        Err(why) => panic!("No library data store, {}", why),
-        Ok(file) => file
+    let data = match library::read_store() {
+        Err(why) => {
+            println!("{}", t!("store.unreadable", error = why));
+            return;
+        },
+        Ok(data) => data
     };
-
-    let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Err(why) => println!("Error when reading file, {}", why),
-        Ok(_) => {
-            let books: Vec<Book> = match to_book_list(&data) {
-                Err(_) => vec![],
-                Ok(b) => b
-            };
-            println!("\n#----------------------------#");
-            println!("#  Rusty Library Book List   #"); 
-            println!("#----------------------------#");            
+    match to_book_list(&data) {
+        Err(why) => println!("{}", t!("store.unreadable", error = why)),
+        Ok(books) => {
+            print_banner(&t!("banner.book_list"));
             for book in books {
-                println!("Book Name      : {}", book.name);
-                println!("Book Author    : {}", book.author);
-                println!("Published Year : {}", book.year_published);
-                println!("Borrow Status  : {}", book.borrowed);
-                println!("Issue on       : {}", book.issue_date);
-                println!("--------------------------------------------");
+                print_book(&book);
             }            
             println!();            
         }
     }
 }
 
-// list all book
-fn to_book_list(data: &str) -> Result<Vec<Book>, ParseError>{
+// a banner as wide as its title, e.g. "#  Rusty Library Book List  #"
+fn print_banner(title: &str) {
+    let rule = format!("#{}#", "-".repeat(i18n::width(title) + 4));
+    println!("\n{}", rule);
+    println!("#  {}  #", title);
+    println!("{}", rule);
+}
+
+// "Book Name      : Dune", the labels line up in every language
+fn print_field(label_key: &str, value: impl fmt::Display) {
+    println!("{} : {}", i18n::pad(&t!(label_key), 14), value);
+}
+
+const RULE: &str = "--------------------------------------------";
+
+fn print_book(book: &Book) {
+    print_field("field.book_name", book.name);
+    print_field("field.author", book.author);
+    print_field("field.published_year", book.year_published);
+    print_field("field.item_type", book.item_type);
+    print_field("field.isbn", book.isbn);
+    print_field("field.barcode", book.barcode);
+    print_field("field.borrow_status", t!(if book.borrowed { "status.borrowed" } else { "status.available" }));
+    print_field("field.issue_date", i18n::date(book.issue_date));
+    println!("{}", RULE);
+}
+
+// every copy in the library store, a line without a name, author and issue date
+// is an error that names the line instead of a book being left out
+fn to_book_list(data: &str) -> Result<Vec<Book<'_>>, LibraryError> {
     let mut books = vec![];
 
-    for line in data.split('\n') {
+    for (number, line) in data.split('\n').enumerate() {
         if line.trim() != "" {
-            let slice_data: Vec<&str> = line.split(',').clone().collect();            
-            let issue_date = NaiveDate::parse_from_str(slice_data[4].trim(), "%Y-%m-%d")?;
+            let damaged = |reason: String| LibraryError::Damaged(t!("error.store_line", line = number + 1, reason = reason));
+            let slice_data: Vec<&str> = line.split(',').collect();
+            if slice_data.len() < 5 {
+                return Err(damaged(t!("error.store_columns", text = line.trim(), count = slice_data.len())));
+            }
+            let issue_date = NaiveDate::parse_from_str(slice_data[4].trim(), "%Y-%m-%d")
+                .map_err(|e| damaged(t!("error.store_date", date = slice_data[4].trim(), error = e)))?;
             books.push(Book {
                 name: slice_data[0].trim(),
                 author: slice_data[1].trim(),
                 year_published: slice_data[2].trim().parse().unwrap_or_default(),            
                 borrowed: slice_data[3].trim().to_string().parse().unwrap_or_default(),
-                issue_date
+                issue_date,
+                item_type: slice_data.get(5)
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or(DEFAULT_ITEM_TYPE),
+                isbn: slice_data.get(6).map(|i| i.trim()).unwrap_or(""),
+                barcode: slice_data.get(7).map(|b| b.trim()).unwrap_or("")
             });
         }
     }
     Ok(books)
 }
 
+// title and author of every book in the library store
+fn load_catalogue() -> Result<Vec<(String, String)>, LibraryError> {
+    let data = library::read_store()?;
+    Ok(to_book_list(&data)?
+        .iter()
+        .map(|b| (b.name.to_string(), b.author.to_string()))
+        .collect())
+}
+
 // search a book
 fn search_a_book() {
-    println!("Please enter your search:");
+    println!("{}", t!("prompt.search"));
     let mut book_name = String::new();
     io::stdin()
         .read_line(&mut book_name)
         .expect("Please enter something to search..");
 
     if book_name.trim() == "" {
-        println!("Please enter something...");
+        println!("{}", t!("input.empty"));
     } else {
-        let data_path = Path::new("librarystore");
-        let mut file = match File::open(&data_path) {
-            Err(why) => panic!("No library data store, {}", why),
-            Ok(file) => file
-        };
-        
-        let mut data = String::new();
-        match file.read_to_string(&mut data) {
-            Err(why) => println!("Error when reading file, {}", why),
-            Ok(_) => {
-                let books: Vec<Book> = match to_book_list(&data) {
-                    Err(_) => vec![],
-                    Ok(b) => b
-                };
-                let search_result: Vec<Book> = books.into_iter()
-                    .filter(|x| x.name.to_uppercase().contains(&book_name.trim().to_uppercase()))
-                    .collect();                
-                println!("\n#-------------------------------#");
-                println!("#  Rusty Library Search Result  #"); 
-                println!("#-------------------------------#");
-                if search_result.len() > 0 {
-                    println!("Result found: {}", search_result.len());
-                    println!("--------------------------------------------");
-                    for (i, book) in search_result.iter().enumerate() {
-                        println!("[{}]", i);
-                        println!("Book Name      : {}", book.name);
-                        println!("Book Author    : {}", book.author);
-                        println!("Published Year : {}", book.year_published);
-                        println!("Borrow Status  : {}", book.borrowed);
-                        println!("Issue on       : {}", book.issue_date);
-                        println!("--------------------------------------------");
-                    }
-                } else {
-                    println!("Nothing is found");
+        show_search_result(&book_name);
+    }
+}
+
+fn show_search_result(book_name: &str) {
+    let data = match library::read_store() {
+        Err(why) => {
+            println!("{}", t!("store.unreadable", error = why));
+            return;
+        },
+        Ok(data) => data
+    };
+    match to_book_list(&data) {
+        Err(why) => println!("{}", t!("store.unreadable", error = why)),
+        Ok(books) => {
+            let search_result: Vec<Book> = search_books(books, book_name);
+            print_banner(&t!("banner.search_result"));
+            if !search_result.is_empty() {
+                println!("{}", t!("result.found", count = search_result.len()));
+                println!("{}", RULE);
+                for (i, book) in search_result.iter().enumerate() {
+                    println!("[{}]", i);
+                    print_book(book);
                 }
-                println!();       
+                cite_search_result(&search_result);
+            } else {
+                println!("{}", t!("result.none"));
+            }
+            println!();       
+        }
+    }
+}
+
+// books whose name contains the search text
+fn search_books<'b>(books: Vec<Book<'b>>, text: &str) -> Vec<Book<'b>> {
+    books.into_iter()
+        .filter(|x| collation::contains(x.name, text))
+        .collect()
+}
+
+// print the search result, or some of it, as citations
+fn cite_search_result(search_result: &[Book]) {
+    println!("{}", t!("cite.prompt_format"));
+    let mut format = String::new();
+    io::stdin()
+        .read_line(&mut format)
+        .expect("Please enter a citation format");
+    if format.trim().is_empty() {
+        return;
+    }
+    let format = match citation::CitationFormat::parse(&format) {
+        Some(format) => format,
+        None => {
+            println!("{}", t!("cite.invalid_format"));
+            return;
+        }
+    };
+
+    println!("{}", t!("cite.prompt_numbers"));
+    let mut numbers = String::new();
+    io::stdin()
+        .read_line(&mut numbers)
+        .expect("Please enter the result numbers");
+    let mut selected = vec![];
+    for number in numbers.split(|c: char| c.is_whitespace() || c == ',').filter(|n| !n.is_empty()) {
+        match number.parse::<usize>().ok().and_then(|i| search_result.get(i)) {
+            Some(book) => selected.push(*book),
+            None => {
+                println!("{}", t!("cite.no_result", number = number));
+                return;
             }
         }
     }
+    if selected.is_empty() {
+        selected = search_result.to_vec();
+    }
+    println!("{}", RULE);
+    print!("{}", citation::write(&backup::titles(&selected), format));
+    println!("{}", RULE);
 }
 
 // create a book
-fn create_a_book() {    
-    println!("Insert new book to library");
+fn create_a_book(clock: &dyn Clock, staff: &Staff) {    
+    println!("{}", t!("create.intro"));
 
-    println!("Book Name :");
+    println!("{}", t!("create.name"));
     let mut book_name = String::new();
     io::stdin()
         .read_line(&mut book_name)
         .expect("Please enter something...");
 
-    println!("Book Author :");
+    println!("{}", t!("create.author"));
     let mut author = String::new();
     io::stdin()
         .read_line(&mut author)
         .expect("Please enter something...");
 
-    println!("Book Published Year [YYYY] :");
+    println!("{}", t!("create.year"));
     let mut published_year = String::new();
     io::stdin()
         .read_line(&mut published_year)
         .expect("Please enter something...");
+    let year_published: u32 = match published_year.trim().parse() {
+        Err(_) => {
+            println!("{}", t!("input.invalid_year"));
+            return;
+        },
+        Ok(year) => year
+    };
 
-    println!("Issue Date [YYYY-MM-DD] :");
+    println!("{}", t!("create.issue_date"));
     let mut issue_date = String::new();
     io::stdin()
         .read_line(&mut issue_date)
         .expect("Please enter something...");
-    
-    let data_path = Path::new("librarystore");
-    let mut file = OpenOptions::new()        
-        .write(true)    
-        .append(true)
-        .open(&data_path)
-        .unwrap();          
-            
-    if let Err(e) = write!(&mut file, "{}", format!("{}", format!("{},{},{},{},{}", 
-        book_name.trim(), author.trim(), published_year.trim(), 0.to_string(), issue_date.to_string()))) {
-        println!("{}", e);
-    } else {
-        println!();
-        println!("#[ New book has been added to library ]#");        
-        println!();
+    let issue_date = match issue_date.trim() {
+        "" => None,
+        date => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Err(_) => {
+                println!("{}", t!("input.invalid_date"));
+                return;
+            },
+            Ok(date) => Some(date)
+        }
+    };
+
+    println!("{}", t!("create.item_type"));
+    let mut item_type = String::new();
+    io::stdin()
+        .read_line(&mut item_type)
+        .expect("Please enter something...");
+
+    println!("{}", t!("create.isbn"));
+    let mut isbn = String::new();
+    io::stdin()
+        .read_line(&mut isbn)
+        .expect("Please enter something...");
+
+    let input = library::BookInput {
+        name: book_name.trim().to_string(),
+        author: author.trim().to_string(),
+        year_published,
+        issue_date,
+        item_type: Some(item_type),
+        isbn: Some(isbn)
+    };
+    match library::add_book(&input, clock.today(), staff) {
+        Err(err) => println!("{}\n", t!("create.failed", error = err)),
+        Ok(_) => {
+            println!();
+            println!("#[ {} ]#", t!("create.done"));        
+            println!();
+        }
     }
 }
 
-// delete a book
-fn delete_a_book() {
-    println!("Please select a book from below:");
-    let data_path = Path::new("librarystore");
-    let mut file = match File::open(&data_path) {
-        Err(why) => panic!("No library data store, {}", why),
-        Ok(file) => file
+// withdraw a book, it goes to the trash with a reason until it is purged
+fn withdraw_a_book(clock: &dyn Clock, staff: &Staff) {
+    println!("{}", t!("withdraw.select"));
+    let data = match library::read_store() {
+        Err(why) => {
+            println!("{}", t!("store.unreadable", error = why));
+            return;
+        },
+        Ok(data) => data
     };
+    let books: Vec<Book> = match to_book_list(&data) {
+        Err(err) => {
+            println!("{}", t!("store.unreadable", error = err));
+            return;
+        },
+        Ok(books) => books
+    };
+    print_banner(&t!("banner.book_list"));
+    for (i, book) in books.iter().enumerate() {
+        println!("[{}]", i);
+        print_book(book);
+    }        
+    println!();            
 
-    let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Err(why) => println!("Error when reading file, {}", why),
-        Ok(_) => {
-            let books: Vec<Book> = match to_book_list(&data) {
-                Err(_) => vec![],
-                Ok(b) => b
-            };
-            println!("\n#----------------------------#");
-            println!("#  Rusty Library Book List   #"); 
-            println!("#----------------------------#");            
-            for (i, book) in books.iter().enumerate() {
-                println!("[{}]", i);
-                println!("Book Name      : {}", book.name);
-                println!("Book Author    : {}", book.author);
-                println!("Published Year : {}", book.year_published);
-                println!("Borrow Status  : {}", book.borrowed);
-                println!("Issue on       : {}", book.issue_date);
-                println!("--------------------------------------------");
-            }        
-            println!();            
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp).unwrap();
+    // a mistyped number must not pick a book
+    let (index, book) = match inp.trim().parse::<usize>().ok().and_then(|n| books.get(n).map(|b| (n, b))) {
+        None => {
+            println!("{}", t!("input.invalid_choice"));
+            return;
+        },
+        Some(found) => found
+    };
 
-            let mut inp = String::new();
-            io::stdin()
-                .read_line(&mut inp).unwrap();
-            let delete_option: usize = inp.trim().parse().unwrap_or_default();
-            let mut updated_file = match File::create(data_path) {
-                Err(why) => panic!("Cannot create new file, {}", why),
-                Ok(file) => file
-            };
-            let mut updated_data = String::new();
+    println!("{}", t!("withdraw.reason", book = book.name));
+    let mut reason = String::new();
+    io::stdin()
+        .read_line(&mut reason)
+        .unwrap();
+    if !confirm(&t!("withdraw.confirm", number = index, book = book.name, author = book.author)) {
+        println!("#[ {} ]#\n", t!("withdraw.cancelled"));
+        return;
+    }
+    match library::withdraw_book(index, &reason, clock.today(), staff) {
+        Err(err) => println!("{}\n", t!("withdraw.failed", error = err)),
+        Ok(_) => println!("#[ {} ]#\n", t!("withdraw.done", book = book.name))
+    }
+}
 
-            for (i, book) in books.iter().enumerate() {
-                if i != delete_option {
-                    updated_data.push_str(&book.to_string());
-                }
-            }
+// undo or redo the last changes of this session, as many as asked for
+fn undo_actions(history: &mut undo::History, staff: &Staff, redo: bool) {
+    let (key, available) = if redo {
+        ("redo", history.redo_count())
+    } else {
+        ("undo", history.undo_count())
+    };
+    if available == 0 {
+        println!("{}\n", t!(&format!("{}.nothing", key)));
+        return;
+    }
+    println!("{}", t!(&format!("{}.how_many", key), available = available));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let count = match inp.trim() {
+        "" => 1,
+        n => match n.parse::<usize>().ok().filter(|n| (1..=available).contains(n)) {
+            None => {
+                println!("{}", t!("input.invalid_count", available = available));
+                return;
+            },
+            Some(n) => n
+        }
+    };
+    for _ in 0..count {
+        let result = if redo { history.redo(&staff.name) } else { history.undo(&staff.name) };
+        match result {
+            Err(err) => {
+                println!("{}\n", capitalize(&err));
+                return;
+            },
+            Ok(description) => println!("#[ {} ]#", t!(&format!("{}.done", key), action = description))
+        }
+    }
+    println!();
+}
+
+// yes or no question, anything but y or yes (in the language of the menu) is a no
+fn confirm(question: &str) -> bool {
+    println!("{} {}", question, t!("answer.choices"));
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .unwrap();
+    i18n::is_yes(&answer)
+}
+
+// withdrawn books, restore one to the library store or purge them for good
+fn view_trash(staff: &Staff) {
+    let trash = match trash::load_trash() {
+        Err(err) => {
+            println!("{}", t!("trash.unreadable", error = err));
+            return;
+        },
+        Ok(trash) => trash
+    };
+    if trash.is_empty() {
+        println!("{}\n", t!("trash.empty"));
+        return;
+    }
+    print_banner(&t!("banner.trash"));
+    for (i, withdrawn) in trash.iter().enumerate() {
+        println!("[{}]", i);
+        if let Some(book) = withdrawn.book() {
+            print_field("field.book_name", book.name);
+            print_field("field.author", book.author);
+        }
+        print_field("field.withdrawn_on", i18n::date(withdrawn.date));
+        print_field("field.withdrawn_by", &withdrawn.staff);
+        print_field("field.reason", &withdrawn.reason);
+        println!("{}", RULE);
+    }
+    println!();
 
-            match updated_file.write(updated_data.as_bytes()) {
-                Err(err) => println!("Error occured when update file, {}", err),
-                Ok(_) => println!("#[ Library store has been updated ]#")
+    println!("{}", t!("trash.choices"));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let choice = inp.trim().to_string();
+    let index = match choice.as_str() {
+        "1" | "2" => {
+            println!("{}", t!("prompt.book_number"));
+            let mut number = String::new();
+            io::stdin()
+                .read_line(&mut number)
+                .unwrap();
+            match number.trim().parse::<usize>().ok().filter(|n| *n < trash.len()) {
+                None => {
+                    println!("{}", t!("input.invalid_choice"));
+                    return;
+                },
+                some => some
             }
+        },
+        "3" => None,
+        _ => return
+    };
+    if choice == "1" {
+        match library::restore_book(index.unwrap_or_default(), staff) {
+            Err(err) => println!("{}\n", t!("trash.restore_failed", error = err)),
+            Ok(number) => println!("#[ {} ]#\n", t!("trash.restored", number = number))
         }
-    }    
+        return;
+    }
+    let question = match index {
+        Some(i) => t!("trash.confirm_purge", number = i),
+        None => t!("trash.confirm_purge_all", count = trash.len())
+    };
+    if !confirm(&question) {
+        println!("#[ {} ]#\n", t!("trash.purge_cancelled"));
+        return;
+    }
+    match library::purge(index, staff) {
+        Err(err) => println!("{}\n", t!("trash.purge_failed", error = err)),
+        Ok(count) => println!("#[ {} ]#\n", t!("trash.purged", count = count))
+    }
 }
 
 // borrow a book
-fn borrow_a_book() {
-    println!("Please enter the book name you want to borrow:");
+fn borrow_a_book(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("{}", t!("borrow.prompt_book"));
     let mut book_name = String::new();
     io::stdin()
         .read_line(&mut book_name)
         .expect("Please enter something to search..");
 
     if book_name.trim() == "" {
-        println!("Please enter something...");
+        println!("{}", t!("input.empty"));
+        return;
+    }
+    let data = match library::read_store() {
+        Err(why) => {
+            println!("{}", t!("store.unreadable", error = why));
+            return;
+        },
+        Ok(data) => data
+    };
+    let books: Vec<Book> = match to_book_list(&data) {
+        Err(err) => {
+            println!("{}", t!("store.unreadable", error = err));
+            return;
+        },
+        Ok(books) => books
+    };
+    // copy number in the library store and the copy
+    let search_result: Vec<(usize, &Book)> = books.iter()
+        .enumerate()
+        .filter(|(_, x)| collation::contains(x.name, &book_name) && !x.borrowed)
+        .collect();
+    print_banner(&t!("banner.search_result"));
+    if !search_result.is_empty() {
+        print_copies(&search_result);
     } else {
-        let data_path = Path::new("librarystore");
-        let mut library_file = match File::open(&data_path) {
-            Err(why) => panic!("No library data store, {}", why),
-            Ok(file) => file
-        };       
-        
-        let mut data = String::new();
-        match library_file.read_to_string(&mut data) {
-            Err(why) => println!("Error when reading file, {}", why),
-            Ok(_) => {
-                let books: Vec<Book> = match to_book_list(&data) {
-                    Err(_) => vec![],
-                    Ok(b) => b
-                };
-                let search_result: Vec<&Book> = books.iter()
-                    .filter(|x| x.name.to_uppercase().contains(&book_name.trim().to_uppercase()) && x.borrowed == false)
-                    .clone().collect();
-                println!("\n#-------------------------------#");
-                println!("#  Rusty Library Search Result  #"); 
-                println!("#-------------------------------#");
-                if search_result.len() > 0 {
-                    println!("Result found: {}", search_result.len());
-                    println!("--------------------------------------------");
-                    for (i, book) in search_result.iter().enumerate() {
-                        println!("[{}]", i);
-                        println!("Book Name      : {}", book.name);
-                        println!("Book Author    : {}", book.author);
-                        println!("Published Year : {}", book.year_published);                        
-                        println!("Issue on       : {}", book.issue_date);
-                        println!("--------------------------------------------");
-                    }
-                } else {
-                    println!("Book is borrowed others\n");
-                    return;
-                }
-                println!();
-
-                println!("Please enter the book number you want to borrow:");
-                let mut book_number = String::new();
-                io::stdin()
-                    .read_line(&mut book_number)
-                    .expect("Please enter a valid number");
-
-                let book_number: isize = book_number.trim().parse().unwrap_or(-1);
-
-                if book_number == -1 {
-                    println!("Please enter a valid book number");
-                } else {                    
-                    let book = &search_result.get(book_number as usize).unwrap();
-                    let path = Path::new("borrower");        
-                    let mut file = OpenOptions::new()
-                                    .write(true)
-                                    .append(true)
-                                    .create(true)
-                                    .open(&path)
-                                    .unwrap();                    
-
-                    println!("Please enter the borrower name:");
-                    let mut borrower_name = String::new();
-                    io::stdin()
-                        .read_line(&mut borrower_name)                    
-                        .expect("Please enter the borrow name");                
-
-                    println!("Please enter the borrow date [YYYY-MM-DD]:");
-                    let mut borrow_date = String::new();
-                    io::stdin()
-                        .read_line(&mut borrow_date)                    
-                        .expect("Please enter the borrow date");
-
-                    if let Err(e) = write!(&mut file, "{}", format!("{}", 
-                        format!("{},{},{}\n", borrower_name.trim(), book.name.trim(), borrow_date.trim()))) {
-                            println!("Error when writing file, {}", e);
-                        }
-                    
-                    let mut book_index = 0;
-                    for (i, b) in books.iter().enumerate() {
-                        if &book.name == &b.name {
-                            println!("{}", &book.name);
-                            book_index = i;
-                        }
-                    }
-                    
-                    let mut updated_file = match File::create(data_path) {
-                        Err(why) => panic!("Cannot create new file, {}", why),
-                        Ok(file) => file
-                    };
-                    let mut updated_data = String::new();
-                    for (i, line) in data.split('\n').enumerate() {
-                        let new_line: String;                     
-                        if i == book_index {
-                            let mut slice_data: Vec<&str> = line.split(',').clone().collect();
-                            slice_data[3] = "true";
-                            new_line = format!("{},{},{},{},{}\n", 
-                                slice_data[0].trim(),
-                                slice_data[1].trim(),
-                                slice_data[2].trim(),
-                                slice_data[3].trim(),
-                                slice_data[4].trim());
-                        } else {
-                            new_line = format!("{}\n", line);
-                        }
-                        updated_data.push_str(&new_line);
-                    }
+        println!("{}\n", t!("borrow.all_borrowed"));
+        place_a_hold(&books, &book_name, clock, staff);
+        return;
+    }
+    println!();
 
-                    match updated_file.write_all(updated_data.as_bytes()) {
-                        Err(err) => println!("Error occured when update file, {}", err),
-                        Ok(_) => println!("#[ Library store has been updated ]#")
-                    }
+    println!("{}", t!("borrow.prompt_number"));
+    let mut book_number = String::new();
+    io::stdin()
+        .read_line(&mut book_number)
+        .expect("Please enter a valid number");
+    let (book_index, book) = match book_number.trim().parse::<usize>().ok().and_then(|n| search_result.get(n)) {
+        None => {
+            println!("{}", t!("input.invalid_book_number"));
+            return;
+        },
+        Some(result) => *result
+    };
 
-                    println!("#[ {} has been borrowed by {} ]#", &book.name.trim(), &borrower_name.trim());
-                }
-            }
+    println!("{}", t!("prompt.borrower_name"));
+    let mut borrower_name = String::new();
+    io::stdin()
+        .read_line(&mut borrower_name)                    
+        .expect("Please enter the borrow name");                
+
+    println!("{}", t!("borrow.prompt_date"));
+    let mut borrow_date = String::new();
+    io::stdin()
+        .read_line(&mut borrow_date)                    
+        .expect("Please enter the borrow date");
+    let borrow_date = if borrow_date.trim().is_empty() {
+        clock.today()
+    } else {
+        match NaiveDate::parse_from_str(borrow_date.trim(), "%Y-%m-%d") {
+            Err(_) => {
+                println!("{}", t!("input.invalid_date"));
+                return;
+            },
+            Ok(date) => date
         }
-    }    
+    };
+
+    match library::issue(book_index, borrower_name.trim(), borrow_date, clock.today(), config, staff) {
+        Err(err) => println!("{}\n", capitalize(&err.to_string())),
+        Ok(loan) => {
+            println!("#[ {} ]#", t!("store.updated"));
+            println!("#[ {} ]#", t!("borrow.done", book = book.name, patron = loan.borrower, due = i18n::date(loan.due_date)));
+            let mut receipt = Receipt::new(&loan.borrower, clock.today(), &staff.name);
+            receipt.loans.push(loan);
+            print_receipt(&receipt, config);
+        }
+    }
 }
 
-fn return_a_book() {
-    println!("Please enter the borrower name:");
+fn return_a_book(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("{}", t!("prompt.borrower_name"));
     let mut borrower_name = String::new();
     io::stdin()
         .read_line(&mut borrower_name)
         .unwrap();
     
-    println!("Please enter book that want to return:");
+    println!("{}", t!("return.prompt_book"));
     let mut borrowed_book = String::new();
     io::stdin()
         .read_line(&mut borrowed_book)
         .unwrap();    
-    let mut library_data = String::new();
-    let library_path = Path::new("librarystore");
-    let mut library_file = match File::open(library_path) {
-        Err(err) => panic!("Failed to open library file, {}", err),
-        Ok(file) => file
-    };
-        
-    match library_file.read_to_string(&mut library_data) {
-        Err(err) => println!("Failed to read library file, {}", err),
-        Ok(_) => {
-            let books: Vec<Book> = match to_book_list(&library_data) {
-                Err(_) => vec![],
-                Ok(b) => b
-            };
-            let search_result: Vec<&Book> = books.iter()
-                .filter(|x| x.name.to_uppercase().contains(&borrowed_book.trim().to_uppercase()) && x.borrowed == true)
-                .clone().collect();
-            println!("\n#-------------------------------#");
-            println!("#  Rusty Library Search Result  #"); 
-            println!("#-------------------------------#");
-            if search_result.len() > 0 {
-                println!("Result found: {}", search_result.len());
-                println!("--------------------------------------------");
-                for (i, book) in search_result.iter().enumerate() {
-                    println!("[{}]", i);
-                    println!("Book Name      : {}", book.name);
-                    println!("Book Author    : {}", book.author);
-                    println!("Published Year : {}", book.year_published);                        
-                    println!("Issue on       : {}", book.issue_date);
-                    println!("--------------------------------------------");
-                }
-            } else {
-                println!("No books found with this name\n");
-                return;
+    let library_data = match library::read_store() {
+        Err(err) => {
+            println!("{}", t!("store.unreadable", error = err));
+            return;
+        },
+        Ok(data) => data
+    };
+    let books: Vec<Book> = match to_book_list(&library_data) {
+        Err(err) => {
+            println!("{}", t!("store.unreadable", error = err));
+            return;
+        },
+        Ok(books) => books
+    };
+    let search_result: Vec<(usize, &Book)> = books.iter()
+        .enumerate()
+        .filter(|(_, x)| collation::contains(x.name, &borrowed_book) && x.borrowed)
+        .collect();
+    print_banner(&t!("banner.search_result"));
+    if !search_result.is_empty() {
+        print_copies(&search_result);
+    } else {
+        println!("{}\n", t!("return.not_found"));
+        return;
+    }
+    println!("{}", t!("return.prompt_number"));
+    let mut book_number = String::new();
+    io::stdin()
+        .read_line(&mut book_number)
+        .expect("Please enter a valid number");
+    let (book_index, book) = match book_number.trim().parse::<usize>().ok().and_then(|n| search_result.get(n)) {
+        None => {
+            println!("{}", t!("input.invalid_choice"));
+            return;
+        },
+        Some(result) => *result
+    };
+
+    let today = clock.today();
+    match library::return_book(book_index, borrower_name.trim(), today, config, staff) {
+        Err(err) => println!("{}\n", t!("return.failed", error = err)),
+        Ok(returned) => report_return(&returned, book.name, borrower_name.trim(), today, config, staff)
+    }
+}
+
+fn report_return(returned: &library::Returned, book_name: &str, borrower_name: &str, today: NaiveDate, config: &Config, staff: &Staff) {
+    println!("{}", t!("return.was_due", due = i18n::date(returned.loan.due_date)));
+    let mut receipt = Receipt::new(&returned.loan.borrower, today, &staff.name);
+    if returned.fine > 0 {
+        println!("{}", t!("return.days_late", days = returned.days_overdue));
+        receipt.paid = collect_late_fine(borrower_name, returned.fine, today, staff);
+    }
+    receipt.loans.push(returned.loan.clone());
+    println!("#[ {} ]#", t!("return.loans_updated"));
+    println!("#[ {} ]#", t!("store.updated"));
+    println!("#[ {} ]#\n", t!("return.done", book = book_name, patron = borrower_name));
+    if let Some(hold) = &returned.set_aside {
+        println!("#[ {} ]#\n", hold_shelf_message(hold, today));
+    }
+    print_receipt(&receipt, config);
+}
+
+// copies to pick from with their copy number in the store
+fn print_copies(copies: &[(usize, &Book)]) {
+    println!("{}", t!("result.found", count = copies.len()));
+    println!("{}", RULE);
+    for (i, (_, book)) in copies.iter().enumerate() {
+        println!("[{}]", i);
+        print_field("field.book_name", book.name);
+        print_field("field.author", book.author);
+        print_field("field.published_year", book.year_published);
+        print_field("field.issue_date", i18n::date(book.issue_date));
+        println!("{}", RULE);
+    }
+}
+
+fn hold_shelf_message(hold: &hold::Hold, today: NaiveDate) -> String {
+    t!("hold.put_on_shelf", book = hold.book_name, patron = hold.patron, until = i18n::date(hold.pickup_until.unwrap_or(today)))
+}
+
+// write the receipt of a loan or return and tell where it is
+fn print_receipt(receipt: &Receipt, config: &Config) {
+    match receipt::write(receipt, config) {
+        Err(err) => println!("{}", t!("receipt.failed", error = err)),
+        Ok(paths) => {
+            for path in paths {
+                println!("#[ {} ]#", t!("receipt.written", patron = receipt.patron, path = path.display()));
             }
-            println!("Please enter the book number you want to return:");
-            let mut book_number = String::new();
-            io::stdin()
-                .read_line(&mut book_number)
-                .expect("Please enter a valid number");
+        }
+    }
+}
 
-            let book_number: isize = book_number.trim().parse().unwrap_or(-1);
+// title of a typed command, the exact name or the only title that contains the text
+fn find_title<'b>(books: &[Book<'b>], text: &str) -> Result<&'b str, String> {
+    if let Some(book) = books.iter().find(|b| collation::same(b.name, text)) {
+        return Ok(book.name);
+    }
+    let mut titles: Vec<&str> = books.iter()
+        .filter(|b| collation::contains(b.name, text))
+        .map(|b| b.name)
+        .collect();
+    titles.sort_by_cached_key(|title| collation::sort_key(title));
+    titles.dedup();
+    match titles.as_slice() {
+        [] => Err(t!("command.no_match", text = text.trim())),
+        [title] => Ok(title),
+        _ => Err(t!("command.many_matches", text = text.trim(), titles = titles.join(", ")))
+    }
+}
 
-            if book_number == -1 {
-                println!("Please enter a valid book number");
-            } else {
-                if book_number as usize >= search_result.len() {
-                    println!("Please enter a valid option from the display list");
-                    return;
-                }
-                let book = &search_result.get(book_number as usize).unwrap();
-                let borrower_path = Path::new("borrower");
-                let mut borrower_file = match File::open(borrower_path) {
-                    Err(err) => panic!("Failed to open borrower file, {}", err),
-                    Ok(file) => file
-                };
-            
-                let mut borrower_data = String::new();
-                match borrower_file.read_to_string(&mut borrower_data) {
-                    Err(err) => println!("Failed to read borrower file, {}", err),
-                    Ok(_) => {            
-                        let mut updated_borrower_data = String::new();                        
-                        for line in borrower_data.split('\n') {
-                            if line.trim().len() > 0 {
-                                let slice_data: Vec<&str> = line.split(',').clone().collect();
-                                if slice_data[0].to_uppercase() == borrower_name.trim().to_uppercase() 
-                                    && slice_data[1] == book.name {
-                                    let borrower_date = DateTime::parse_from_str(format!("{}  00:00:0.000 +0000", slice_data[2].trim()).as_str(), "%Y-%m-%d %H:%M:%S%.3f %z").unwrap();
-                                    let today: DateTime<Utc> = Utc::now();
-                                    let diff = borrower_date.signed_duration_since(today).num_days();
-                                    // println!("day: {}", diff);
-                                    if diff < -14 {
-                                        let late_payment: f64 = (diff * -1) as f64 * 0.5;
-                                        println!("Borrower has late payment of {} days,", diff);
-                                        println!("Borrower need to pay {} ", late_payment);
-
-                                        let mut done_payment = String::new();
-                                        println!("Type [done] after borrower make payment");
-                                        io::stdin()
-                                            .read_line(&mut done_payment)
-                                            .unwrap();
-                                        if done_payment.trim() != "done" {
-                                            println!("Please type correct command, 'done' in order to finish the operation\nRetry again\n");
-                                            return;
-                                        }
-                                    }                                    
-                                } else {
-                                    updated_borrower_data.push_str(line);
-                                    updated_borrower_data.push_str("\n");
-                                }
-                            }
-                        }
-            
-                        let mut updated_borrower_file = match File::create(borrower_path) {
-                            Err(why) => panic!("Cannot create new file, {}", why),
-                            Ok(file) => file
-                        };
-                        match updated_borrower_file.write_all(updated_borrower_data.as_bytes()) {
-                            Err(err) => println!("Error occured when update file, {}", err),
-                            Ok(_) => println!("#[ Borrower data has been updated ]#")
-                        };
-                    }
-                }
+// copy number of a title that is not on loan
+fn find_copy(books: &[Book], text: &str) -> Result<usize, String> {
+    let title = find_title(books, text)?;
+    books.iter()
+        .position(|b| b.name == title && !b.borrowed)
+        .ok_or_else(|| t!("command.all_on_loan", book = title))
+}
+
+// a scanned barcode, None on an empty line or the end of the input
+fn read_scan() -> Option<String> {
+    let mut code = String::new();
+    match io::stdin().read_line(&mut code) {
+        Ok(read) if read > 0 && !code.trim().is_empty() => Some(barcode::normalize(&code)),
+        _ => None
+    }
+}
+
+// what the desk needs to know of a patron before lending
+fn patron_summary(patron: &Patron, clock: &dyn Clock, config: &Config) -> Result<String, LibraryError> {
+    let loans = loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY))?;
+    let open: Vec<&Loan> = loans.iter().filter(|l| l.is_open() && l.is_for(&patron.name)).collect();
+    let overdue = open.iter().filter(|l| l.is_overdue(clock.today())).count();
+    Ok(t!("desk.patron_summary", patron = patron.name, category = patron.category, on_loan = open.len(),
+        overdue = overdue, balance = ledger::format_money(ledger::balance(&patron.name)?)))
+}
 
-                let mut book_index = 0;
-                for (i, b) in books.iter().enumerate() {
-                    if &book.name == &b.name {
-                        book_index = i;
+// scan a library card, then every item scanned is lent to that patron until the next card
+fn checkout_desk(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("#[ {} ]#", t!("desk.checkout_start"));
+    let today = clock.today();
+    let mut borrower: Option<Patron> = None;
+    // the receipt of the patron at the desk, written when the next card is scanned
+    let mut receipt: Option<Receipt> = None;
+    while let Some(code) = read_scan() {
+        if barcode::is_card(&code) {
+            match library::patron_by_card(&code) {
+                Err(err) => println!("{}", capitalize(&err.to_string())),
+                Ok(patron) => {
+                    // a card scanned twice leaves nothing to print
+                    if let Some(receipt) = receipt.replace(Receipt::new(&patron.name, today, &staff.name)).filter(|r| !r.is_empty()) {
+                        print_receipt(&receipt, config);
                     }
-                }
-                
-                let mut updated_file = match File::create(library_path) {
-                    Err(why) => panic!("Cannot create new file, {}", why),
-                    Ok(file) => file
-                };
-                let mut updated_library_data = String::new();
-                for (i, line) in library_data.split('\n').enumerate() {
-                    let new_line: String;
-                    if i == book_index {
-                        let mut slice_data: Vec<&str> = line.split(',').clone().collect();
-                        slice_data[3] = "false";
-                        new_line = format!("{},{},{},{},{}\n", 
-                            slice_data[0].trim(),
-                            slice_data[1].trim(),
-                            slice_data[2].trim(),
-                            slice_data[3].trim(),
-                            slice_data[4].trim());
-                    } else {
-                        new_line = format!("{}\n", line);
+                    match patron_summary(&patron, clock, config) {
+                        Err(err) => println!("{}", t!("desk.loans_unreadable", patron = patron.name, error = err)),
+                        Ok(summary) => println!("#[ {} ]#", summary)
                     }
-                    updated_library_data.push_str(&new_line);
+                    borrower = Some(patron);
                 }
-
-                match updated_file.write_all(updated_library_data.as_bytes()) {
-                    Err(err) => println!("Error occured when update file, {}", err),
-                    Ok(_) => println!("#[ Library store has been updated ]#")
+            }
+        } else if !barcode::is_item(&code) {
+            println!("{}", t!("desk.unknown_code", code = code));
+        } else if let Some(patron) = &borrower {
+            match library::copy_by_barcode(&code).and_then(|index| library::issue(index, &patron.name, today, today, config, staff)) {
+                Err(err) => println!("{} {}", code, err),
+                Ok(loan) => {
+                    println!("#[ {} ]#", t!("desk.lent", code = code, book = loan.book_name, patron = loan.borrower, due = i18n::date(loan.due_date)));
+                    receipt.iter_mut().for_each(|r| r.loans.push(loan.clone()));
                 }
+            }
+        } else {
+            println!("{}", t!("desk.card_first"));
+        }
+    }
+    if let Some(receipt) = receipt.filter(|r| !r.is_empty()) {
+        print_receipt(&receipt, config);
+    }
+    println!("#[ {} ]#\n", t!("desk.checkout_end"));
+}
 
-                println!("#[ {} has been return by {} ]#\n", &book.name.trim(), &borrower_name.trim());
+// every item scanned is returned, its fine and the hold it goes to are shown at once
+fn checkin_desk(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("#[ {} ]#", t!("desk.checkin_start"));
+    let today = clock.today();
+    // one receipt for each patron whose items came back
+    let mut receipts: Vec<Receipt> = vec![];
+    while let Some(code) = read_scan() {
+        let returned = match library::check_in(&code, today, config, staff) {
+            Err(err) => {
+                println!("{} {}", code, err);
+                continue;
+            },
+            Ok(returned) => returned
+        };
+        println!("#[ {} ]#", t!("desk.returned", code = code, book = returned.loan.book_name, patron = returned.loan.borrower));
+        if returned.fine > 0 {
+            let balance = ledger::balance(&returned.loan.borrower).unwrap_or(returned.fine);
+            println!("#[ {} ]#", t!("desk.fined", days = returned.days_overdue, fine = ledger::format_money(returned.fine),
+                patron = returned.loan.borrower, balance = ledger::format_money(balance)));
+        }
+        if let Some(hold) = &returned.set_aside {
+            println!("#[ {} ]#", hold_shelf_message(hold, today));
+        }
+        match receipts.iter_mut().find(|r| returned.loan.is_for(&r.patron)) {
+            Some(receipt) => receipt.loans.push(returned.loan),
+            None => {
+                let mut receipt = Receipt::new(&returned.loan.borrower, today, &staff.name);
+                receipt.loans.push(returned.loan);
+                receipts.push(receipt);
             }
         }
-    };    
-    
-}
\ No newline at end of file
+    }
+    for receipt in &receipts {
+        print_receipt(receipt, config);
+    }
+    println!("#[ {} ]#\n", t!("desk.checkin_end"));
+}
+
+// typed commands of the shell, the same library operations as the numbered options
+fn run_command(command: &Command, clock: &dyn Clock, config: &Config, staff: &Staff) -> Result<(), String> {
+    let today = clock.today();
+    let data = library::read_store().map_err(|err| t!("store.unreadable", error = err))?;
+    let books: Vec<Book> = to_book_list(&data).map_err(|err| t!("store.unreadable", error = err))?;
+    match command {
+        Command::Borrow { title, patron, date } => {
+            let index = find_copy(&books, title)?;
+            let loan = library::issue(index, patron, date.unwrap_or(today), today, config, staff).map_err(|err| err.to_string())?;
+            println!("#[ {} ]#", t!("store.updated"));
+            println!("#[ {} ]#\n", t!("borrow.done", book = loan.book_name, patron = loan.borrower, due = i18n::date(loan.due_date)));
+            let mut receipt = Receipt::new(&loan.borrower, today, &staff.name);
+            receipt.loans.push(loan);
+            print_receipt(&receipt, config);
+        },
+        Command::Return { title, patron } => {
+            let index = library::copy_on_loan(&books, find_title(&books, title)?, patron, config)
+                .map_err(|err| t!("return.failed", error = err))?;
+            let returned = library::return_book(index, patron, today, config, staff)
+                .map_err(|err| t!("return.failed", error = err))?;
+            report_return(&returned, books[index].name, patron, today, config, staff);
+        },
+        Command::Renew { title, patron } => {
+            let loan = library::renew(patron, find_title(&books, title)?, today, config, staff).map_err(|err| err.to_string())?;
+            println!("#[ {} ]#\n", t!("renew.done", book = loan.book_name, due = i18n::date(loan.due_date)));
+        },
+        Command::Hold { title, patron } => {
+            let title = find_title(&books, title)?;
+            let position = library::place_hold(title, patron, today, staff).map_err(|err| t!("hold.failed", error = err))?;
+            println!("#[ {} ]#\n", t!("hold.placed", book = title, patron = patron, position = position));
+        },
+        Command::Option(_) | Command::Help | Command::Search(_) => ()
+    }
+    Ok(())
+}
+
+// "loan is refused, ..." -> "Loan is refused, ..."
+fn capitalize(message: &str) -> String {
+    let mut chars = message.chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first.to_uppercase().chain(chars).collect()
+    }
+}
+
+// the late fine is on the borrower account, take a full or partial payment
+// or leave it outstanding so the book can still be returned, returns what was paid
+fn collect_late_fine(borrower_name: &str, amount: i64, today: NaiveDate, staff: &Staff) -> i64 {
+    println!("{}", t!("fine.to_pay", amount = ledger::format_money(amount)));
+    match ledger::balance(borrower_name) {
+        Err(err) => println!("{}", t!("ledger.unreadable", error = err)),
+        Ok(balance) => println!("{}", t!("fine.balance", balance = ledger::format_money(balance)))
+    }
+
+    println!("{}", t!("fine.prompt_paid"));
+    let mut paid = String::new();
+    io::stdin()
+        .read_line(&mut paid)
+        .unwrap();
+    if paid.trim().is_empty() {
+        println!("#[ {} ]#", t!("fine.kept", patron = borrower_name));
+        return 0;
+    }
+    match ledger::parse_money(&paid) {
+        None => {
+            println!("{}", t!("fine.invalid_kept", patron = borrower_name));
+            0
+        },
+        Some(amount) if record_account_entry(borrower_name, EntryKind::Payment, amount, "payment on return", today, staff) => amount,
+        Some(_) => 0
+    }
+}
+
+// patron account, show the ledger and take payments, waive fines or refund
+fn patron_account(clock: &dyn Clock, staff: &Staff) {
+    println!("{}", t!("prompt.borrower_name"));
+    let mut borrower_name = String::new();
+    io::stdin()
+        .read_line(&mut borrower_name)
+        .unwrap();
+    let borrower_name = borrower_name.trim();
+    if borrower_name.is_empty() {
+        println!("{}", t!("input.empty"));
+        return;
+    }
+
+    let entries = match ledger::entries_for(borrower_name) {
+        Err(err) => {
+            println!("{}", t!("ledger.unreadable", error = err));
+            return;
+        },
+        Ok(entries) => entries
+    };
+    print_banner(&t!("banner.patron_account"));
+    for entry in &entries {
+        println!("{}  {} {:>10}  {}", i18n::date(entry.date), i18n::pad(&t!(&format!("ledger.{}", entry.kind)), 8),
+            ledger::format_money(entry.amount), entry.note);
+    }
+    println!("{}", RULE);
+    println!("{}", t!("account.balance", balance = ledger::format_money(ledger::balance_of(&entries))));
+    println!();
+
+    println!("{}", t!("account.choices"));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let kind = match inp.trim() {
+        "1" => EntryKind::Payment,
+        "2" => EntryKind::Waiver,
+        "3" => EntryKind::Refund,
+        _ => return
+    };
+    let permission = if kind == EntryKind::Payment { Permission::Payments } else { Permission::Waivers };
+    if let Err(err) = staff.require(permission) {
+        println!("{}\n", capitalize(&err.to_string()));
+        return;
+    }
+
+    println!("{}", t!("account.prompt_amount"));
+    let mut amount = String::new();
+    io::stdin()
+        .read_line(&mut amount)
+        .unwrap();
+    let amount = match ledger::parse_money(&amount) {
+        Some(amount) if amount > 0 => amount,
+        _ => {
+            println!("{}", t!("input.invalid_amount"));
+            return;
+        }
+    };
+
+    println!("{}", t!("account.prompt_note"));
+    let mut note = String::new();
+    io::stdin()
+        .read_line(&mut note)
+        .unwrap();
+
+    record_account_entry(borrower_name, kind, amount, note.trim(), clock.today(), staff);
+}
+
+
+// true when the entry has been recorded
+fn record_account_entry(borrower_name: &str, kind: EntryKind, amount: i64, note: &str, today: NaiveDate, staff: &Staff) -> bool {
+    match library::record_account_entry(borrower_name, kind, amount, note, today, staff) {
+        Err(err) => {
+            println!("{}\n", t!("account.failed", error = err));
+            false
+        },
+        Ok(balance) => {
+            println!("#[ {} ]#", t!("account.recorded", kind = t!(&format!("ledger.{}", kind)), amount = ledger::format_money(amount),
+                patron = borrower_name));
+            println!("#[ {} ]#\n", t!("fine.balance", balance = ledger::format_money(balance)));
+            true
+        }
+    }
+}
+
+// renew a book, push the due date by another loan period
+fn renew_a_book(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("{}", t!("prompt.borrower_name"));
+    let mut borrower_name = String::new();
+    io::stdin()
+        .read_line(&mut borrower_name)
+        .unwrap();
+
+    let loans = match loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            println!("{}", t!("loans.unreadable", error = err));
+            return;
+        },
+        Ok(loans) => loans
+    };
+    let borrowed: Vec<&Loan> = loans.iter()
+        .filter(|l| l.is_open() && l.is_for(&borrower_name))
+        .collect();
+    if borrowed.is_empty() {
+        println!("{}\n", t!("renew.nothing_borrowed", patron = borrower_name.trim()));
+        return;
+    }
+
+    print_banner(&t!("banner.borrowed_books"));
+    for (i, loan) in borrowed.iter().enumerate() {
+        println!("[{}]", i);
+        print_field("field.book_name", &loan.book_name);
+        print_field("field.borrow_date", i18n::date(loan.issue_date));
+        print_field("field.due_date", i18n::date(loan.due_date));
+        print_field("field.renewed", t!("renew.times", count = loan.renewals));
+        println!("{}", RULE);
+    }
+
+    println!("{}", t!("renew.prompt_number"));
+    let mut book_number = String::new();
+    io::stdin()
+        .read_line(&mut book_number)
+        .expect("Please enter a valid number");
+    let loan = match book_number.trim().parse::<usize>().ok().and_then(|n| borrowed.get(n)) {
+        None => {
+            println!("{}", t!("input.invalid_choice"));
+            return;
+        },
+        Some(loan) => loan
+    };
+
+    match library::renew(&borrower_name, &loan.book_name, clock.today(), config, staff) {
+        Err(err) => println!("{}\n", capitalize(&err.to_string())),
+        Ok(loan) => println!("#[ {} ]#\n", t!("renew.done", book = loan.book_name, due = i18n::date(loan.due_date)))
+    }
+}
+
+// register a patron with a category, the category picks the loan rules
+fn register_patron(staff: &Staff) {
+    println!("{}", t!("prompt.patron_name"));
+    let mut name = String::new();
+    io::stdin()
+        .read_line(&mut name)
+        .unwrap();
+
+    println!("{}", t!("register.prompt_category", default = patron::DEFAULT_CATEGORY));
+    let mut category = String::new();
+    io::stdin()
+        .read_line(&mut category)
+        .unwrap();
+
+    println!("{}", t!("register.prompt_email"));
+    let mut email = String::new();
+    io::stdin()
+        .read_line(&mut email)
+        .unwrap();
+
+    let patron = Patron { name, category, email, notify: true, card: String::new() };
+    match library::register_patron(&patron, staff) {
+        Err(err) => println!("{}\n", t!("register.failed", error = err)),
+        Ok(patron) => println!("#[ {} ]#\n", t!("register.done", patron = patron.name, category = patron.category, card = patron.card))
+    }
+}
+
+// place a hold on a title where every copy is borrowed
+fn place_a_hold(books: &[Book], book_name: &str, clock: &dyn Clock, staff: &Staff) {
+    let mut titles: Vec<&str> = books.iter()
+        .filter(|x| collation::contains(x.name, book_name))
+        .map(|x| x.name)
+        .collect();
+    titles.sort_by_cached_key(|title| collation::sort_key(title));
+    titles.dedup();
+    if titles.is_empty() {
+        return;
+    }
+
+    println!("{}", t!("hold.prompt_number"));
+    for (i, title) in titles.iter().enumerate() {
+        println!("[{}] {}", i, title);
+    }
+    let mut book_number = String::new();
+    io::stdin()
+        .read_line(&mut book_number)
+        .unwrap();
+    if book_number.trim().is_empty() {
+        return;
+    }
+    let title = match book_number.trim().parse::<usize>().ok().and_then(|n| titles.get(n)) {
+        None => {
+            println!("{}", t!("input.invalid_choice"));
+            return;
+        },
+        Some(title) => *title
+    };
+
+    println!("{}", t!("prompt.borrower_name"));
+    let mut borrower_name = String::new();
+    io::stdin()
+        .read_line(&mut borrower_name)
+        .unwrap();
+
+    match library::place_hold(title, &borrower_name, clock.today(), staff) {
+        Err(err) => println!("{}\n", t!("hold.failed", error = err)),
+        Ok(position) => println!("#[ {} ]#\n", t!("hold.placed", book = title, patron = borrower_name.trim(), position = position))
+    }
+}
+
+// list the hold shelf and the waiting queues
+fn view_holds(clock: &dyn Clock, config: &Config) {
+    let mut holds = match hold::load_holds() {
+        Err(err) => {
+            println!("{}", t!("hold.unreadable", error = err));
+            return;
+        },
+        Ok(holds) => holds
+    };
+    let expired = hold::expire(&mut holds, clock.today(), config.hold_pickup_days());
+    if !expired.is_empty() {
+        if let Err(err) = hold::save_holds(&holds) {
+            println!("{}", t!("store.unwritable", error = err));
+        }
+    }
+
+    print_banner(&t!("banner.holds"));
+    for hold in &expired {
+        print_field("field.expired", t!("hold.expired", book = hold.book_name, patron = hold.patron));
+    }
+    for hold in &holds {
+        print_field("field.book_name", &hold.book_name);
+        print_field("field.patron", &hold.patron);
+        print_field("field.placed_on", i18n::date(hold.placed_date));
+        match hold.pickup_until {
+            Some(date) => print_field("field.status", t!("hold.on_shelf", until = i18n::date(date))),
+            None => print_field("field.status", t!("hold.waiting"))
+        }
+        println!("{}", RULE);
+    }
+    println!();
+}
+
+// loan history of a book or of a patron, returned loans included
+fn loan_history(clock: &dyn Clock, config: &Config) {
+    println!("{}", t!("history.choices"));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let by_book = match inp.trim() {
+        "1" => true,
+        "2" => false,
+        _ => {
+            println!("{}", t!("menu.invalid_option"));
+            return;
+        }
+    };
+
+    println!("{}", t!(if by_book { "prompt.book_name" } else { "prompt.borrower_name" }));
+    let mut search = String::new();
+    io::stdin()
+        .read_line(&mut search)
+        .unwrap();
+    if search.trim().is_empty() {
+        println!("{}", t!("input.empty"));
+        return;
+    }
+
+    let loans = match loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            println!("{}", t!("loans.unreadable", error = err));
+            return;
+        },
+        Ok(loans) => loans
+    };
+    let history: Vec<&Loan> = loans.iter()
+        .filter(|l| if by_book {
+            collation::contains(&l.book_name, &search)
+        } else {
+            l.is_for(&search)
+        })
+        .collect();
+
+    print_banner(&t!("banner.loan_history"));
+    if history.is_empty() {
+        println!("{}\n", t!("result.none"));
+        return;
+    }
+    println!("{}", t!("result.found", count = history.len()));
+    println!("{}", RULE);
+    let today = clock.today();
+    for loan in history {
+        print_field("field.book_name", &loan.book_name);
+        print_field("field.borrower", &loan.borrower);
+        print_field("field.borrow_date", i18n::date(loan.issue_date));
+        print_field("field.due_date", i18n::date(loan.due_date));
+        match loan.return_date {
+            Some(date) => print_field("field.return_date", i18n::date(date)),
+            None if loan.is_overdue(today) => print_field("field.return_date", t!("history.overdue", days = loan.days_overdue(today))),
+            None => print_field("field.return_date", t!("history.not_returned"))
+        }
+        print_field("field.fine", ledger::format_money(loan.fine));
+        print_field("field.clerk", &loan.clerk);
+        if !loan.returned_by.is_empty() {
+            print_field("field.returned_to", &loan.returned_by);
+        }
+        println!("{}", RULE);
+    }
+    println!();
+}
+
+// every open loan past its due date, export it or write reminder notices
+fn overdue_report(clock: &dyn Clock, config: &Config) {
+    let loans = match loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            println!("{}", t!("loans.unreadable", error = err));
+            return;
+        },
+        Ok(loans) => loans
+    };
+    let today = clock.today();
+    let mut items = overdue::overdue_items(&loans, today, config.fine_per_day());
+
+    println!("{}", t!("overdue.prompt_sort"));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let sort_by = match inp.trim() {
+        "2" => overdue::SortBy::Patron,
+        "3" => overdue::SortBy::Title,
+        "4" => overdue::SortBy::Fine,
+        _ => overdue::SortBy::DaysLate
+    };
+    overdue::sort(&mut items, sort_by);
+
+    print_banner(&t!("banner.overdue_report"));
+    if items.is_empty() {
+        println!("{}\n", t!("overdue.nothing"));
+        return;
+    }
+    println!("{}", t!("result.found", count = items.len()));
+    println!("{}", RULE);
+    for item in &items {
+        print_field("field.borrower", &item.patron);
+        print_field("field.book_name", &item.book_name);
+        print_field("field.due_date", i18n::date(item.due_date));
+        print_field("field.days_late", item.days_late);
+        print_field("field.accrued_fine", ledger::format_money(item.fine));
+        println!("{}", RULE);
+    }
+    println!();
+
+    println!("{}", t!("overdue.choices"));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    match inp.trim() {
+        "1" => {
+            let path = format!("overdue-{}.csv", today);
+            match overdue::export_csv(&items, Path::new(&path)) {
+                Err(err) => println!("{}", t!("store.unwritable", error = err)),
+                Ok(_) => println!("#[ {} ]#\n", t!("overdue.exported", path = path))
+            }
+        },
+        "2" | "3" => {
+            let format = if inp.trim() == "2" { overdue::NoticeFormat::Letter } else { overdue::NoticeFormat::Email };
+            let patrons = patron::load_patrons().unwrap_or_default();
+            match overdue::generate_notices(&items, &patrons, format, today) {
+                Err(err) => println!("{}", t!("overdue.notices_failed", error = err)),
+                Ok(files) => {
+                    for file in &files {
+                        println!("{}", file.display());
+                    }
+                    println!("#[ {} ]#\n", t!("overdue.notices_written", count = files.len()));
+                }
+            }
+        },
+        _ => ()
+    }
+}
+
+// mail due soon, overdue and hold ready reminders, or write them to the outbox for a dry run
+fn send_reminders(clock: &dyn Clock, config: &Config, staff: &Staff) {
+    println!("{}", t!("reminders.choices", outbox = config.notify_outbox()));
+    let mut inp = String::new();
+    io::stdin()
+        .read_line(&mut inp)
+        .unwrap();
+    let dry_run = match inp.trim() {
+        "1" => false,
+        "2" => true,
+        "3" => return set_patron_notify(false, staff),
+        "4" => return set_patron_notify(true, staff),
+        _ => return
+    };
+
+    let today = clock.today();
+    let loans = loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY));
+    let holds = hold::load_holds();
+    let patrons = patron::load_patrons();
+    let sent = notify::load_sent_log();
+    let notices = match (loans, holds, patrons, sent) {
+        (Ok(loans), Ok(holds), Ok(patrons), Ok(sent)) => 
+            notify::collect(&loans, &holds, &patrons, &sent, today, config.due_soon_days(), config.fine_per_day()),
+        _ => {
+            println!("{}", t!("reminders.unreadable"));
+            return;
+        }
+    };
+    if notices.is_empty() {
+        println!("{}\n", t!("reminders.nothing"));
+        return;
+    }
+
+    let (delivered, errors) = if dry_run {
+        let mut outbox = notify::OutboxTransport { dir: Path::new(config.notify_outbox()).to_path_buf(), written: vec![] };
+        let result = notify::send_all(&notices, &mut outbox, config.smtp_from(), today, false);
+        for file in &outbox.written {
+            println!("{}", file.display());
+        }
+        result
+    } else {
+        let mut smtp = match notify::SmtpTransport::connect(config.smtp_host(), config.smtp_port(), config.smtp_from()) {
+            Err(err) => {
+                println!("{}", t!("reminders.connect_failed", host = config.smtp_host(), port = config.smtp_port(), error = err));
+                return;
+            },
+            Ok(smtp) => smtp
+        };
+        let result = notify::send_all(&notices, &mut smtp, config.smtp_from(), today, true);
+        if let Err(err) = smtp.close() {
+            println!("{}", t!("reminders.close_failed", error = err));
+        }
+        result
+    };
+
+    for err in &errors {
+        println!("{}", t!("reminders.send_failed", error = err));
+    }
+    let key = if dry_run { "reminders.written" } else { "reminders.sent" };
+    println!("#[ {} ]#\n", t!(key, delivered = delivered, count = notices.len()));
+}
+
+fn set_patron_notify(notify: bool, staff: &Staff) {
+    println!("{}", t!("prompt.patron_name"));
+    let mut name = String::new();
+    io::stdin()
+        .read_line(&mut name)
+        .unwrap();
+
+    let patron = match patron::find(&name) {
+        Err(err) => {
+            println!("{}", t!("patrons.unreadable", error = err));
+            return;
+        },
+        Ok(None) => {
+            println!("{}\n", t!("patrons.not_registered", patron = name.trim()));
+            return;
+        },
+        Ok(Some(patron)) => patron
+    };
+    match library::update_patron(&name, &Patron { notify, ..patron }, staff) {
+        Err(err) => println!("{}\n", t!("patrons.not_updated", error = err)),
+        Ok(patron) => println!("#[ {} ]#\n", t!(if notify { "reminders.opted_in" } else { "reminders.opted_out" }, patron = patron.name))
+    }
+}
+
+fn build_report(clock: &dyn Clock, config: &Config, from: Option<NaiveDate>, to: Option<NaiveDate>, 
+    period: stats::Period, top: usize) -> Result<stats::Report, LibraryError> {
+    let loans = loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY))?;
+    let catalogue = load_catalogue()?;
+    let entries = ledger::load_entries()?;
+    let today = clock.today();
+    let from = from.unwrap_or_else(|| loans.iter().map(|l| l.issue_date).min().unwrap_or(today));
+    Ok(stats::report(&loans, &catalogue, &entries, from, to.unwrap_or(today), today, period, top))
+}
+
+// circulation statistics of every loan so far
+fn circulation_statistics(clock: &dyn Clock, config: &Config) {
+    match build_report(clock, config, None, None, stats::Period::Month, 5) {
+        Err(err) => println!("{}", t!("store.unreadable", error = err)),
+        Ok(report) => {
+            print_banner(&t!("banner.statistics"));
+            println!("{}", stats::to_text(&report, "all"));
+        }
+    }
+}
+
+// report [summary|periods|titles|authors|all] [--from YYYY-MM-DD] [--to YYYY-MM-DD]
+//        [--period day|week|month|year] [--format text|json|csv] [--top N]
+fn date_option(args: &[String], name: &str) -> Result<Option<NaiveDate>, String> {
+    match cli::option(args, name) {
+        None => Ok(None),
+        Some(v) => NaiveDate::parse_from_str(&v, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|e| t!("cli.invalid_date", option = name, date = v, error = e))
+    }
+}
+
+fn run_report(args: &[String], clock: &dyn Clock, config: &Config) -> i32 {
+    let section = cli::positionals(args).get(1).copied().unwrap_or("all");
+    if !["summary", "periods", "titles", "authors", "all"].contains(&section) {
+        eprintln!("{}", t!("report.unknown", report = section));
+        return 1;
+    }
+    let (from, to) = match (date_option(args, "--from"), date_option(args, "--to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let period = match stats::Period::parse(&cli::option(args, "--period").unwrap_or_else(|| "month".to_string())) {
+        Some(period) => period,
+        None => {
+            eprintln!("{}", t!("report.invalid_period"));
+            return 1;
+        }
+    };
+    let top = match cli::option(args, "--top").map(|t| t.parse::<usize>()) {
+        None => 5,
+        Some(Ok(top)) => top,
+        Some(Err(_)) => {
+            eprintln!("{}", t!("report.invalid_top"));
+            return 1;
+        }
+    };
+
+    let report = match build_report(clock, config, from, to, period, top) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.data_unreadable", error = err));
+            return 1;
+        },
+        Ok(report) => report
+    };
+    match cli::option(args, "--format").as_deref().unwrap_or("text") {
+        "text" => print!("{}", stats::to_text(&report, section)),
+        "csv" => print!("{}", stats::to_csv(&report, section)),
+        "json" => match stats::to_json(&report, section) {
+            Err(err) => {
+                eprintln!("{}", t!("cli.json_failed", error = err));
+                return 1;
+            },
+            Ok(json) => println!("{}", json)
+        },
+        other => {
+            eprintln!("{}", t!("cli.unknown_format", format = other, formats = "text, json, csv"));
+            return 1;
+        }
+    }
+    0
+}
+
+// import <file.csv|file.json|file.mrc|file.xml> [--format csv|json|marc|marcxml] [--map field=column,...] [--dry-run] [--all-or-nothing]
+fn run_import(args: &[String], clock: &dyn Clock, staff: &Staff) -> i32 {
+    let path = match cli::positionals(args).get(1) {
+        Some(path) => Path::new(*path).to_path_buf(),
+        None => {
+            eprintln!("{}", t!("import.no_file"));
+            return 1;
+        }
+    };
+    let format = match cli::option(args, "--format") {
+        Some(f) => import::SourceFormat::parse(&f),
+        None => import::SourceFormat::from_path(&path)
+    };
+    let format = match format {
+        Some(format) => format,
+        None => {
+            eprintln!("{}", t!("import.unknown_format"));
+            return 1;
+        }
+    };
+    let mapping = match import::parse_mapping(&cli::option(args, "--map").unwrap_or_default()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        },
+        Ok(mapping) => mapping
+    };
+
+    let mut data = vec![];
+    if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+        eprintln!("{}", t!("cli.file_unreadable", path = path.display(), error = err));
+        return 1;
+    }
+    let records = match import::read_records(&data, format) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        },
+        Ok(records) => records
+    };
+
+    let data_path = Path::new("librarystore");
+    // a library that has no store yet is empty, everything is imported
+    let library_data = match library::read_store() {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(data) => data
+    };
+    let books = match to_book_list(&library_data) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(books) => books
+    };
+    let report = import::plan(&records, &mapping, &books, format.first_row(), clock.today());
+
+    for (row, problem) in &report.rejected {
+        match problem {
+            import::Problem::Invalid(reason) => println!("{}", t!("import.invalid", row = format!("{:>4}", row), reason = reason)),
+            import::Problem::Duplicate(reason) => println!("{}", t!("import.duplicate", row = format!("{:>4}", row), reason = reason))
+        }
+    }
+    print_field("field.rows_read", report.rows);
+    print_field("field.to_import", report.accepted.len());
+    print_field("field.rejected", report.rejected.len());
+
+    if cli::flag(args, "--dry-run") {
+        println!("#[ {} ]#", t!("import.dry_run"));
+        return 0;
+    }
+    if cli::flag(args, "--all-or-nothing") && !report.rejected.is_empty() {
+        println!("#[ {} ]#", t!("import.all_or_nothing"));
+        return 1;
+    }
+    let first_barcode = match library::next_item_number(&books) {
+        Err(err) => {
+            eprintln!("{}", t!("trash.unreadable", error = err));
+            return 1;
+        },
+        Ok(number) => number
+    };
+    match import::commit(&report.accepted, data_path, first_barcode) {
+        Err(err) => {
+            eprintln!("{}", t!("import.failed", error = err));
+            1
+        },
+        Ok(_) => {
+            println!("#[ {} ]#", t!("import.done", count = report.accepted.iter().map(|r| r.copies).sum::<usize>()));
+            if let Err(err) = audit_import(books.len(), &staff.name) {
+                eprintln!("{}", capitalize(&t!("cli.audit_failed", error = err)));
+                return 1;
+            }
+            0
+        }
+    }
+}
+
+// export [file] [--format marc|marcxml|bibtex|ris|dc] [--search TEXT], every title of the library store
+// or those whose name contains TEXT, printed when no file is given
+fn run_export(args: &[String]) -> i32 {
+    let path = cli::positionals(args).get(1).map(|p| Path::new(*p).to_path_buf());
+    let format = cli::option(args, "--format")
+        .or_else(|| path.as_ref()
+            .and_then(|p| p.extension())
+            .map(|e| e.to_string_lossy().to_lowercase()))
+        .unwrap_or_default();
+
+    let data = match library::read_store() {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(data) => data
+    };
+    let books = match to_book_list(&data) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(books) => books
+    };
+    let books = match cli::option(args, "--search") {
+        Some(text) => search_books(books, &text),
+        None => books
+    };
+    let titles = backup::titles(&books);
+    let marc_records = || -> Vec<marc::Record> {
+        titles.iter().enumerate().map(|(i, title)| marc::from_title(title, i + 1)).collect()
+    };
+    let output = match (format.as_str(), citation::CitationFormat::parse(&format)) {
+        ("marc", _) | ("mrc", _) => match marc::write_binary(&marc_records()) {
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            },
+            Ok(output) => output
+        },
+        ("marcxml", _) | ("xml", _) => marc::write_xml(&marc_records()).into_bytes(),
+        (_, Some(format)) => citation::write(&titles, format).into_bytes(),
+        _ => {
+            eprintln!("{}", t!("export.unknown_format"));
+            return 1;
+        }
+    };
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            if let Err(err) = io::stdout().write_all(&output) {
+                eprintln!("{}", t!("export.failed", error = err));
+                return 1;
+            }
+            return 0;
+        }
+    };
+    match File::create(&path).and_then(|mut f| f.write_all(&output)) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.file_unwritable", path = path.display(), error = err));
+            1
+        },
+        Ok(_) => {
+            println!("#[ {} ]#", t!("export.done", count = titles.len(), path = path.display()));
+            0
+        }
+    }
+}
+
+// backup [archive.tar.gz]
+fn run_backup(args: &[String], clock: &dyn Clock, config: &Config) -> i32 {
+    let today = clock.today();
+    let path = match cli::positionals(args).get(1) {
+        Some(path) => Path::new(*path).to_path_buf(),
+        None => Path::new(&format!("library-backup-{}.tar.gz", today)).to_path_buf()
+    };
+    let data = match library::read_store() {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(data) => data
+    };
+    let books = match to_book_list(&data) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(books) => books
+    };
+    let library = match backup::Library::from_store(&books, config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.data_unreadable", error = err));
+            return 1;
+        },
+        Ok(library) => library
+    };
+    match backup::export(&library, &path, today) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.file_unwritable", path = path.display(), error = err));
+            1
+        },
+        Ok(manifest) => {
+            for file in &manifest.files {
+                println!("{}", t!("backup.records", file = format!("{:<14}", file.name), count = format!("{:>6}", file.records)));
+            }
+            println!("#[ {} ]#", t!("backup.done", path = path.display()));
+            0
+        }
+    }
+}
+
+// one audit entry per imported copy, the copies after the first index of the library store
+fn audit_import(first_index: usize, staff: &str) -> Result<(), LibraryError> {
+    let data = library::read_store()?;
+    for (index, book) in to_book_list(&data)?.iter().enumerate().skip(first_index) {
+        audit::record(staff, "import book", &format!("book [{}] {}", index, book.name), None, Some(serde_json::json!(book)))?;
+    }
+    Ok(())
+}
+
+// record counts of a library, what the audit trail keeps of a restore
+fn library_counts(library: &backup::Library) -> serde_json::Value {
+    serde_json::json!({
+        "titles": library.books.len(),
+        "copies": library.copies(),
+        "patrons": library.patrons.len(),
+        "loans": library.loans.len(),
+        "holds": library.holds.len(),
+        "ledger_entries": library.ledger.len(),
+        "withdrawn": library.trash.len()
+    })
+}
+
+// restore <archive.tar.gz> [--dry-run] [--force]
+fn run_restore(args: &[String], config: &Config, staff: &Staff) -> i32 {
+    let path = match cli::positionals(args).get(1) {
+        Some(path) => Path::new(*path).to_path_buf(),
+        None => {
+            eprintln!("{}", t!("restore.no_file"));
+            return 1;
+        }
+    };
+    let (manifest, library) = match backup::read_archive(&path) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        },
+        Ok(backup) => backup
+    };
+    print_field("field.backup_of", manifest.created);
+    print_field("field.titles", library.books.len());
+    print_field("field.copies", library.copies());
+    print_field("field.patrons", library.patrons.len());
+    print_field("field.loans", library.loans.len());
+    print_field("field.holds", library.holds.len());
+    print_field("field.ledger_entries", library.ledger.len());
+    print_field("field.withdrawn", library.trash.len());
+
+    if cli::flag(args, "--dry-run") {
+        println!("#[ {} ]#", t!("restore.valid"));
+        return 0;
+    }
+    let data_path = Path::new("librarystore");
+    let has_data = std::fs::metadata(data_path).map(|m| m.len() > 0).unwrap_or(false);
+    if has_data && !cli::flag(args, "--force") {
+        println!("#[ {} ]#", t!("restore.not_empty"));
+        return 1;
+    }
+    let mut data = String::new();
+    if let Err(err) = File::open(data_path).and_then(|mut f| f.read_to_string(&mut data)) {
+        if err.kind() != io::ErrorKind::NotFound {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        }
+    }
+    let books = match to_book_list(&data) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(books) => books
+    };
+    let before = match backup::Library::from_store(&books, config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.data_unreadable", error = err));
+            return 1;
+        },
+        Ok(current) => library_counts(&current)
+    };
+    if let Err(err) = backup::restore(&library, data_path) {
+        eprintln!("{}", t!("restore.failed", error = err));
+        return 1;
+    }
+    println!("#[ {} ]#", t!("restore.done", path = path.display()));
+    let mut after = library_counts(&library);
+    after["backup_created"] = serde_json::json!(manifest.created);
+    match audit::record(&staff.name, "restore", &format!("library from {}", path.display()), Some(before), Some(after)) {
+        Err(err) => {
+            eprintln!("{}", capitalize(&t!("cli.audit_failed", error = err)));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+// serve [--port N], the REST API until the program is stopped
+fn run_serve(args: &[String], clock: &dyn Clock, config: &Config) -> i32 {
+    let port = match cli::option(args, "--port").map(|p| p.parse::<u16>()) {
+        None => config.serve_port(),
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            eprintln!("{}", t!("serve.invalid_port"));
+            return 1;
+        }
+    };
+    match server::serve(config.serve_host(), port, clock, config) {
+        Err(err) => {
+            eprintln!("{}", t!("serve.failed", error = err));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+fn run_tui(clock: &dyn Clock, config: &Config, staff: &Staff) -> i32 {
+    match tui::start(clock, config, staff) {
+        Err(err) => {
+            eprintln!("{}", t!("tui.failed", error = err));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+// staff [list | add NAME --role ROLE | passwd NAME | remove NAME | token NAME | revoke NAME],
+// only admins manage accounts, everybody can change their own password
+fn run_staff(args: &[String], clock: &dyn Clock) -> i32 {
+    let positionals = cli::positionals(args);
+    let action = positionals.get(1).copied().unwrap_or("list");
+    let name = positionals.get(2).copied();
+    let accounts = match auth::load_staff() {
+        Err(err) => {
+            eprintln!("{}", t!("staff.unreadable", error = err));
+            return 1;
+        },
+        Ok(accounts) => accounts
+    };
+
+    // the first account can be added without signing in, it has to be an admin
+    let first_account = accounts.is_empty() && action == "add";
+    let actor = if first_account {
+        name.unwrap_or("").to_string()
+    } else {
+        let staff = match sign_in(args, 1) {
+            None => return 1,
+            Some(staff) => staff
+        };
+        let own_password = action == "passwd" && name.map(|n| staff.is_named(n)).unwrap_or(false);
+        if let (false, Err(err)) = (own_password, staff.require(Permission::Administration)) {
+            eprintln!("{}", capitalize(&err.to_string()));
+            return 1;
+        }
+        staff.name
+    };
+    let account_json = |staff: &Staff| serde_json::json!({ "name": staff.name, "role": staff.role.to_string() });
+
+    // a change gives its audit trail operation with the account before and after it
+    let result = match (action, name) {
+        ("list", _) => {
+            for account in &accounts {
+                println!("{:<24} {}", account.name, account.role);
+            }
+            Ok(None)
+        },
+        ("add", Some(name)) => {
+            let role = match cli::option(args, "--role") {
+                None if first_account => Some(Role::Admin),
+                None => Some(Role::Librarian),
+                Some(role) => Role::parse(&role)
+            };
+            match role {
+                None => Err(t!("staff.invalid_role")),
+                Some(role) if first_account && role != Role::Admin => Err(t!("staff.first_not_admin")),
+                Some(role) => match read_new_password() {
+                    None => Err(t!("staff.password_not_set")),
+                    Some(password) => auth::add_staff(name, role, &password).map(|staff| {
+                        println!("#[ {} ]#", t!("staff.added", name = staff.name, role = staff.role));
+                        Some(("add staff", None, Some(account_json(&staff))))
+                    })
+                }
+            }
+        },
+        ("passwd", Some(name)) => match read_new_password() {
+            None => Err(t!("staff.password_not_set")),
+            Some(password) => auth::set_password(name, &password).map(|_| {
+                println!("#[ {} ]#", t!("staff.password_changed", name = name));
+                Some(("change password", None, None))
+            })
+        },
+        ("remove", Some(name)) => {
+            let before = accounts.iter().find(|s| s.is_named(name)).map(account_json);
+            auth::remove_staff(name).map(|_| {
+                println!("#[ {} ]#", t!("staff.removed", name = name));
+                Some(("remove staff", before, None))
+            })
+        },
+        ("token", Some(name)) => match accounts.iter().find(|s| s.is_named(name)) {
+            None => Err(t!("staff.no_account", name = name)),
+            Some(staff) => auth::issue_token(&staff.name, clock.today())
+                .map(|token| {
+                    println!("{}", token);
+                    println!("#[ {} ]#", t!("staff.token", name = staff.name, role = staff.role));
+                    Some(("issue token", None, None))
+                })
+                .map_err(|e| e.to_string())
+        },
+        ("revoke", Some(name)) => auth::revoke_tokens(name)
+            .map(|count| {
+                println!("#[ {} ]#", t!("staff.revoked", count = count, name = name));
+                Some(("revoke tokens", None, Some(serde_json::json!({ "revoked": count }))))
+            })
+            .map_err(|e| e.to_string()),
+        _ => Err(t!("cli.usage", usage = "staff [list | add NAME --role admin|librarian|kiosk | passwd NAME | remove NAME | token NAME | revoke NAME]"))
+    };
+    let result = result.and_then(|change| match change {
+        None => Ok(()),
+        Some((operation, before, after)) => audit::record(&actor, operation, &format!("staff {}", name.unwrap_or("").trim()), before, after)
+            .map_err(|e| t!("cli.audit_failed", error = e))
+    });
+    match result {
+        Err(err) => {
+            eprintln!("{}", capitalize(&err));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+// audit [--from DATE] [--to DATE] [--staff NAME] [--record TEXT] [--format text|json]
+fn run_audit(args: &[String]) -> i32 {
+    let (from, to) = match (date_option(args, "--from"), date_option(args, "--to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let filter = audit::Filter { from, to, staff: cli::option(args, "--staff"), record: cli::option(args, "--record") };
+    let entries = match audit::load_entries() {
+        Err(err) => {
+            eprintln!("{}", t!("audit.unreadable", error = err));
+            return 1;
+        },
+        Ok(entries) => entries
+    };
+    // a broken chain is reported but the entries are still shown
+    if let Some(line) = audit::verify(&entries) {
+        eprintln!("{}", t!("audit.changed", line = line));
+    }
+    let found: Vec<&audit::AuditEntry> = entries.iter().filter(|e| filter.matches(e)).collect();
+    match cli::option(args, "--format").as_deref().unwrap_or("text") {
+        "text" => {
+            let value = |v: &Option<serde_json::Value>| v.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+            for entry in &found {
+                println!("{}  {:<16} {:<16} {}", entry.time.format("%Y-%m-%d %H:%M:%S"), entry.staff, entry.operation, entry.record);
+                if entry.before.is_some() {
+                    println!("    {} : {}", i18n::pad(&t!("audit.before"), 6), value(&entry.before));
+                }
+                if entry.after.is_some() {
+                    println!("    {} : {}", i18n::pad(&t!("audit.after"), 6), value(&entry.after));
+                }
+            }
+            println!("#[ {} ]#", t!("audit.found", found = found.len(), count = entries.len()));
+        },
+        "json" => match serde_json::to_string_pretty(&found) {
+            Err(err) => {
+                eprintln!("{}", t!("cli.json_failed", error = err));
+                return 1;
+            },
+            Ok(json) => println!("{}", json)
+        },
+        other => {
+            eprintln!("{}", t!("cli.unknown_format", format = other, formats = "text, json"));
+            return 1;
+        }
+    }
+    0
+}
+
+// desk checkout | checkin, scanning without the menu
+fn run_desk(args: &[String], clock: &dyn Clock, config: &Config, staff: &Staff) -> i32 {
+    match cli::positionals(args).get(1).copied() {
+        Some("checkout") => checkout_desk(clock, config, staff),
+        Some("checkin") => checkin_desk(clock, config, staff),
+        _ => {
+            eprintln!("{}", t!("cli.usage", usage = "desk checkout | checkin"));
+            return 1;
+        }
+    }
+    0
+}
+
+// barcodes assign, numbers the copies and library cards that have none
+fn run_barcodes(args: &[String], staff: &Staff) -> i32 {
+    if cli::positionals(args).get(1).copied() != Some("assign") {
+        eprintln!("{}", t!("cli.usage", usage = "barcodes assign"));
+        return 1;
+    }
+    match library::assign_barcodes(staff) {
+        Err(err) => {
+            eprintln!("{}", capitalize(&err.to_string()));
+            1
+        },
+        Ok((copies, cards)) => {
+            println!("#[ {} ]#", t!("barcodes.assigned", copies = copies, cards = cards));
+            0
+        }
+    }
+}
+
+// copy numbers like 0,2-5 as shown in the book list, every number has to be
+// a copy of the library store, which has `copies` of them
+fn parse_copies(spec: &str, copies: usize) -> Result<Vec<usize>, String> {
+    let number = |text: &str| -> Result<usize, String> {
+        let n: usize = text.trim().parse().map_err(|_| t!("labels.not_a_copy", text = text.trim()))?;
+        if n < copies {
+            Ok(n)
+        } else if copies == 0 {
+            Err(t!("labels.no_copies"))
+        } else {
+            Err(t!("labels.no_such_copy", number = n, last = copies - 1))
+        }
+    };
+    let mut numbers = vec![];
+    for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            None => numbers.push(number(part)?),
+            Some((from, to)) => {
+                let (from, to) = (number(from)?, number(to)?);
+                if from > to {
+                    return Err(t!("labels.reversed", from = from, to = to));
+                }
+                numbers.extend(from..=to);
+            }
+        }
+    }
+    numbers.sort_unstable();
+    numbers.dedup();
+    Ok(numbers)
+}
+
+// labels items|cards [FILE|DIR] [--format html|svg] [--search TEXT] [--copies 0,2-5] [--symbology code128|ean13]
+fn run_labels(args: &[String]) -> i32 {
+    let positionals = cli::positionals(args);
+    let path = positionals.get(2).map(|p| Path::new(*p).to_path_buf());
+    let symbology = match cli::option(args, "--symbology").map(|s| barcode::Symbology::parse(&s)) {
+        None => barcode::Symbology::Code128,
+        Some(Some(symbology)) => symbology,
+        Some(None) => {
+            eprintln!("{}", t!("labels.unknown_symbology"));
+            return 1;
+        }
+    };
+    let svg = match cli::option(args, "--format").as_deref() {
+        None | Some("html") => false,
+        Some("svg") => true,
+        Some(_) => {
+            eprintln!("{}", t!("labels.unknown_format"));
+            return 1;
+        }
+    };
+    let search = cli::option(args, "--search");
+
+    let data = match library::read_store() {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(data) => data
+    };
+    let books = match to_book_list(&data) {
+        Err(err) => {
+            eprintln!("{}", t!("cli.store_unreadable", error = err));
+            return 1;
+        },
+        Ok(books) => books
+    };
+    let patrons = match patron::load_patrons() {
+        Err(err) => {
+            eprintln!("{}", t!("patrons.unreadable", error = err));
+            return 1;
+        },
+        Ok(patrons) => patrons
+    };
+    // (file name, svg) of every record and the sheet, or what could not be printed
+    let (files, sheet, problems): (Vec<(String, Result<String, String>)>, _, _) = match positionals.get(1).copied() {
+        Some("items") => {
+            let books: Vec<Book> = match cli::option(args, "--copies").map(|spec| parse_copies(&spec, books.len())) {
+                Some(Err(err)) => {
+                    eprintln!("{}", capitalize(&err));
+                    return 1;
+                },
+                Some(Ok(numbers)) => books.into_iter().enumerate()
+                    .filter(|(i, _)| numbers.binary_search(i).is_ok())
+                    .map(|(_, book)| book)
+                    .collect(),
+                None => match &search {
+                    Some(text) => search_books(books, text),
+                    None => books
+                }
+            };
+            let files = books.iter()
+                .map(|b| (labels::item_code(b, symbology).unwrap_or_default(), labels::item_svg(b, symbology)))
+                .collect();
+            match labels::item_sheet(&books, symbology) {
+                Err(err) => (files, Err(err), vec![]),
+                Ok((sheet, problems)) => (files, Ok(sheet), problems)
+            }
+        },
+        Some("cards") => {
+            let patrons: Vec<Patron> = patrons.into_iter()
+                .filter(|p| search.as_ref().is_none_or(|text| collation::contains(&p.name, text)))
+                .collect();
+            let files = patrons.iter().map(|p| (p.card.clone(), labels::card_svg(p))).collect();
+            match labels::card_sheet(&patrons) {
+                Err(err) => (files, Err(err), vec![]),
+                Ok((sheet, problems)) => (files, Ok(sheet), problems)
+            }
+        },
+        _ => {
+            eprintln!("{}", t!("cli.usage", usage = "labels items|cards [FILE|DIR] [--format html|svg] [--search TEXT] [--copies 0,2-5] [--symbology code128|ean13]"));
+            return 1;
+        }
+    };
+
+    if svg {
+        let dir = match path {
+            Some(dir) => dir,
+            None => {
+                eprintln!("{}", t!("labels.no_dir"));
+                return 1;
+            }
+        };
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            eprintln!("{}", t!("labels.dir_failed", path = dir.display(), error = err));
+            return 1;
+        }
+        let mut written = 0;
+        for (name, svg) in files {
+            match svg {
+                Err(err) => eprintln!("{}", capitalize(&err)),
+                Ok(svg) => {
+                    let file = dir.join(format!("{}.svg", name));
+                    if let Err(err) = File::create(&file).and_then(|mut f| f.write_all(svg.as_bytes())) {
+                        eprintln!("{}", t!("cli.file_unwritable", path = file.display(), error = err));
+                        return 1;
+                    }
+                    written += 1;
+                }
+            }
+        }
+        println!("#[ {} ]#", t!("labels.svg_written", count = written, path = dir.display()));
+        return 0;
+    }
+
+    for problem in &problems {
+        eprintln!("{}", capitalize(problem));
+    }
+    let sheet = match sheet {
+        Err(err) => {
+            eprintln!("{}", t!("labels.template_unreadable", error = err));
+            return 1;
+        },
+        Ok(sheet) => sheet
+    };
+    let count = files.len() - problems.len();
+    match path {
+        None => match io::stdout().write_all(sheet.as_bytes()) {
+            Err(err) => {
+                eprintln!("{}", t!("labels.failed", error = err));
+                1
+            },
+            Ok(_) => 0
+        },
+        Some(path) => match File::create(&path).and_then(|mut f| f.write_all(sheet.as_bytes())) {
+            Err(err) => {
+                eprintln!("{}", t!("cli.file_unwritable", path = path.display(), error = err));
+                1
+            },
+            Ok(_) => {
+                println!("#[ {} ]#", t!("labels.written", count = count, path = path.display()));
+                0
+            }
+        }
+    }
+}
+
+// receipt PATRON [FILE] [--date YYYY-MM-DD] [--format text|html]
+fn run_receipt(args: &[String], clock: &dyn Clock, config: &Config) -> i32 {
+    let positionals = cli::positionals(args);
+    let patron = match positionals.get(1) {
+        Some(patron) => *patron,
+        None => {
+            eprintln!("{}", t!("cli.usage", usage = "receipt PATRON [FILE] [--date YYYY-MM-DD] [--format text|html]"));
+            return 1;
+        }
+    };
+    let date = match cli::option(args, "--date") {
+        None => clock.today(),
+        Some(date) => match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+            Err(_) => {
+                eprintln!("{}", t!("input.invalid_date"));
+                return 1;
+            },
+            Ok(date) => date
+        }
+    };
+    let format = match cli::option(args, "--format").map(|f| receipt::ReceiptFormat::parse(&f)) {
+        None => receipt::ReceiptFormat::Text,
+        Some(Some(format)) => format,
+        Some(None) => {
+            eprintln!("{}", t!("receipt.unknown_format"));
+            return 1;
+        }
+    };
+    let loans = match loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY)) {
+        Err(err) => {
+            eprintln!("{}", t!("loans.unreadable", error = err));
+            return 1;
+        },
+        Ok(loans) => loans
+    };
+    let rendered = ledger::load_entries()
+        .and_then(|entries| {
+            let receipt = receipt::for_day(patron, date, &loans, &entries);
+            if receipt.is_empty() {
+                return Ok(None);
+            }
+            receipt::render(&receipt, format, ledger::balance(patron)?).map(Some)
+        });
+    let rendered = match rendered {
+        Err(err) => {
+            eprintln!("{}", t!("receipt.failed", error = err));
+            return 1;
+        },
+        Ok(None) => {
+            eprintln!("{}", t!("receipt.nothing", patron = patron, date = i18n::date(date)));
+            return 1;
+        },
+        Ok(Some(rendered)) => rendered
+    };
+    let result = match positionals.get(2) {
+        None => io::stdout().write_all(rendered.as_bytes()),
+        Some(path) => File::create(Path::new(path)).and_then(|mut f| f.write_all(rendered.as_bytes()))
+    };
+    match result {
+        Err(err) => {
+            eprintln!("{}", t!("receipt.failed", error = err));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+// trash [list | restore N | purge N | purge --all]
+fn run_trash(args: &[String], staff: &Staff) -> i32 {
+    let positionals = cli::positionals(args);
+    let number = positionals.get(2).map(|n| n.parse::<usize>());
+    let result = match (positionals.get(1).copied().unwrap_or("list"), number) {
+        ("list", _) => match trash::load_trash() {
+            Err(err) => Err(t!("trash.unreadable", error = err)),
+            Ok(trash) => {
+                for (i, withdrawn) in trash.iter().enumerate() {
+                    let name = withdrawn.book().map(|b| b.name.to_string()).unwrap_or_default();
+                    println!("[{}] {}  {:<32} {:<16} {}", i, withdrawn.date, name, withdrawn.staff, withdrawn.reason);
+                }
+                Ok(())
+            }
+        },
+        ("restore", Some(Ok(n))) => library::restore_book(n, staff)
+            .map(|number| println!("#[ {} ]#", t!("trash.restored", number = number)))
+            .map_err(|e| e.to_string()),
+        ("purge", Some(Ok(n))) => library::purge(Some(n), staff)
+            .map(|count| println!("#[ {} ]#", t!("trash.purged", count = count)))
+            .map_err(|e| e.to_string()),
+        ("purge", None) if cli::flag(args, "--all") => library::purge(None, staff)
+            .map(|count| println!("#[ {} ]#", t!("trash.purged", count = count)))
+            .map_err(|e| e.to_string()),
+        _ => Err(t!("cli.usage", usage = "trash [list | restore N | purge N | purge --all]"))
+    };
+    match result {
+        Err(err) => {
+            eprintln!("{}", capitalize(&err));
+            1
+        },
+        Ok(_) => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_lines_are_read_with_their_defaults() {
+        let books = to_book_list("Dune,Frank Herbert,1965,true,2024-01-03\n\nGolang,Alan Donovan,2015,false,2024-01-02,dvd,9780134190440,I00002\n").unwrap();
+        assert_eq!(books.len(), 2);
+        assert_eq!((books[0].name, books[0].borrowed, books[0].item_type, books[0].barcode), ("Dune", true, DEFAULT_ITEM_TYPE, ""));
+        assert_eq!((books[1].item_type, books[1].isbn, books[1].barcode), ("dvd", "9780134190440", "I00002"));
+    }
+
+    #[test]
+    fn short_line_is_an_error_that_names_it() {
+        let err = to_book_list("Dune,Frank Herbert,1965,true,2024-01-03\nGolang,Alan Donovan\n").err().unwrap();
+        assert!(matches!(err, LibraryError::Damaged(_)));
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn copy_ranges_are_expanded() {
+        assert_eq!(parse_copies("0, 2-4,3", 5).unwrap(), [0, 2, 3, 4]);
+        assert_eq!(parse_copies("", 5).unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn copy_ranges_outside_the_store_are_refused() {
+        assert!(parse_copies("0-18446744073709551615", 5).unwrap_err().contains("there is no copy [18446744073709551615]"));
+        assert!(parse_copies("5", 5).is_err());
+        assert!(parse_copies("4-2", 5).unwrap_err().contains("reversed"));
+        assert!(parse_copies("two", 5).is_err());
+        assert!(parse_copies("0", 0).is_err());
+    }
+
+    #[test]
+    fn bad_issue_date_is_an_error() {
+        assert!(matches!(to_book_list("Dune,Frank Herbert,1965,true,2024-13-03\n"), Err(LibraryError::Damaged(_))));
+    }
+}
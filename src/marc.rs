@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use crate::backup::BookRecord;
+use crate::i18n::t;
+use crate::template;
+
+/**
+ * MARC 21 bibliographic records, as binary MARC (ISO 2709) or MARCXML.
+ *
+ * A title of the library store maps to:
+ *
+ *   leader/06  type of record, `a` for books (see ITEM_TYPES)
+ *   001        control number
+ *   008        date entered on file and date 1 (year published)
+ *   020 $a     ISBN
+ *   100 $a     author
+ *   245 $a     title
+ *   264 $c     year published (260 $c is read too)
+ *   942 $c     item type, as used by Koha
+ *   952 $d     one field per copy with its acquisition date, as used by Koha
+ *
+ * An imported record adds one copy per 952 field, all with the first date.
+ * Binary MARC has room for fields up to 9999 bytes and records up to 99999
+ * bytes, a title that does not fit is an error, MARCXML has no such limit.
+ * Records are written in UTF-8 (leader/09 `a`), MARC-8 records are read as
+ * UTF-8 so only their ASCII text comes through unchanged.
+ */
+const FIELD_TERMINATOR: u8 = 0x1E;
+const RECORD_TERMINATOR: u8 = 0x1D;
+const SUBFIELD_DELIMITER: u8 = 0x1F;
+const MARCXML_NAMESPACE: &str = "http://www.loc.gov/MARC21/slim";
+
+// item type of the library store -> MARC type of record (leader/06)
+const ITEM_TYPES: &[(&str, char)] = &[
+    ("book", 'a'),
+    ("manuscript", 't'),
+    ("map", 'e'),
+    ("score", 'c'),
+    ("audiobook", 'i'),
+    ("cd", 'j'),
+    ("dvd", 'g'),
+    ("software", 'm'),
+    ("kit", 'o')
+];
+
+#[derive(Clone, Debug)]
+pub enum Field {
+    Control { tag: String, value: String },
+    Data { tag: String, indicators: [char; 2], subfields: Vec<(char, String)> }
+}
+
+impl Field {
+    pub fn tag(&self) -> &str {
+        match self {
+            Field::Control { tag, .. } | Field::Data { tag, .. } => tag
+        }
+    }
+
+    fn data(tag: &str, indicators: [char; 2], subfields: &[(char, &str)]) -> Field {
+        Field::Data {
+            tag: tag.to_string(),
+            indicators,
+            subfields: subfields.iter().map(|(c, v)| (*c, v.to_string())).collect()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub leader: String,
+    pub fields: Vec<Field>
+}
+
+impl Record {
+    pub fn control(&self, tag: &str) -> Option<&str> {
+        self.fields.iter().find_map(|f| match f {
+            Field::Control { tag: t, value } if t == tag => Some(value.as_str()),
+            _ => None
+        })
+    }
+
+    // every value of the subfield in the fields with this tag
+    pub fn subfields(&self, tag: &str, code: char) -> Vec<&str> {
+        self.fields.iter()
+            .filter_map(|f| match f {
+                Field::Data { tag: t, subfields, .. } if t == tag => Some(subfields),
+                _ => None
+            })
+            .flat_map(|subfields| subfields.iter().filter(|(c, _)| *c == code).map(|(_, v)| v.as_str()))
+            .collect()
+    }
+
+    pub fn subfield(&self, tag: &str, code: char) -> Option<&str> {
+        self.subfields(tag, code).first().copied()
+    }
+}
+
+// ISBD punctuation left at the end of a subfield, "Golang /" -> "Golang"
+fn strip_punctuation(value: &str) -> &str {
+    value.trim().trim_end_matches([' ', '/', ':', ';', ',', '.', '=']).trim()
+}
+
+// "c2019." or "[2019?]" -> "2019"
+fn year_of(value: &str) -> String {
+    value.chars().filter(|c| c.is_ascii_digit()).take(4).collect()
+}
+
+pub fn from_title(book: &BookRecord, control_number: usize) -> Record {
+    let type_of_record = ITEM_TYPES.iter()
+        .find(|(t, _)| *t == book.item_type)
+        .map(|(_, c)| *c)
+        .unwrap_or('a');
+    let entered = book.copies.iter().map(|c| c.issue_date).min();
+    // 008/00-05 date entered, 06 single date, 07-10 date 1, 15-17 place, 35-37 language
+    let fixed = format!("{}s{:<4}    xx {}und d",
+        entered.map(|d| d.format("%y%m%d").to_string()).unwrap_or_else(|| "000000".to_string()),
+        book.year_published, " ".repeat(17));
+
+    let mut fields = vec![
+        Field::Control { tag: "001".to_string(), value: control_number.to_string() },
+        Field::Control { tag: "008".to_string(), value: fixed }
+    ];
+    if !book.isbn.is_empty() {
+        fields.push(Field::data("020", [' ', ' '], &[('a', &book.isbn)]));
+    }
+    fields.push(Field::data("100", ['1', ' '], &[('a', &book.author)]));
+    fields.push(Field::data("245", ['1', '0'], &[('a', &book.name)]));
+    fields.push(Field::data("264", [' ', '1'], &[('c', &book.year_published.to_string())]));
+    fields.push(Field::data("942", [' ', ' '], &[('c', &book.item_type)]));
+    for copy in &book.copies {
+        fields.push(Field::data("952", [' ', ' '], &[('d', &copy.issue_date.to_string()), ('y', &book.item_type)]));
+    }
+    Record { leader: format!("00000n{}m a2200000 a 4500", type_of_record), fields }
+}
+
+// the record as an import record, keys are the import field names
+pub fn to_import_record(record: &Record) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    let mut put = |field: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            values.insert(field.to_string(), value.to_string());
+        }
+    };
+    put("name", record.subfield("245", 'a').map(strip_punctuation));
+    put("author", record.subfield("100", 'a')
+        .or_else(|| record.subfield("110", 'a'))
+        .or_else(|| record.subfield("700", 'a'))
+        .map(strip_punctuation));
+    let year = record.subfield("264", 'c')
+        .or_else(|| record.subfield("260", 'c'))
+        .map(year_of)
+        .filter(|y| !y.is_empty())
+        .or_else(|| record.control("008").and_then(|f| f.get(7..11)).map(year_of));
+    put("year", year.as_deref());
+    // "9780131103627 (pbk.)" -> "9780131103627"
+    put("isbn", record.subfield("020", 'a').and_then(|i| i.split_whitespace().next()));
+    let item_type = record.subfield("942", 'c').map(|t| t.to_lowercase()).or_else(|| {
+        let type_of_record = record.leader.chars().nth(6)?;
+        ITEM_TYPES.iter().find(|(_, c)| *c == type_of_record).map(|(t, _)| t.to_string())
+    });
+    put("item_type", item_type.as_deref());
+    let copies = record.subfields("952", 'd');
+    put("issue_date", copies.first().copied());
+    if !copies.is_empty() {
+        put("copies", Some(&copies.len().to_string()));
+    }
+    values
+}
+
+// a field over 9999 bytes or a record over 99999 bytes has no room in the directory
+// and the leader, it is an error rather than a corrupt record
+const MAX_FIELD_LENGTH: usize = 9999;
+const MAX_RECORD_LENGTH: usize = 99999;
+
+fn write_one(record: &Record, index: usize) -> Result<Vec<u8>, String> {
+    let invalid = |reason: String| t!("marc.not_binary", number = index + 1, reason = reason);
+    let leader = record.leader.as_bytes();
+    if leader.len() != 24 || !record.leader.is_ascii() {
+        return Err(invalid(t!("marc.leader", leader = record.leader)));
+    }
+    let mut directory = vec![];
+    let mut data = vec![];
+    for field in &record.fields {
+        let tag = field.tag();
+        if tag.len() != 3 || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid(t!("marc.tag", tag = tag)));
+        }
+        let start = data.len();
+        match field {
+            Field::Control { value, .. } => data.extend_from_slice(value.as_bytes()),
+            Field::Data { indicators, subfields, .. } => {
+                if let Some(c) = indicators.iter().find(|c| !c.is_ascii_graphic() && **c != ' ') {
+                    return Err(invalid(t!("marc.indicator", indicator = c, tag = tag)));
+                }
+                data.extend(indicators.iter().map(|c| *c as u8));
+                for (code, value) in subfields {
+                    if !code.is_ascii_graphic() {
+                        return Err(invalid(t!("marc.subfield_code", code = code, tag = tag)));
+                    }
+                    data.push(SUBFIELD_DELIMITER);
+                    data.push(*code as u8);
+                    data.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        data.push(FIELD_TERMINATOR);
+        let length = data.len() - start;
+        if length > MAX_FIELD_LENGTH {
+            return Err(invalid(t!("marc.field_length", tag = tag, length = length, limit = MAX_FIELD_LENGTH)));
+        }
+        directory.extend_from_slice(format!("{}{:04}{:05}", tag, length, start).as_bytes());
+    }
+    directory.push(FIELD_TERMINATOR);
+    data.push(RECORD_TERMINATOR);
+
+    let base_address = 24 + directory.len();
+    let length = base_address + data.len();
+    if length > MAX_RECORD_LENGTH {
+        return Err(invalid(t!("marc.record_length", length = length, limit = MAX_RECORD_LENGTH)));
+    }
+    let mut out = vec![];
+    out.extend_from_slice(format!("{:05}", length).as_bytes());
+    out.extend_from_slice(&leader[5..12]);
+    out.extend_from_slice(format!("{:05}", base_address).as_bytes());
+    out.extend_from_slice(&leader[17..24]);
+    out.extend(directory);
+    out.extend(data);
+    Ok(out)
+}
+
+pub fn write_binary(records: &[Record]) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    for (i, record) in records.iter().enumerate() {
+        out.extend(write_one(record, i)?);
+    }
+    Ok(out)
+}
+
+fn parse_number(bytes: &[u8]) -> Option<usize> {
+    std::str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
+
+fn read_one(raw: &[u8], index: usize) -> Result<Record, String> {
+    let invalid = |reason: &str| t!("marc.invalid", number = index + 1, reason = reason);
+    if raw.len() < 24 {
+        return Err(invalid(&t!("marc.short")));
+    }
+    let leader = String::from_utf8_lossy(&raw[..24]).to_string();
+    let base_address = parse_number(&raw[12..17]).ok_or_else(|| invalid(&t!("marc.base_address")))?;
+    if base_address > raw.len() || base_address < 25 {
+        return Err(invalid(&t!("marc.base_address")));
+    }
+    let directory = &raw[24..base_address - 1];
+    if !directory.len().is_multiple_of(12) {
+        return Err(invalid(&t!("marc.directory")));
+    }
+
+    let mut fields = vec![];
+    for entry in directory.chunks(12) {
+        let tag = String::from_utf8_lossy(&entry[..3]).to_string();
+        let length = parse_number(&entry[3..7]).ok_or_else(|| invalid(&t!("marc.field_length_bad")))?;
+        let start = parse_number(&entry[7..12]).ok_or_else(|| invalid(&t!("marc.field_position")))?;
+        let field = raw.get(base_address + start..base_address + start + length)
+            .ok_or_else(|| invalid(&t!("marc.outside", tag = tag)))?;
+        let field = field.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(field);
+        if tag.starts_with("00") {
+            fields.push(Field::Control { tag, value: String::from_utf8_lossy(field).to_string() });
+            continue;
+        }
+        let mut parts = field.split(|b| *b == SUBFIELD_DELIMITER);
+        let indicators: Vec<char> = parts.next().unwrap_or(&[]).iter().map(|b| *b as char).collect();
+        let subfields = parts
+            .filter(|s| !s.is_empty())
+            .map(|s| (s[0] as char, String::from_utf8_lossy(&s[1..]).to_string()))
+            .collect();
+        fields.push(Field::Data {
+            tag,
+            indicators: [*indicators.first().unwrap_or(&' '), *indicators.get(1).unwrap_or(&' ')],
+            subfields
+        });
+    }
+    Ok(Record { leader, fields })
+}
+
+pub fn read_binary(data: &[u8]) -> Result<Vec<Record>, String> {
+    data.split(|b| *b == RECORD_TERMINATOR)
+        .map(|raw| raw.strip_prefix(b"\n").unwrap_or(raw))
+        .filter(|raw| raw.iter().any(|b| !b.is_ascii_whitespace()))
+        .enumerate()
+        .map(|(i, raw)| read_one(raw, i))
+        .collect()
+}
+
+pub fn write_xml(records: &[Record]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<collection xmlns=\"{}\">\n", MARCXML_NAMESPACE));
+    for record in records {
+        out.push_str("  <record>\n");
+        out.push_str(&format!("    <leader>{}</leader>\n", template::escape_xml(&record.leader)));
+        for field in &record.fields {
+            match field {
+                Field::Control { tag, value } => out.push_str(&format!(
+                    "    <controlfield tag=\"{}\">{}</controlfield>\n", tag, template::escape_xml(value))),
+                Field::Data { tag, indicators, subfields } => {
+                    out.push_str(&format!("    <datafield tag=\"{}\" ind1=\"{}\" ind2=\"{}\">\n",
+                        tag, indicators[0], indicators[1]));
+                    for (code, value) in subfields {
+                        out.push_str(&format!("      <subfield code=\"{}\">{}</subfield>\n", code, template::escape_xml(value)));
+                    }
+                    out.push_str("    </datafield>\n");
+                }
+            }
+        }
+        out.push_str("  </record>\n");
+    }
+    out.push_str("</collection>\n");
+    out
+}
+
+pub fn read_xml(data: &str) -> Result<Vec<Record>, String> {
+    let mut reader = Reader::from_str(data);
+    reader.config_mut().trim_text(true);
+    let mut records = vec![];
+    let mut record: Option<Record> = None;
+    // element being read and its attributes
+    let mut current: Option<(String, HashMap<String, String>)> = None;
+    let mut text = String::new();
+
+    loop {
+        let event = reader.read_event()
+            .map_err(|e| t!("marc.xml_at", position = reader.error_position(), error = e))?;
+        match event {
+            Event::Start(e) | Event::Empty(e) if matches!(e.local_name().as_ref(), b"record") => {
+                record = Some(Record { leader: String::new(), fields: vec![] });
+            },
+            Event::Start(e) => {
+                let mut attributes = HashMap::new();
+                for attribute in e.attributes().flatten() {
+                    let value = attribute.unescape_value().map_err(|e| t!("marc.xml", error = e))?;
+                    attributes.insert(String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(), value.to_string());
+                }
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if name == "datafield" {
+                    if let Some(record) = record.as_mut() {
+                        let indicator = |key: &str| attributes.get(key).and_then(|i| i.chars().next()).unwrap_or(' ');
+                        record.fields.push(Field::Data {
+                            tag: attributes.get("tag").cloned().unwrap_or_default(),
+                            indicators: [indicator("ind1"), indicator("ind2")],
+                            subfields: vec![]
+                        });
+                    }
+                }
+                current = Some((name, attributes));
+                text.clear();
+            },
+            Event::Text(t) => text.push_str(&t.unescape().map_err(|e| t!("marc.xml", error = e))?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            Event::End(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                match (name.as_str(), record.as_mut(), current.take()) {
+                    ("record", Some(_), _) => records.extend(record.take()),
+                    ("leader", Some(record), _) => record.leader = format!("{:<24}", text),
+                    ("controlfield", Some(record), Some((_, attributes))) => record.fields.push(Field::Control {
+                        tag: attributes.get("tag").cloned().unwrap_or_default(),
+                        value: text.clone()
+                    }),
+                    ("subfield", Some(record), Some((_, attributes))) => {
+                        let code = attributes.get("code").and_then(|c| c.chars().next()).unwrap_or(' ');
+                        if let Some(Field::Data { subfields, .. }) = record.fields.last_mut() {
+                            subfields.push((code, text.clone()));
+                        }
+                    },
+                    _ => ()
+                }
+                text.clear();
+            },
+            Event::Eof => break,
+            _ => ()
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::BookCopy;
+    use crate::clock::day;
+
+    fn title(name: &str, copies: usize) -> BookRecord {
+        let copy = BookCopy {
+            borrowed: false,
+            issue_date: day(2024, 1, 3),
+            barcode: String::new(),
+            line: None
+        };
+        BookRecord {
+            name: name.to_string(),
+            author: "Frank Herbert".to_string(),
+            year_published: 1965,
+            item_type: "book".to_string(),
+            isbn: "9780441013593".to_string(),
+            copies: vec![copy; copies]
+        }
+    }
+
+    #[test]
+    fn binary_record_is_read_back() {
+        let data = write_binary(&[from_title(&title("Dune", 2), 1)]).unwrap();
+        let records = read_binary(&data).unwrap();
+        assert_eq!(records.len(), 1);
+        let fields = to_import_record(&records[0]);
+        assert_eq!(fields["name"], "Dune");
+        assert_eq!(fields["author"], "Frank Herbert");
+        assert_eq!(fields["year"], "1965");
+        assert_eq!(fields["isbn"], "9780441013593");
+        assert_eq!(fields["copies"], "2");
+        assert_eq!(&data[..5], format!("{:05}", data.len()).as_bytes());
+    }
+
+    #[test]
+    fn field_over_9999_bytes_is_an_error() {
+        let err = write_binary(&[from_title(&title(&"x".repeat(MAX_FIELD_LENGTH), 1), 1)]).unwrap_err();
+        assert!(err.contains("field 245"));
+    }
+
+    #[test]
+    fn record_over_99999_bytes_is_an_error() {
+        let err = write_binary(&[from_title(&title("Dune", 4000), 1)]).unwrap_err();
+        assert!(err.contains("Record 1"));
+        assert!(write_xml(&[from_title(&title("Dune", 4000), 1)]).contains("Dune"));
+    }
+
+    #[test]
+    fn subfield_code_has_to_be_ascii() {
+        let mut record = from_title(&title("Dune", 1), 1);
+        record.fields.push(Field::data("500", [' ', ' '], &[('é', "note")]));
+        assert!(write_binary(&[record]).unwrap_err().contains("subfield code 'é'"));
+    }
+}
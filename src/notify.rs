@@ -0,0 +1,366 @@
+use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+use std::fmt;
+use chrono::NaiveDate;
+use crate::collation;
+use crate::hold::Hold;
+use crate::i18n::{self, t};
+use crate::ledger;
+use crate::loan::Loan;
+use crate::patron::Patron;
+use crate::smtp::SmtpClient;
+use crate::template;
+
+/**
+ * E-mail reminders for loans due soon, overdue loans and holds ready for pickup.
+ *
+ * Every item that was mailed is written to the `sentlog` file:
+ *
+ *   kind,patron,book name,date,sent on
+ *
+ * date is the due date (or pickup date of a hold) the reminder was about,
+ * so a patron gets one reminder per due date, and a new one after a renewal.
+ * Patrons without an e-mail or that opted out are never mailed.
+ */
+const SENT_LOG_PATH: &str = "sentlog";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NoticeKind {
+    DueSoon,
+    Overdue,
+    HoldReady
+}
+
+impl NoticeKind {
+    pub fn parse(s: &str) -> Option<NoticeKind> {
+        match s.trim() {
+            "due_soon" => Some(NoticeKind::DueSoon),
+            "overdue" => Some(NoticeKind::Overdue),
+            "hold_ready" => Some(NoticeKind::HoldReady),
+            _ => None
+        }
+    }
+
+    fn template(self) -> (&'static str, &'static str) {
+        match self {
+            NoticeKind::DueSoon => ("due_soon_email.eml", include_str!("../templates/due_soon_email.eml")),
+            NoticeKind::Overdue => ("overdue_email.eml", include_str!("../templates/overdue_email.eml")),
+            NoticeKind::HoldReady => ("hold_ready_email.eml", include_str!("../templates/hold_ready_email.eml"))
+        }
+    }
+}
+
+impl fmt::Display for NoticeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            NoticeKind::DueSoon => "due_soon",
+            NoticeKind::Overdue => "overdue",
+            NoticeKind::HoldReady => "hold_ready"
+        };
+        f.pad(s)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NoticeItem {
+    pub book_name: String,
+    // due date, or the last pickup date of a hold
+    pub date: NaiveDate,
+    pub days_late: i64,
+    pub fine: i64
+}
+
+#[derive(Clone, Debug)]
+pub struct Notice {
+    pub kind: NoticeKind,
+    pub patron: Patron,
+    pub items: Vec<NoticeItem>
+}
+
+#[derive(Clone, Debug)]
+pub struct SentEntry {
+    pub kind: NoticeKind,
+    pub patron: String,
+    pub book_name: String,
+    pub date: NaiveDate,
+    pub sent_on: NaiveDate
+}
+
+impl fmt::Display for SentEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{},{}", self.kind, self.patron, self.book_name, self.date, self.sent_on)
+    }
+}
+
+fn parse_sent(line: &str) -> Option<SentEntry> {
+    let slice_data: Vec<&str> = line.split(',').collect();
+    if slice_data.len() < 5 {
+        return None;
+    }
+    Some(SentEntry {
+        kind: NoticeKind::parse(slice_data[0])?,
+        patron: slice_data[1].trim().to_string(),
+        book_name: slice_data[2].trim().to_string(),
+        date: NaiveDate::parse_from_str(slice_data[3].trim(), "%Y-%m-%d").ok()?,
+        sent_on: NaiveDate::parse_from_str(slice_data[4].trim(), "%Y-%m-%d").ok()?
+    })
+}
+
+pub fn load_sent_log() -> io::Result<Vec<SentEntry>> {
+    let mut file = match File::open(Path::new(SENT_LOG_PATH)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+        Ok(file) => file
+    };
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    Ok(data.lines().filter_map(parse_sent).collect())
+}
+
+fn record_sent(notice: &Notice, today: NaiveDate) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(SENT_LOG_PATH))?;
+    for item in &notice.items {
+        write!(&mut file, "{}", SentEntry {
+            kind: notice.kind,
+            patron: notice.patron.name.clone(),
+            book_name: item.book_name.clone(),
+            date: item.date,
+            sent_on: today
+        })?;
+    }
+    Ok(())
+}
+
+fn already_sent(sent: &[SentEntry], kind: NoticeKind, patron: &str, book_name: &str, date: NaiveDate) -> bool {
+    sent.iter().any(|s| s.kind == kind
+        && collation::same(&s.patron, patron)
+        && s.book_name == book_name
+        && s.date == date)
+}
+
+/**
+ * Delivery of a rendered message, by SMTP or into the outbox directory for a dry run.
+ */
+pub trait Transport {
+    fn deliver(&mut self, notice: &Notice, message: &str) -> io::Result<()>;
+}
+
+pub struct SmtpTransport {
+    client: SmtpClient,
+    from: String
+}
+
+impl SmtpTransport {
+    pub fn connect(host: &str, port: u16, from: &str) -> io::Result<SmtpTransport> {
+        Ok(SmtpTransport { client: SmtpClient::connect(host, port)?, from: from.to_string() })
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        self.client.quit()
+    }
+}
+
+impl Transport for SmtpTransport {
+    fn deliver(&mut self, notice: &Notice, message: &str) -> io::Result<()> {
+        self.client.send(&self.from, &notice.patron.email, message)
+    }
+}
+
+pub struct OutboxTransport {
+    pub dir: PathBuf,
+    pub written: Vec<PathBuf>
+}
+
+impl Transport for OutboxTransport {
+    fn deliver(&mut self, notice: &Notice, message: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file_name = template::file_name(&notice.patron.name);
+        let path = self.dir.join(format!("{}-{}.eml", notice.kind, file_name));
+        File::create(&path)?.write_all(message.as_bytes())?;
+        self.written.push(path);
+        Ok(())
+    }
+}
+
+fn add_item(notices: &mut Vec<Notice>, kind: NoticeKind, patron: &Patron, item: NoticeItem) {
+    match notices.iter_mut().find(|n| n.kind == kind && n.patron.is_named(&patron.name)) {
+        Some(notice) => notice.items.push(item),
+        None => notices.push(Notice { kind, patron: patron.clone(), items: vec![item] })
+    }
+}
+
+// reminders that are due today and were not sent before, grouped per patron and kind
+pub fn collect(loans: &[Loan], holds: &[Hold], patrons: &[Patron], sent: &[SentEntry],
+    today: NaiveDate, due_soon_days: i64, fine_per_day: i64) -> Vec<Notice> {
+    let mailable = |name: &str| patrons.iter().find(|p| p.is_named(name) && p.notify && !p.email.is_empty());
+    let mut notices = vec![];
+
+    for loan in loans.iter().filter(|l| l.is_open()) {
+        let patron = match mailable(&loan.borrower) {
+            Some(patron) => patron,
+            None => continue
+        };
+        let days_left = loan.due_date.signed_duration_since(today).num_days();
+        let kind = if loan.is_overdue(today) {
+            NoticeKind::Overdue
+        } else if days_left <= due_soon_days {
+            NoticeKind::DueSoon
+        } else {
+            continue;
+        };
+        if already_sent(sent, kind, &patron.name, &loan.book_name, loan.due_date) {
+            continue;
+        }
+        let days_late = loan.days_overdue(today);
+        add_item(&mut notices, kind, patron, NoticeItem {
+            book_name: loan.book_name.clone(),
+            date: loan.due_date,
+            days_late,
+            fine: days_late * fine_per_day
+        });
+    }
+
+    for hold in holds {
+        let (patron, pickup_until) = match (mailable(&hold.patron), hold.pickup_until) {
+            (Some(patron), Some(date)) => (patron, date),
+            _ => continue
+        };
+        if already_sent(sent, NoticeKind::HoldReady, &patron.name, &hold.book_name, pickup_until) {
+            continue;
+        }
+        add_item(&mut notices, NoticeKind::HoldReady, patron, NoticeItem {
+            book_name: hold.book_name.clone(),
+            date: pickup_until,
+            days_late: 0,
+            fine: 0
+        });
+    }
+    notices
+}
+
+pub fn render(notice: &Notice, from: &str, today: NaiveDate) -> io::Result<String> {
+    let (name, builtin) = notice.kind.template();
+    let message_template = template::load(name, builtin)?;
+    let item_lines: Vec<String> = notice.items.iter()
+        .map(|i| match notice.kind {
+            NoticeKind::DueSoon => format!("  {}", t!("reminders.item_due", book = i.book_name, due = i18n::date(i.date))),
+            NoticeKind::Overdue => format!("  {}", t!("overdue.notice_item", book = i.book_name, due = i18n::date(i.date),
+                days = i.days_late, fine = ledger::format_money(i.fine))),
+            NoticeKind::HoldReady => format!("  {}", t!("reminders.item_pickup", book = i.book_name, until = i18n::date(i.date)))
+        })
+        .collect();
+    let message = template::render(&message_template, &[
+        ("patron", notice.patron.name.clone()),
+        ("email", notice.patron.email.clone()),
+        ("today", today.to_string()),
+        ("items", item_lines.join("\n")),
+        ("total_fine", ledger::format_money(notice.items.iter().map(|i| i.fine).sum()))
+    ]);
+    // names and titles can be in any language, the body is sent as UTF-8 as it is
+    Ok(format!("From: Rusty Library <{}>\nDate: {}\nMIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n{}",
+        from, today.format("%a, %d %b %Y 00:00:00 +0000"), message))
+}
+
+// deliver every notice, the sent log is only written for real deliveries (not dry runs),
+// returns the number of notices delivered and the errors of those that failed
+pub fn send_all(notices: &[Notice], transport: &mut dyn Transport, from: &str,
+    today: NaiveDate, log_sent: bool) -> (usize, Vec<String>) {
+    let mut delivered = 0;
+    let mut errors = vec![];
+    for notice in notices {
+        let result = render(notice, from, today)
+            .and_then(|message| transport.deliver(notice, &message))
+            .and_then(|_| if log_sent { record_sent(notice, today) } else { Ok(()) });
+        match result {
+            Err(err) => errors.push(format!("{} to {}, {}", notice.kind, notice.patron.name, err)),
+            Ok(_) => delivered += 1
+        }
+    }
+    (delivered, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn patron(name: &str, email: &str, notify: bool) -> Patron {
+        Patron { name: name.to_string(), category: "adult".to_string(), email: email.to_string(), notify, card: String::new() }
+    }
+
+    fn patrons() -> Vec<Patron> {
+        vec![patron("Kim", "kim@example.org", true), patron("Lee", "lee@example.org", false), patron("Ada", "", true)]
+    }
+
+    fn loans() -> Vec<Loan> {
+        ["Kim", "Lee", "Ada"].iter()
+            .map(|name| Loan::new(name, "Dune", day(2024, 3, 1), 14, "Sam"))
+            .collect()
+    }
+
+    fn sent(kind: NoticeKind, patron: &str, date: NaiveDate) -> SentEntry {
+        SentEntry { kind, patron: patron.to_string(), book_name: "Dune".to_string(), date, sent_on: day(2024, 3, 14) }
+    }
+
+    #[test]
+    fn patrons_without_an_email_or_that_opted_out_are_not_mailed() {
+        let notices = collect(&loans(), &[], &patrons(), &[], day(2024, 3, 14), 2, 50);
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].patron.name, "Kim");
+        assert_eq!(notices[0].kind, NoticeKind::DueSoon);
+
+        let notices = collect(&loans(), &[], &patrons(), &[], day(2024, 3, 18), 2, 50);
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].kind, NoticeKind::Overdue);
+        assert_eq!(notices[0].items[0].fine, 150);
+    }
+
+    #[test]
+    fn a_reminder_is_sent_once_per_due_date() {
+        let log = [sent(NoticeKind::DueSoon, "KIM", day(2024, 3, 15))];
+        assert!(collect(&loans(), &[], &patrons(), &log, day(2024, 3, 14), 2, 50).is_empty());
+        // the overdue notice is a reminder of its own
+        assert_eq!(collect(&loans(), &[], &patrons(), &log, day(2024, 3, 16), 2, 50).len(), 1);
+        // and a renewal gives the loan a new due date
+        let mut renewed = loans();
+        renewed[0].due_date = day(2024, 3, 29);
+        assert!(collect(&renewed, &[], &patrons(), &log, day(2024, 3, 14), 2, 50).is_empty());
+        assert_eq!(collect(&renewed, &[], &patrons(), &log, day(2024, 3, 28), 2, 50).len(), 1);
+    }
+
+    #[test]
+    fn ready_holds_are_mailed_until_their_pickup_date_is_logged() {
+        let mut hold = Hold::new("Dune", "Kim", day(2024, 3, 1));
+        hold.pickup_until = Some(day(2024, 3, 21));
+        let waiting = Hold::new("Dune", "Ada", day(2024, 3, 2));
+        let holds = [hold, waiting];
+        let notices = collect(&[], &holds, &patrons(), &[], day(2024, 3, 14), 2, 50);
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].kind, NoticeKind::HoldReady);
+        let log = [sent(NoticeKind::HoldReady, "Kim", day(2024, 3, 21))];
+        assert!(collect(&[], &holds, &patrons(), &log, day(2024, 3, 14), 2, 50).is_empty());
+    }
+
+    #[test]
+    fn message_is_plain_utf8_text() {
+        let notices = collect(&loans(), &[], &patrons(), &[], day(2024, 3, 14), 2, 50);
+        let message = render(&notices[0], "library@example.org", day(2024, 3, 14)).unwrap();
+        let headers: Vec<&str> = message.lines().take_while(|line| !line.is_empty()).collect();
+        assert!(headers.contains(&"MIME-Version: 1.0"));
+        assert!(headers.contains(&"Content-Type: text/plain; charset=utf-8"));
+        assert!(headers.contains(&"To: Kim <kim@example.org>"));
+        assert!(message.contains("\n  - Dune (due 2024-03-15)\n"));
+    }
+
+    #[test]
+    fn overdue_items_show_the_days_late_and_fine() {
+        let notices = collect(&loans(), &[], &patrons(), &[], day(2024, 3, 18), 2, 50);
+        let message = render(&notices[0], "library@example.org", day(2024, 3, 18)).unwrap();
+        assert!(message.contains("\n  - Dune (due 2024-03-15, 3 days late, fine $1.50)\n"));
+    }
+}
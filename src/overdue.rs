@@ -0,0 +1,187 @@
+use std::io;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+use std::cmp::Reverse;
+use chrono::NaiveDate;
+use crate::collation;
+use crate::i18n::{self, t};
+use crate::ledger;
+use crate::loan::Loan;
+use crate::patron::Patron;
+use crate::template;
+
+/**
+ * Overdue report of every open loan past its due date, and the reminder
+ * notices sent to the patrons that have them.
+ */
+const NOTICE_DIR: &str = "notices";
+
+#[derive(Clone, Debug)]
+pub struct OverdueItem {
+    pub patron: String,
+    pub book_name: String,
+    pub issue_date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub days_late: i64,
+    pub fine: i64
+}
+
+#[derive(Copy, Clone)]
+pub enum SortBy {
+    DaysLate,
+    Patron,
+    Title,
+    Fine
+}
+
+#[derive(Copy, Clone)]
+pub enum NoticeFormat {
+    Letter,
+    Email
+}
+
+impl NoticeFormat {
+    fn template(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            NoticeFormat::Letter => ("overdue_letter.txt", include_str!("../templates/overdue_letter.txt"), "txt"),
+            NoticeFormat::Email => ("overdue_email.eml", include_str!("../templates/overdue_email.eml"), "eml")
+        }
+    }
+}
+
+pub fn overdue_items(loans: &[Loan], today: NaiveDate, fine_per_day: i64) -> Vec<OverdueItem> {
+    loans.iter()
+        .filter(|l| l.is_open() && l.is_overdue(today))
+        .map(|l| OverdueItem {
+            patron: l.borrower.clone(),
+            book_name: l.book_name.clone(),
+            issue_date: l.issue_date,
+            due_date: l.due_date,
+            days_late: l.days_overdue(today),
+            fine: l.days_overdue(today) * fine_per_day
+        })
+        .collect()
+}
+
+// longest overdue and biggest fine first, names alphabetically
+pub fn sort(items: &mut [OverdueItem], sort_by: SortBy) {
+    match sort_by {
+        SortBy::DaysLate => items.sort_by_key(|a| Reverse(a.days_late)),
+        SortBy::Patron => items.sort_by_cached_key(|a| collation::sort_key(&a.patron)),
+        SortBy::Title => items.sort_by_cached_key(|a| collation::sort_key(&a.book_name)),
+        SortBy::Fine => items.sort_by_key(|a| Reverse(a.fine))
+    }
+}
+
+// quotes a field that holds a comma or a quote, shared by the csv exports
+pub fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(items: &[OverdueItem]) -> String {
+    let mut data = String::from("patron,book name,issue date,due date,days late,fine\n");
+    for item in items {
+        data.push_str(&format!("{},{},{},{},{},{}\n", 
+            csv_field(&item.patron), 
+            csv_field(&item.book_name), 
+            item.issue_date, 
+            item.due_date, 
+            item.days_late, 
+            ledger::plain_money(item.fine)));
+    }
+    data
+}
+
+pub fn export_csv(items: &[OverdueItem], path: &Path) -> io::Result<()> {
+    File::create(path)?.write_all(to_csv(items).as_bytes())
+}
+
+// one notice per patron rendered from the letter or e-mail template,
+// returns the files that were written
+pub fn generate_notices(items: &[OverdueItem], patrons: &[Patron], format: NoticeFormat, today: NaiveDate) -> io::Result<Vec<PathBuf>> {
+    let (name, builtin, extension) = format.template();
+    let notice_template = template::load(name, builtin)?;
+    fs::create_dir_all(NOTICE_DIR)?;
+
+    let mut names: Vec<String> = items.iter().map(|i| collation::normalize(&i.patron)).collect();
+    names.sort();
+    names.dedup();
+
+    let mut written = vec![];
+    for patron in names {
+        let patron_items: Vec<&OverdueItem> = items.iter().filter(|i| collation::normalize(&i.patron) == patron).collect();
+        let item_lines: Vec<String> = patron_items.iter()
+            .map(|i| format!("  {}", t!("overdue.notice_item", book = i.book_name, due = i18n::date(i.due_date),
+                days = i.days_late, fine = ledger::format_money(i.fine))))
+            .collect();
+        let email = patrons.iter()
+            .find(|p| p.is_named(&patron))
+            .map(|p| p.email.clone())
+            .unwrap_or_default();
+        let notice = template::render(&notice_template, &[
+            ("patron", patron_items[0].patron.clone()),
+            ("email", email),
+            ("today", today.to_string()),
+            ("items", item_lines.join("\n")),
+            ("total_fine", ledger::format_money(patron_items.iter().map(|i| i.fine).sum()))
+        ]);
+
+        let file_name = template::file_name(&patron_items[0].patron);
+        let path = Path::new(NOTICE_DIR).join(format!("{}-{}.{}", file_name, today, extension));
+        File::create(&path)?.write_all(notice.as_bytes())?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn loans() -> Vec<Loan> {
+        let mut returned = Loan::new("Ada", "Rust", day(2024, 1, 1), 14, "Lee");
+        returned.close(day(2024, 1, 20), 250, "Lee");
+        vec![
+            Loan::new("Kim", "Golang", day(2024, 1, 1), 14, "Lee"),
+            Loan::new("Ada", "Foundation", day(2024, 1, 20), 14, "Lee"),
+            Loan::new("Lee", "Dune", day(2024, 1, 10), 14, "Lee"),
+            returned
+        ]
+    }
+
+    #[test]
+    fn fine_is_the_days_late_times_the_daily_fine() {
+        let today = day(2024, 2, 1);
+        let items = overdue_items(&loans(), today, 50);
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].book_name.as_str(), items[0].days_late, items[0].fine), ("Golang", 17, 850));
+        assert_eq!((items[1].book_name.as_str(), items[1].days_late, items[1].fine), ("Dune", 8, 400));
+    }
+
+    #[test]
+    fn nothing_is_overdue_on_the_due_date() {
+        let today = day(2024, 1, 15);
+        assert!(overdue_items(&loans(), today, 50).is_empty());
+    }
+
+    #[test]
+    fn longest_overdue_comes_first() {
+        let mut items = overdue_items(&loans(), day(2024, 3, 1), 50);
+        sort(&mut items, SortBy::Patron);
+        assert_eq!(items.iter().map(|i| i.patron.as_str()).collect::<Vec<_>>(), ["Ada", "Kim", "Lee"]);
+        sort(&mut items, SortBy::DaysLate);
+        assert_eq!(items.iter().map(|i| i.days_late).collect::<Vec<_>>(), [46, 37, 27]);
+    }
+
+    #[test]
+    fn csv_fines_are_plain_amounts() {
+        let items = overdue_items(&loans(), day(2024, 2, 1), 50);
+        assert_eq!(to_csv(&items[..1]), "patron,book name,issue date,due date,days late,fine\nKim,Golang,2024-01-01,2024-01-15,17,$8.50\n");
+    }
+}
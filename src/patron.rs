@@ -0,0 +1,88 @@
+use std::io;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::io::prelude::*;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::collation;
+use crate::library::{self, LibraryError};
+use crate::staging::{self, DataFile};
+
+/**
+ * Registered patrons, one `name,category,email,notify,card` per line in the `patrons` file.
+ * The category (e.g. student, staff) picks the loan rules in `libraryconfig`.
+ * notify is `false` when the patron opted out of e-mail reminders.
+ * card is the barcode of the library card, see `barcode`.
+ * A borrower that was never registered is treated as a general patron.
+ */
+const PATRON_PATH: &str = "patrons";
+
+pub const DEFAULT_CATEGORY: &str = "general";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Patron {
+    pub name: String,
+    pub category: String,
+    pub email: String,
+    pub notify: bool,
+    #[serde(default)]
+    pub card: String
+}
+
+impl Patron {
+    pub fn is_named(&self, name: &str) -> bool {
+        collation::same(&self.name, name)
+    }
+}
+
+impl fmt::Display for Patron {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{},{}", self.name, self.category, self.email, self.notify, self.card)
+    }
+}
+
+fn parse_patron(line: &str) -> Option<Patron> {
+    let slice_data: Vec<&str> = line.split(',').collect();
+    if slice_data[0].trim().is_empty() {
+        return None;
+    }
+    Some(Patron {
+        name: slice_data[0].trim().to_string(),
+        category: slice_data.get(1)
+            .map(|c| c.trim().to_lowercase())
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| DEFAULT_CATEGORY.to_string()),
+        email: slice_data.get(2).map(|e| e.trim().to_string()).unwrap_or_default(),
+        notify: slice_data.get(3).map(|n| n.trim() != "false").unwrap_or(true),
+        card: slice_data.get(4).map(|c| c.trim().to_string()).unwrap_or_default()
+    })
+}
+
+pub fn load_patrons() -> Result<Vec<Patron>, LibraryError> {
+    library::read_records(PATRON_PATH, parse_patron)
+}
+
+pub fn find(name: &str) -> Result<Option<Patron>, LibraryError> {
+    Ok(load_patrons()?.into_iter().find(|p| p.is_named(name)))
+}
+
+pub fn category_of(name: &str) -> Result<String, LibraryError> {
+    Ok(find(name)?.map(|p| p.category).unwrap_or_else(|| DEFAULT_CATEGORY.to_string()))
+}
+
+// the whole `patrons` file, for writes staged together with other files
+pub fn data_file(patrons: &[Patron]) -> DataFile {
+    DataFile::new(PATRON_PATH, patrons)
+}
+
+pub fn save_patrons(patrons: &[Patron]) -> io::Result<()> {
+    staging::write_all(&[data_file(patrons)])
+}
+
+pub fn register(patron: &Patron) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(Path::new(PATRON_PATH))?;
+    write!(&mut file, "{}", patron)
+}
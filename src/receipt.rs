@@ -0,0 +1,219 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+use chrono::NaiveDate;
+use crate::config::Config;
+use crate::ledger::{self, EntryKind, LedgerEntry};
+use crate::library::LibraryError;
+use crate::loan::Loan;
+use crate::patron;
+use crate::template::{self, escape_xml};
+
+/**
+ * Receipts of the loans and returns of a patron at the desk, with the due
+ * dates, the fines charged and paid and the balance left on the account.
+ *
+ * They are written to the `receipts` directory after every borrow and return,
+ * as 40 column plain text for a thermal printer, as an HTML page to print or
+ * save as PDF from the browser, or both, see `receipts` in `libraryconfig`.
+ * Both come from the templates `receipt.txt` and `receipt.html` with {title},
+ * {date}, {patron}, {card}, {clerk}, {items}, {fines}, {paid} and {balance}.
+ */
+const RECEIPT_WIDTH: usize = 40;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReceiptFormat {
+    Text,
+    Html
+}
+
+impl ReceiptFormat {
+    pub fn parse(s: &str) -> Option<ReceiptFormat> {
+        match s.trim().to_lowercase().as_str() {
+            "text" | "txt" => Some(ReceiptFormat::Text),
+            "html" => Some(ReceiptFormat::Html),
+            _ => None
+        }
+    }
+
+    fn template(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            ReceiptFormat::Text => ("receipt.txt", include_str!("../templates/receipt.txt"), "txt"),
+            ReceiptFormat::Html => ("receipt.html", include_str!("../templates/receipt.html"), "html")
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Receipt {
+    pub patron: String,
+    pub date: NaiveDate,
+    pub clerk: String,
+    // the loans as they are after the loan or return
+    pub loans: Vec<Loan>,
+    pub paid: i64
+}
+
+impl Receipt {
+    pub fn new(patron: &str, date: NaiveDate, clerk: &str) -> Receipt {
+        Receipt { patron: patron.trim().to_string(), date, clerk: clerk.to_string(), loans: vec![], paid: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loans.is_empty() && self.paid == 0
+    }
+
+    fn title(&self) -> &'static str {
+        if self.loans.iter().all(|l| l.is_open()) {
+            "Loan receipt"
+        } else if self.loans.iter().all(|l| !l.is_open()) {
+            "Return receipt"
+        } else {
+            "Loan and return receipt"
+        }
+    }
+}
+
+// everything a patron borrowed, returned and paid on a day, to print a receipt again
+pub fn for_day(patron: &str, date: NaiveDate, loans: &[Loan], entries: &[LedgerEntry]) -> Receipt {
+    let mut receipt = Receipt::new(patron, date, "");
+    receipt.loans = loans.iter()
+        .filter(|l| l.is_for(patron) && (l.issue_date == date || l.return_date == Some(date)))
+        .cloned()
+        .collect();
+    // the staff member who lent or took back the first item
+    receipt.clerk = receipt.loans.first()
+        .map(|l| if l.return_date == Some(date) { l.returned_by.clone() } else { l.clerk.clone() })
+        .unwrap_or_default();
+    receipt.paid = entries.iter()
+        .filter(|e| e.is_for(patron) && e.date == date && e.kind == EntryKind::Payment)
+        .map(|e| e.amount)
+        .sum();
+    receipt
+}
+
+fn text_lines(loan: &Loan) -> String {
+    let mut lines = vec![loan.book_name.chars().take(RECEIPT_WIDTH).collect::<String>()];
+    lines.push(format!("  {:<10}due {}", loan.barcode, loan.due_date));
+    if let Some(returned) = loan.return_date {
+        match loan.fine {
+            0 => lines.push(format!("  returned {}", returned)),
+            fine => lines.push(format!("  returned {}  fine {}", returned, ledger::format_money(fine)))
+        }
+    }
+    lines.join("\n")
+}
+
+fn html_row(loan: &Loan) -> String {
+    format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        escape_xml(&loan.book_name), escape_xml(&loan.barcode), loan.due_date,
+        loan.return_date.map(|d| d.to_string()).unwrap_or_default(),
+        if loan.fine > 0 { ledger::format_money(loan.fine) } else { String::new() })
+}
+
+// the receipt from its template, the balance is the one on the account now
+pub fn render(receipt: &Receipt, format: ReceiptFormat, balance: i64) -> Result<String, LibraryError> {
+    let (name, builtin, _) = format.template();
+    let receipt_template = template::load(name, builtin)?;
+    let card = patron::find(&receipt.patron)?.map(|p| p.card).unwrap_or_default();
+    let escape = |value: &str| match format {
+        ReceiptFormat::Text => value.to_string(),
+        ReceiptFormat::Html => escape_xml(value)
+    };
+    let items: Vec<String> = receipt.loans.iter()
+        .map(|loan| match format {
+            ReceiptFormat::Text => text_lines(loan),
+            ReceiptFormat::Html => html_row(loan)
+        })
+        .collect();
+    Ok(template::render(&receipt_template, &[
+        ("title", receipt.title().to_string()),
+        ("date", receipt.date.to_string()),
+        ("patron", escape(&receipt.patron)),
+        ("card", escape(&card)),
+        ("clerk", escape(&receipt.clerk)),
+        ("items", items.join("\n")),
+        ("fines", ledger::format_money(receipt.loans.iter().map(|l| l.fine).sum())),
+        ("paid", ledger::format_money(receipt.paid)),
+        ("balance", ledger::format_money(balance))
+    ]))
+}
+
+// write the receipt in every format the config asks for, returns the files that were written
+pub fn write(receipt: &Receipt, config: &Config) -> Result<Vec<PathBuf>, LibraryError> {
+    let formats = config.receipt_formats();
+    if formats.is_empty() || receipt.is_empty() {
+        return Ok(vec![]);
+    }
+    let balance = ledger::balance(&receipt.patron)?;
+    let dir = Path::new(config.receipt_dir());
+    fs::create_dir_all(dir)?;
+
+    let file_name = template::file_name(&receipt.patron);
+    // a patron can come to the desk more than once a day
+    let mut number = 1;
+    while formats.iter().any(|f| dir.join(format!("{}-{}-{}.{}", file_name, receipt.date, number, f.template().2)).exists()) {
+        number += 1;
+    }
+    let mut written = vec![];
+    for format in formats {
+        let path = dir.join(format!("{}-{}-{}.{}", file_name, receipt.date, number, format.template().2));
+        File::create(&path)?.write_all(render(receipt, format, balance)?.as_bytes())?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+    use crate::library;
+
+    const PATRONS: &str = "Kim & Co,adult,kim@example.com,true,P0000001\n";
+
+    fn receipt() -> Receipt {
+        let mut lent = Loan::new("Kim & Co", "Rust", day(2024, 1, 20), 14, "Lee");
+        lent.barcode = "I0000001".to_string();
+        let mut returned = Loan::new("Kim & Co", "<Dune>", day(2024, 1, 1), 14, "Lee");
+        returned.barcode = "I0000002".to_string();
+        returned.close(day(2024, 1, 20), 150, "Lee");
+        let mut receipt = Receipt::new("Kim & Co", day(2024, 1, 20), "Lee");
+        receipt.loans = vec![lent, returned];
+        receipt.paid = 100;
+        receipt
+    }
+
+    #[test]
+    fn text_receipt_lists_due_dates_and_fines() {
+        let text = library::in_data_dir("receipt-text", &[("patrons", PATRONS)], || render(&receipt(), ReceiptFormat::Text, 50)).unwrap();
+        assert!(text.contains("Loan and return receipt\nDate    : 2024-01-20\nPatron  : Kim & Co P0000001\nStaff   : Lee\n"));
+        assert!(text.contains("Rust\n  I0000001  due 2024-02-03\n<Dune>\n  I0000002  due 2024-01-15\n  returned 2024-01-20  fine $1.50\n"));
+        assert!(text.contains("Fines charged       $1.50\nPaid now            $1.00\nOutstanding balance $0.50\n"));
+    }
+
+    #[test]
+    fn html_receipt_escapes_names() {
+        let html = library::in_data_dir("receipt-html", &[("patrons", PATRONS)], || render(&receipt(), ReceiptFormat::Html, 50)).unwrap();
+        assert!(html.contains("<title>Loan and return receipt</title>"));
+        assert!(html.contains("Patron: Kim &amp; Co P0000001<br>"));
+        assert!(html.contains("<tr><td>Rust</td><td>I0000001</td><td>2024-02-03</td><td></td><td></td></tr>"));
+        assert!(html.contains("<tr><td>&lt;Dune&gt;</td><td>I0000002</td><td>2024-01-15</td><td>2024-01-20</td><td>$1.50</td></tr>"));
+    }
+
+    #[test]
+    fn receipt_again_has_the_loans_returns_and_payments_of_the_day() {
+        let mut receipt = receipt();
+        receipt.loans.push(Loan::new("Kim & Co", "Emma", day(2024, 1, 19), 14, "Lee"));
+        receipt.loans.push(Loan::new("Ada", "Golang", day(2024, 1, 20), 14, "Lee"));
+        let entries = vec![
+            LedgerEntry::new("Kim & Co", day(2024, 1, 20), EntryKind::Payment, 100, ""),
+            LedgerEntry::new("Kim & Co", day(2024, 1, 20), EntryKind::Fine, 150, ""),
+            LedgerEntry::new("Kim & Co", day(2024, 1, 19), EntryKind::Payment, 300, "")
+        ];
+        let again = for_day("kim & co", day(2024, 1, 20), &receipt.loans, &entries);
+        assert_eq!(again.loans.iter().map(|l| l.book_name.as_str()).collect::<Vec<_>>(), ["Rust", "<Dune>"]);
+        assert_eq!((again.clerk.as_str(), again.paid), ("Lee", 100));
+        assert!(for_day("Kim & Co", day(2024, 1, 21), &receipt.loans, &entries).is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+use std::io;
+use std::io::prelude::*;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::DEFAULT_ITEM_TYPE;
+use crate::auth::{self, Staff};
+use crate::clock::Clock;
+use crate::collation;
+use crate::config::Config;
+use crate::hold;
+use crate::i18n::t;
+use crate::ledger::{self, EntryKind};
+use crate::library::{self, BookInput, LibraryError};
+use crate::loan;
+use crate::patron::{self, Patron};
+use crate::trash;
+
+/**
+ * JSON REST API over the library operations of `library`, for `serve`.
+ *
+ *   GET    /books?search=text         GET/PUT/DELETE /books/{id}     POST /books
+ *   GET    /trash                     POST /trash/{id} restore       DELETE /trash/{id} purge
+ *   GET    /patrons                   GET/PUT/DELETE /patrons/{name} POST /patrons
+ *   GET    /loans?borrower=name&open=true
+ *   POST   /loans     {book_id, borrower, issue_date}   issue a copy
+ *   POST   /returns   {book_id, borrower}               return a copy
+ *   POST   /renewals  {borrower, book_name}             renew a loan
+ *   GET    /holds     POST /holds {book_name, patron}   DELETE /holds?book_name=&patron=
+ *   GET    /fines/{patron}            POST /fines/{patron} {kind, amount, note}
+ *
+ * Every request needs an `Authorization: Bearer TOKEN` header with a token from
+ * `staff token NAME`, reading works with any token, changes need the role for them.
+ * A book id is the copy number in the library store, as in the menu lists.
+ * Deleting a book withdraws it to the trash, `DELETE /books/{id}?reason=text`.
+ * Amounts are given as text ("1.50") and returned in cents with a formatted copy.
+ * Requests are served one at a time so two requests never write the data files together.
+ * A request body over MAX_BODY bytes is refused with 413 before it is read.
+ */
+const MAX_BODY: u64 = 64 * 1024;
+
+struct ApiError {
+    status: u16,
+    message: String
+}
+
+impl ApiError {
+    fn new(status: u16, message: &str) -> ApiError {
+        ApiError { status, message: message.to_string() }
+    }
+}
+
+impl From<LibraryError> for ApiError {
+    fn from(err: LibraryError) -> ApiError {
+        let status = match err {
+            LibraryError::NotFound(_) => 404,
+            LibraryError::Invalid(_) => 400,
+            LibraryError::Conflict(_) | LibraryError::Refused(_) | LibraryError::RenewRefused(_) => 409,
+            LibraryError::Forbidden(_) => 403,
+            LibraryError::Io(_) | LibraryError::Damaged(_) | LibraryError::Unaudited(_) => 500
+        };
+        ApiError { status, message: err.to_string() }
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(err: io::Error) -> ApiError {
+        LibraryError::Io(err).into()
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> ApiError {
+        ApiError::new(400, &t!("api.invalid_body", error = err))
+    }
+}
+
+type ApiResult = Result<(u16, Value), ApiError>;
+
+#[derive(Deserialize)]
+struct PatronInput {
+    name: Option<String>,
+    category: Option<String>,
+    email: Option<String>,
+    notify: Option<bool>,
+    card: Option<String>
+}
+
+#[derive(Deserialize)]
+struct IssueInput {
+    book_id: usize,
+    borrower: String,
+    issue_date: Option<NaiveDate>
+}
+
+#[derive(Deserialize)]
+struct ReturnInput {
+    book_id: usize,
+    borrower: String
+}
+
+#[derive(Deserialize)]
+struct RenewInput {
+    borrower: String,
+    book_name: String
+}
+
+#[derive(Deserialize)]
+struct HoldInput {
+    book_name: String,
+    patron: String
+}
+
+#[derive(Deserialize)]
+struct AccountInput {
+    kind: String,
+    amount: String,
+    note: Option<String>
+}
+
+// "Kim%20Loo+Uii" -> "Kim Loo Uii"
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+                continue;
+            },
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn book_id(segment: &str) -> Result<usize, ApiError> {
+    segment.parse().map_err(|_| ApiError::new(400, &t!("api.invalid_id", id = segment)))
+}
+
+fn book_json(id: usize, book: &crate::Book) -> Value {
+    let mut value = json!(book);
+    value["id"] = json!(id);
+    value
+}
+
+fn money_json(cents: i64) -> Value {
+    json!({ "cents": cents, "formatted": ledger::plain_money(cents) })
+}
+
+fn books(method: &Method, id: Option<&str>, query: &str, body: &str, today: NaiveDate, staff: &Staff) -> ApiResult {
+    match (method, id) {
+        (Method::Get, None) => {
+            let data = library::read_store()?;
+            let books = crate::to_book_list(&data)?;
+            let search = query_value(query, "search").unwrap_or_default();
+            let found: Vec<Value> = books.iter()
+                .enumerate()
+                .filter(|(_, b)| collation::contains(b.name, &search))
+                .map(|(i, b)| book_json(i, b))
+                .collect();
+            Ok((200, json!(found)))
+        },
+        (Method::Post, None) => {
+            let input: BookInput = serde_json::from_str(body)?;
+            let id = library::add_book(&input, today, staff)?;
+            let data = library::read_store()?;
+            let books = crate::to_book_list(&data)?;
+            Ok((201, books.get(id).map(|b| book_json(id, b)).unwrap_or(Value::Null)))
+        },
+        (Method::Get, Some(id)) => {
+            let id = book_id(id)?;
+            let data = library::read_store()?;
+            let books = crate::to_book_list(&data)?;
+            match books.get(id) {
+                Some(book) => Ok((200, book_json(id, book))),
+                None => Err(ApiError::new(404, &t!("error.no_book", number = id)))
+            }
+        },
+        (Method::Put, Some(id)) => {
+            let id = book_id(id)?;
+            let input: BookInput = serde_json::from_str(body)?;
+            library::update_book(id, &input, today, staff)?;
+            let data = library::read_store()?;
+            let books = crate::to_book_list(&data)?;
+            Ok((200, books.get(id).map(|b| book_json(id, b)).unwrap_or(Value::Null)))
+        },
+        (Method::Delete, Some(id)) => {
+            let reason = query_value(query, "reason").unwrap_or_default();
+            Ok((200, library::withdraw_book(book_id(id)?, &reason, today, staff)?.to_json()))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "books")))
+    }
+}
+
+fn trash(method: &Method, id: Option<&str>, staff: &Staff) -> ApiResult {
+    match (method, id) {
+        (Method::Get, None) => {
+            let trash: Vec<Value> = trash::load_trash()?.iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    let mut value = w.to_json();
+                    value["id"] = json!(i);
+                    value
+                })
+                .collect();
+            Ok((200, json!(trash)))
+        },
+        (Method::Post, Some(id)) => {
+            let id = library::restore_book(book_id(id)?, staff)?;
+            let data = library::read_store()?;
+            let books = crate::to_book_list(&data)?;
+            Ok((201, books.get(id).map(|b| book_json(id, b)).unwrap_or(Value::Null)))
+        },
+        (Method::Delete, Some(id)) => {
+            library::purge(Some(book_id(id)?), staff)?;
+            Ok((204, Value::Null))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "trash")))
+    }
+}
+
+fn patrons(method: &Method, name: Option<&str>, body: &str, config: &Config, staff: &Staff) -> ApiResult {
+    match (method, name) {
+        (Method::Get, None) => Ok((200, json!(patron::load_patrons()?))),
+        (Method::Post, None) => {
+            let input: PatronInput = serde_json::from_str(body)?;
+            let patron = library::register_patron(&Patron {
+                name: input.name.unwrap_or_default(),
+                category: input.category.unwrap_or_default(),
+                email: input.email.unwrap_or_default(),
+                notify: input.notify.unwrap_or(true),
+                card: input.card.unwrap_or_default()
+            }, staff)?;
+            Ok((201, json!(patron)))
+        },
+        (Method::Get, Some(name)) => match patron::find(name)? {
+            Some(patron) => Ok((200, json!(patron))),
+            None => Err(ApiError::new(404, &t!("patrons.not_registered", patron = name)))
+        },
+        (Method::Put, Some(name)) => {
+            let input: PatronInput = serde_json::from_str(body)?;
+            let current = patron::find(name)?
+                .ok_or_else(|| ApiError::new(404, &t!("patrons.not_registered", patron = name)))?;
+            // fields left out keep their value
+            let patron = library::update_patron(name, &Patron {
+                name: current.name.clone(),
+                category: input.category.unwrap_or(current.category),
+                email: input.email.unwrap_or(current.email),
+                notify: input.notify.unwrap_or(current.notify),
+                card: input.card.unwrap_or(current.card)
+            }, staff)?;
+            Ok((200, json!(patron)))
+        },
+        (Method::Delete, Some(name)) => {
+            library::delete_patron(name, config, staff)?;
+            Ok((204, Value::Null))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "patrons")))
+    }
+}
+
+fn loans(method: &Method, query: &str, body: &str, config: &Config, today: NaiveDate, staff: &Staff) -> ApiResult {
+    match method {
+        Method::Get => {
+            let borrower = query_value(query, "borrower");
+            let open_only = query_value(query, "open").map(|o| o == "true").unwrap_or(false);
+            let loans: Vec<loan::Loan> = loan::load_loans(config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY))?
+                .into_iter()
+                .filter(|l| borrower.as_ref().map(|b| l.is_for(b)).unwrap_or(true) && (!open_only || l.is_open()))
+                .collect();
+            Ok((200, json!(loans)))
+        },
+        Method::Post => {
+            let input: IssueInput = serde_json::from_str(body)?;
+            let loan = library::issue(input.book_id, &input.borrower, input.issue_date.unwrap_or(today), today, config, staff)?;
+            Ok((201, json!(loan)))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "loans")))
+    }
+}
+
+fn holds(method: &Method, query: &str, body: &str, today: NaiveDate, staff: &Staff) -> ApiResult {
+    match method {
+        Method::Get => Ok((200, json!(hold::load_holds()?))),
+        Method::Post => {
+            let input: HoldInput = serde_json::from_str(body)?;
+            let position = library::place_hold(&input.book_name, &input.patron, today, staff)?;
+            Ok((201, json!({ "book_name": input.book_name.trim(), "patron": input.patron.trim(), "position": position })))
+        },
+        Method::Delete => {
+            let book_name = query_value(query, "book_name").unwrap_or_default();
+            let patron = query_value(query, "patron").unwrap_or_default();
+            library::cancel_hold(&book_name, &patron, staff)?;
+            Ok((204, Value::Null))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "holds")))
+    }
+}
+
+fn fines(method: &Method, patron: &str, body: &str, today: NaiveDate, staff: &Staff) -> ApiResult {
+    match method {
+        Method::Get => {
+            let entries = ledger::entries_for(patron)?;
+            Ok((200, json!({
+                "patron": patron,
+                "balance": money_json(ledger::balance_of(&entries)),
+                "entries": entries
+            })))
+        },
+        Method::Post => {
+            let input: AccountInput = serde_json::from_str(body)?;
+            let kind = EntryKind::parse(&input.kind)
+                .ok_or_else(|| ApiError::new(400, &t!("api.invalid_kind")))?;
+            let amount = ledger::parse_money(&input.amount)
+                .ok_or_else(|| ApiError::new(400, &t!("api.invalid_amount", amount = input.amount)))?;
+            let balance = library::record_account_entry(patron, kind, amount, input.note.as_deref().unwrap_or(""), today, staff)?;
+            Ok((201, json!({ "patron": patron, "kind": kind, "amount": money_json(amount), "balance": money_json(balance) })))
+        },
+        _ => Err(ApiError::new(405, &t!("api.method", resource = "fines")))
+    }
+}
+
+fn route(method: &Method, url: &str, body: &str, clock: &dyn Clock, config: &Config, staff: &Staff) -> ApiResult {
+    let today = clock.today();
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<String> = path.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect();
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+    match segments.as_slice() {
+        ["books"] => books(method, None, query, body, today, staff),
+        ["books", id] => books(method, Some(id), query, body, today, staff),
+        ["trash"] => trash(method, None, staff),
+        ["trash", id] => trash(method, Some(id), staff),
+        ["patrons"] => patrons(method, None, body, config, staff),
+        ["patrons", name] => patrons(method, Some(name), body, config, staff),
+        ["loans"] => loans(method, query, body, config, today, staff),
+        ["returns"] if *method == Method::Post => {
+            let input: ReturnInput = serde_json::from_str(body)?;
+            let returned = library::return_book(input.book_id, &input.borrower, today, config, staff)?;
+            Ok((200, json!({
+                "loan": returned.loan,
+                "days_overdue": returned.days_overdue,
+                "fine": money_json(returned.fine),
+                "set_aside_for": returned.set_aside
+            })))
+        },
+        ["renewals"] if *method == Method::Post => {
+            let input: RenewInput = serde_json::from_str(body)?;
+            Ok((200, json!(library::renew(&input.borrower, &input.book_name, today, config, staff)?)))
+        },
+        ["holds"] => holds(method, query, body, today, staff),
+        ["fines", patron] => fines(method, patron, body, today, staff),
+        ["returns"] | ["renewals"] => Err(ApiError::new(405, &t!("api.use_post"))),
+        _ => Err(ApiError::new(404, &t!("api.no_resource", path = path)))
+    }
+}
+
+// the staff account of the bearer token of a request
+fn authenticate(request: &Request) -> Result<Staff, ApiError> {
+    let token = request.headers().iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::new(401, &t!("api.token_required")))?;
+    auth::staff_for_token(token)?.ok_or_else(|| ApiError::new(401, &t!("api.invalid_token")))
+}
+
+// at most MAX_BODY bytes of UTF-8 text, a longer body is not read to its end
+fn read_body<R: Read>(reader: R) -> Result<String, ApiError> {
+    let mut data = vec![];
+    reader.take(MAX_BODY + 1).read_to_end(&mut data)
+        .map_err(|e| ApiError::new(400, &t!("api.body_unreadable", error = e)))?;
+    if data.len() as u64 > MAX_BODY {
+        return Err(ApiError::new(413, &t!("api.body_too_large", limit = MAX_BODY)));
+    }
+    String::from_utf8(data).map_err(|_| ApiError::new(400, &t!("api.body_not_utf8")))
+}
+
+fn respond(mut request: Request, clock: &dyn Clock, config: &Config) -> io::Result<()> {
+    let body = match request.body_length() {
+        Some(length) if length as u64 > MAX_BODY =>
+            Err(ApiError::new(413, &t!("api.body_too_large", limit = MAX_BODY))),
+        _ => read_body(request.as_reader())
+    };
+    let result = match (authenticate(&request), body) {
+        (Err(err), _) | (_, Err(err)) => Err(err),
+        (Ok(staff), Ok(body)) => route(request.method(), request.url(), &body, clock, config, &staff)
+    };
+    let (status, value) = match result {
+        Ok(response) => response,
+        Err(err) => (err.status, json!({ "error": err.message }))
+    };
+    println!("{} {} {}", request.method(), request.url(), status);
+    if status == 204 {
+        return request.respond(Response::empty(204));
+    }
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let mut response = Response::from_string(format!("{}\n", value))
+        .with_status_code(status)
+        .with_header(content_type);
+    if status == 401 {
+        response.add_header(Header::from_bytes("WWW-Authenticate", "Bearer").expect("valid header"));
+    }
+    request.respond(response)
+}
+
+pub fn serve(host: &str, port: u16, clock: &dyn Clock, config: &Config) -> io::Result<()> {
+    let server = Server::http((host, port)).map_err(|e| io::Error::other(e.to_string()))?;
+    println!("#[ {} ]#", t!("serve.listening", host = host, port = port));
+    for request in server.incoming_requests() {
+        if let Err(err) = respond(request, clock, config) {
+            eprintln!("{}", t!("serve.answer_failed", error = err));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::auth::Role;
+    use crate::clock::{day, FixedClock};
+
+    #[test]
+    fn body_up_to_the_limit_is_read() {
+        assert_eq!(read_body(Cursor::new(b"{\"borrower\": \"Kim\"}".to_vec())).ok().unwrap(), "{\"borrower\": \"Kim\"}");
+        assert_eq!(read_body(Cursor::new(vec![b'a'; MAX_BODY as usize])).ok().unwrap().len(), MAX_BODY as usize);
+    }
+
+    #[test]
+    fn body_over_the_limit_is_refused() {
+        let err = read_body(Cursor::new(vec![b'a'; MAX_BODY as usize + 1])).err().unwrap();
+        assert_eq!(err.status, 413);
+        assert_eq!(read_body(Cursor::new(vec![0xff, 0xfe])).err().unwrap().status, 400);
+    }
+
+    #[test]
+    fn query_values_are_percent_decoded() {
+        assert_eq!(query_value("borrower=Kim%20Loo&open=true", "borrower").as_deref(), Some("Kim Loo"));
+        assert_eq!(query_value("open=true", "borrower"), None);
+    }
+
+    #[test]
+    fn loans_and_returns_are_dated_by_the_clock() {
+        let store = "Dune,Frank Herbert,1965,false,2024-01-01,book,,I00001\n";
+        let (config, staff) = (Config::default(), Staff::without_password("Lee", Role::Librarian));
+        let body = r#"{"book_id": 0, "borrower": "Kim"}"#;
+        let (loan, returned) = library::in_data_dir("route-clock", &[("librarystore", store), ("patrons", "Kim\n")], || {
+            let loan = route(&Method::Post, "/loans", body, &FixedClock { date: day(2024, 3, 1) }, &config, &staff);
+            let returned = route(&Method::Post, "/returns", body, &FixedClock { date: day(2024, 3, 20) }, &config, &staff);
+            (loan.ok().unwrap().1, returned.ok().unwrap().1)
+        });
+        assert_eq!(loan["issue_date"], "2024-03-01");
+        assert_eq!(loan["due_date"], "2024-03-15");
+        assert_eq!(returned["loan"]["return_date"], "2024-03-20");
+        assert_eq!(returned["days_overdue"], 5);
+        assert_eq!(returned["fine"]["cents"], 250);
+    }
+}
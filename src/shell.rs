@@ -0,0 +1,291 @@
+use std::io;
+use std::io::IsTerminal;
+use chrono::NaiveDate;
+use rustyline::{CompletionType, Context, Editor, Helper};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use crate::cli;
+use crate::collation;
+use crate::i18n::t;
+use crate::library;
+use crate::patron;
+
+/**
+ * Command line of the menu, with line editing, history kept in the
+ * `shellhistory` file and tab completion of commands, book titles and patron
+ * names. Next to the numbered options it takes typed commands:
+ *
+ *   borrow "Design Pattern" --to Kim [--date 2024-01-31]
+ *   return Golang --from Kim
+ *   renew Golang --for Kim
+ *   hold Foundation --for Kim
+ *   search Java
+ *
+ * A title is the full name or a part only one title has, quotes keep a name
+ * with spaces together. Piped input is read line by line without any of it.
+ */
+const HISTORY_PATH: &str = "shellhistory";
+
+// typed commands and how they are used, for help and errors
+const USAGES: &[(&str, &str)] = &[
+    ("borrow", "borrow TITLE --to PATRON [--date YYYY-MM-DD]"),
+    ("return", "return TITLE --from PATRON"),
+    ("renew", "renew TITLE --for PATRON"),
+    ("hold", "hold TITLE --for PATRON"),
+    ("search", "search TEXT")
+];
+
+// words for the numbered options
+const OPTION_WORDS: &[(&str, u8)] = &[
+    ("list", 1),
+    ("create", 3),
+    ("withdraw", 6),
+    ("account", 7),
+    ("register", 9),
+    ("holds", 10),
+    ("history", 11),
+    ("overdue", 12),
+    ("reminders", 13),
+    ("statistics", 14),
+    ("trash", 15),
+    ("undo", 16),
+    ("redo", 17),
+    ("checkout", 18),
+    ("checkin", 19),
+    ("exit", 0),
+    ("quit", 0)
+];
+
+#[derive(Debug)]
+pub enum Command {
+    // a numbered menu option
+    Option(u8),
+    Help,
+    Search(String),
+    Borrow { title: String, patron: String, date: Option<NaiveDate> },
+    Return { title: String, patron: String },
+    Renew { title: String, patron: String },
+    Hold { title: String, patron: String }
+}
+
+// words of a command line with where they start, quotes keep spaces in a word,
+// the quote that is still open at the end of the line is returned too
+fn split_words(line: &str) -> (Vec<(usize, String)>, Option<char>) {
+    let mut words = vec![];
+    let mut word: Option<(usize, String)> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert((i, String::new()));
+            },
+            (_, '\\') => {
+                if let Some((_, next)) = chars.next() {
+                    word.get_or_insert((i, String::new())).1.push(next);
+                }
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, c) => word.get_or_insert((i, String::new())).1.push(c)
+        }
+    }
+    words.extend(word);
+    (words, quote)
+}
+
+fn usage(command: &str) -> String {
+    let usage = USAGES.iter().find(|(name, _)| *name == command).map(|(_, usage)| *usage).unwrap_or(command);
+    t!("command.usage", usage = usage)
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let (words, quote) = split_words(line);
+    if quote.is_some() {
+        return Err(t!("command.open_quote"));
+    }
+    let words: Vec<String> = words.into_iter().map(|(_, word)| word).collect();
+    let positionals = cli::positionals(&words);
+    let name = match positionals.first() {
+        None => return Err(t!("menu.invalid_input")),
+        Some(name) => name.to_lowercase()
+    };
+    if let Ok(option) = name.parse::<u8>() {
+        return Ok(Command::Option(option));
+    }
+    if let Some((_, option)) = OPTION_WORDS.iter().find(|(word, _)| *word == name) {
+        return Ok(Command::Option(*option));
+    }
+
+    let text = positionals[1..].join(" ");
+    let title = || match text.trim() {
+        "" => Err(usage(&name)),
+        title => Ok(title.to_string())
+    };
+    let patron = |option: &str| cli::option(&words, option)
+        .filter(|patron| !patron.is_empty())
+        .ok_or_else(|| usage(&name));
+    match name.as_str() {
+        "help" | "?" => Ok(Command::Help),
+        "search" => Ok(Command::Search(title()?)),
+        "borrow" => Ok(Command::Borrow {
+            title: title()?,
+            patron: patron("--to")?,
+            date: match cli::option(&words, "--date") {
+                None => None,
+                Some(date) => Some(NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .map_err(|_| t!("input.invalid_date"))?)
+            }
+        }),
+        "return" => Ok(Command::Return { title: title()?, patron: patron("--from")? }),
+        "renew" => Ok(Command::Renew { title: title()?, patron: patron("--for")? }),
+        "hold" => Ok(Command::Hold { title: title()?, patron: patron("--for")? }),
+        _ => Err(t!("command.unknown", command = name))
+    }
+}
+
+pub fn print_help() {
+    println!("{}", t!("help.commands"));
+    for (_, usage) in USAGES {
+        println!("  {}", usage);
+    }
+    println!("{}", t!("help.words"));
+    for (word, option) in OPTION_WORDS {
+        println!("  {:<12}[{}]", word, option);
+    }
+    println!();
+}
+
+// a damaged line only leaves its own title out, the menu reports the line when the books are listed
+fn book_titles() -> Vec<String> {
+    let data = library::read_store().unwrap_or_default();
+    let mut titles: Vec<String> = data.lines()
+        .filter_map(|line| crate::to_book_list(line).ok())
+        .flatten()
+        .map(|b| b.name.to_string())
+        .collect();
+    titles.sort_by_cached_key(|title| collation::sort_key(title));
+    titles.dedup();
+    titles
+}
+
+fn patron_names() -> Vec<String> {
+    patron::load_patrons().unwrap_or_default().into_iter().map(|p| p.name).collect()
+}
+
+// the library files are read on every tab, so new titles and patrons show at once
+pub struct Completion;
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (mut words, quote) = split_words(&line[..pos]);
+        // the word under the cursor, a new one after a space
+        let (start, partial) = match words.last() {
+            Some(_) if quote.is_some() || !line[..pos].ends_with(char::is_whitespace) => words.pop().unwrap_or_default(),
+            _ => (pos, String::new())
+        };
+        let before: Vec<&str> = words.iter().map(|(_, word)| word.as_str()).collect();
+        let choices: Vec<String> = match before.as_slice() {
+            [] => USAGES.iter().map(|(name, _)| *name)
+                .chain(OPTION_WORDS.iter().map(|(word, _)| *word))
+                .chain(["help"])
+                .map(|name| name.to_string())
+                .collect(),
+            [.., "--to"] | [.., "--from"] | [.., "--for"] => patron_names(),
+            [command] if USAGES.iter().any(|(name, _)| name == command) => book_titles(),
+            ["borrow", ..] => vec!["--to".to_string(), "--date".to_string()],
+            ["return", ..] => vec!["--from".to_string()],
+            ["renew", ..] | ["hold", ..] => vec!["--for".to_string()],
+            _ => vec![]
+        };
+        let candidates = choices.into_iter()
+            .filter(|choice| choice.to_lowercase().starts_with(&partial.to_lowercase()))
+            .map(|choice| {
+                let replacement = if quote.is_some() || choice.contains(char::is_whitespace) {
+                    format!("\"{}\" ", choice)
+                } else {
+                    format!("{} ", choice)
+                };
+                Pair { display: choice, replacement }
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+pub struct Shell {
+    // None when the input is piped in, it is then read line by line as before
+    editor: Option<Editor<Completion, DefaultHistory>>
+}
+
+impl Shell {
+    pub fn new(history_size: usize) -> Shell {
+        if !io::stdin().is_terminal() {
+            return Shell { editor: None };
+        }
+        let editor = rustyline::Config::builder()
+            .max_history_size(history_size)
+            .map(|config| config.completion_type(CompletionType::List).build())
+            .and_then(Editor::with_config);
+        match editor {
+            Err(err) => {
+                println!("{}", t!("shell.no_editing", error = err));
+                Shell { editor: None }
+            },
+            Ok(mut editor) => {
+                editor.set_helper(Some(Completion));
+                // there is no history file before the first session
+                let _ = editor.load_history(HISTORY_PATH);
+                Shell { editor: Some(editor) }
+            }
+        }
+    }
+
+    // a line of input, None at the end of the input or Ctrl-D
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let editor = match self.editor.as_mut() {
+            Some(editor) => editor,
+            None => {
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some(line));
+            }
+        };
+        loop {
+            match editor.readline(prompt) {
+                // Ctrl-C drops the line being typed
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(err) => return Err(io::Error::other(err.to_string())),
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor.add_history_entry(line.as_str()).map_err(|e| io::Error::other(e.to_string()))?;
+                        if let Err(err) = editor.save_history(HISTORY_PATH) {
+                            println!("{}", t!("shell.history_failed", path = HISTORY_PATH, error = err));
+                        }
+                    }
+                    return Ok(Some(line));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::time::Duration;
+use crate::i18n::t;
+
+/**
+ * Minimal SMTP client for a local mail relay, plain text without TLS or
+ * authentication: EHLO, MAIL FROM, RCPT TO, DATA, QUIT.
+ */
+pub struct SmtpClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream
+}
+
+fn smtp_error(reply: &str) -> io::Error {
+    io::Error::other(t!("smtp.replied", reply = reply.trim()))
+}
+
+impl SmtpClient {
+    pub fn connect(host: &str, port: u16) -> io::Result<SmtpClient> {
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let mut client = SmtpClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream
+        };
+        client.expect(220)?;
+        client.command("EHLO rusty-library", 250)?;
+        Ok(client)
+    }
+
+    // read a (possibly multi-line) reply and check its code
+    fn expect(&mut self, code: u16) -> io::Result<String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, t!("smtp.closed")));
+            }
+            reply.push_str(&line);
+            // "250-..." continues, "250 ..." is the last line
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                break;
+            }
+        }
+        match reply.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+            Some(c) if c == code => Ok(reply),
+            _ => Err(smtp_error(&reply))
+        }
+    }
+
+    fn command(&mut self, command: &str, code: u16) -> io::Result<String> {
+        write!(self.writer, "{}\r\n", command)?;
+        self.expect(code)
+    }
+
+    pub fn send(&mut self, from: &str, to: &str, message: &str) -> io::Result<()> {
+        self.command(&format!("MAIL FROM:<{}>", from), 250)?;
+        self.command(&format!("RCPT TO:<{}>", to), 250)?;
+        self.command("DATA", 354)?;
+        let mut data = String::new();
+        for line in message.lines() {
+            // dot stuffing, a line with a single "." would end the message
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        self.writer.write_all(data.as_bytes())?;
+        self.expect(250)?;
+        Ok(())
+    }
+
+    pub fn quit(mut self) -> io::Result<()> {
+        self.command("QUIT", 221).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // a mail relay that accepts one message and returns everything the client sent
+    fn relay(replies: &'static [&'static str]) -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = String::new();
+            writer.write_all(b"220 relay ready\r\n").unwrap();
+            for reply in replies {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                received.push_str(&line);
+                if line == "DATA\r\n" {
+                    writer.write_all(b"354 go ahead\r\n").unwrap();
+                    while line != ".\r\n" {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        received.push_str(&line);
+                    }
+                }
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+            received
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn message_is_sent_with_dot_stuffing() {
+        let (port, relay) = relay(&["250-relay\r\n250 8BITMIME\r\n", "250 ok\r\n", "250 ok\r\n", "250 queued\r\n", "221 bye\r\n"]);
+        let mut client = SmtpClient::connect("127.0.0.1", port).unwrap();
+        client.send("library@example.org", "kim@example.org", "Subject: Dune\n\n.hidden\nDue soon\n").unwrap();
+        client.quit().unwrap();
+        assert_eq!(relay.join().unwrap(), "EHLO rusty-library\r\n\
+            MAIL FROM:<library@example.org>\r\n\
+            RCPT TO:<kim@example.org>\r\n\
+            DATA\r\n\
+            Subject: Dune\r\n\r\n..hidden\r\nDue soon\r\n.\r\n\
+            QUIT\r\n");
+    }
+
+    #[test]
+    fn refused_recipient_is_an_error_with_the_reply() {
+        let (port, relay) = relay(&["250 relay\r\n", "250 ok\r\n", "550 no such user\r\n"]);
+        let mut client = SmtpClient::connect("127.0.0.1", port).unwrap();
+        let err = client.send("library@example.org", "nobody@example.org", "Subject: Dune\n").unwrap_err();
+        assert!(err.to_string().contains("550 no such user"));
+        drop(client);
+        relay.join().unwrap();
+    }
+
+    #[test]
+    fn closed_connection_is_an_error() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let relay = thread::spawn(move || drop(listener.accept().unwrap()));
+        let err = SmtpClient::connect("127.0.0.1", port).err().unwrap();
+        relay.join().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "SMTP server closed the connection");
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+
+/**
+ * Writes of whole data files that have to change together, e.g. a return
+ * closes the loan, charges the fine and frees the copy.
+ *
+ * Every file is first written next to its target as `<name>.staged` and
+ * only renamed into place once all of them are written, so a failed write
+ * leaves every data file as it was.
+ */
+pub struct DataFile {
+    pub path: PathBuf,
+    pub data: String
+}
+
+impl DataFile {
+    pub fn new<P: AsRef<Path>, T: ToString>(path: P, records: &[T]) -> DataFile {
+        let mut data = String::new();
+        for record in records {
+            data.push_str(&record.to_string());
+        }
+        DataFile { path: path.as_ref().to_path_buf(), data }
+    }
+}
+
+pub fn write_all(files: &[DataFile]) -> io::Result<()> {
+    let temp_paths: Vec<PathBuf> = files.iter().map(|f| f.path.with_extension("staged")).collect();
+    let written = files.iter().zip(&temp_paths)
+        .try_for_each(|(file, temp_path)| File::create(temp_path)?.write_all(file.data.as_bytes()));
+    if let Err(err) = written {
+        for temp_path in &temp_paths {
+            let _ = fs::remove_file(temp_path);
+        }
+        return Err(err);
+    }
+    for (file, temp_path) in files.iter().zip(&temp_paths) {
+        fs::rename(temp_path, &file.path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("library-staging-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file(path: &Path, data: &str) -> DataFile {
+        DataFile { path: path.to_path_buf(), data: data.to_string() }
+    }
+
+    #[test]
+    fn every_file_is_replaced() {
+        let dir = dir("replaced");
+        let (loans, store) = (dir.join("borrower"), dir.join("librarystore"));
+        fs::write(&loans, "old loans\n").unwrap();
+        write_all(&[file(&loans, "new loans\n"), file(&store, "new store\n")]).unwrap();
+        assert_eq!(fs::read_to_string(&loans).unwrap(), "new loans\n");
+        assert_eq!(fs::read_to_string(&store).unwrap(), "new store\n");
+        assert!(!dir.join("borrower.staged").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_changes_no_file() {
+        let dir = dir("failed");
+        let loans = dir.join("borrower");
+        fs::write(&loans, "old loans\n").unwrap();
+        let missing = dir.join("missing").join("ledger");
+        assert!(write_all(&[file(&loans, "new loans\n"), file(&missing, "fine\n")]).is_err());
+        assert_eq!(fs::read_to_string(&loans).unwrap(), "old loans\n");
+        assert!(!dir.join("borrower.staged").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_are_written_one_per_line() {
+        let data_file = DataFile::new("holds", &["Dune,Kim,2024-01-01,\n", "Dune,Ada,2024-01-02,\n"]);
+        assert_eq!(data_file.data, "Dune,Kim,2024-01-01,\nDune,Ada,2024-01-02,\n");
+        assert_eq!(data_file.path, Path::new("holds"));
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDate;
+use serde::{Serialize, Serializer};
+use crate::collation;
+use crate::i18n::{self, t};
+use crate::ledger::{self, EntryKind, LedgerEntry};
+use crate::loan::Loan;
+use crate::overdue::csv_field;
+
+/**
+ * Circulation statistics over the loans issued between two dates:
+ * loans per period, most and least circulated titles and authors,
+ * average loan length, overdue rate, fines and active patrons.
+ */
+#[derive(Copy, Clone)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year
+}
+
+impl Period {
+    pub fn parse(s: &str) -> Option<Period> {
+        match s.trim() {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "year" => Some(Period::Year),
+            _ => None
+        }
+    }
+
+    fn key(self, date: NaiveDate) -> String {
+        let format = match self {
+            Period::Day => "%Y-%m-%d",
+            Period::Week => "%G-W%V",
+            Period::Month => "%Y-%m",
+            Period::Year => "%Y"
+        };
+        date.format(format).to_string()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub from: String,
+    pub to: String,
+    pub total_loans: usize,
+    pub returned_loans: usize,
+    pub average_loan_days: f64,
+    pub overdue_loans: usize,
+    pub overdue_rate: f64,
+    #[serde(serialize_with = "plain_money")]
+    pub fines_charged: i64,
+    #[serde(serialize_with = "plain_money")]
+    pub fines_collected: i64,
+    pub active_patrons: usize
+}
+
+// fines are "$1.50" in json and csv whatever the language of the library
+fn plain_money<S: Serializer>(cents: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&ledger::plain_money(*cents))
+}
+
+#[derive(Serialize)]
+pub struct PeriodCount {
+    pub period: String,
+    pub loans: usize
+}
+
+#[derive(Serialize)]
+pub struct Circulation {
+    pub name: String,
+    pub loans: usize
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub summary: Summary,
+    pub loans_per_period: Vec<PeriodCount>,
+    pub most_circulated_titles: Vec<Circulation>,
+    pub least_circulated_titles: Vec<Circulation>,
+    pub most_circulated_authors: Vec<Circulation>,
+    pub least_circulated_authors: Vec<Circulation>
+}
+
+// count per name, names of the catalogue that were never lent count 0,
+// ordered by count then name
+fn circulation(names: &[String], catalogue: &[String]) -> Vec<Circulation> {
+    let mut counts: HashMap<&str, usize> = catalogue.iter().map(|n| (n.as_str(), 0)).collect();
+    for name in names {
+        *counts.entry(name.as_str()).or_insert(0) += 1;
+    }
+    let mut circulation: Vec<Circulation> = counts.into_iter()
+        .map(|(name, loans)| Circulation { name: name.to_string(), loans })
+        .collect();
+    circulation.sort_by(|a, b| b.loans.cmp(&a.loans).then_with(|| collation::compare(&a.name, &b.name)));
+    circulation
+}
+
+fn least(mut circulation: Vec<Circulation>, top: usize) -> Vec<Circulation> {
+    circulation.sort_by(|a, b| a.loans.cmp(&b.loans).then_with(|| collation::compare(&a.name, &b.name)));
+    circulation.truncate(top);
+    circulation
+}
+
+fn most(mut circulation: Vec<Circulation>, top: usize) -> Vec<Circulation> {
+    circulation.truncate(top);
+    circulation
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (count as f64 / total as f64 * 100.0).round() / 100.0
+}
+
+/**
+ * books is the catalogue as (title, author) so titles that never circulated
+ * show up in the least circulated lists.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn report(loans: &[Loan], books: &[(String, String)], ledger: &[LedgerEntry],
+    from: NaiveDate, to: NaiveDate, today: NaiveDate, period: Period, top: usize) -> Report {
+    let in_range: Vec<&Loan> = loans.iter()
+        .filter(|l| l.issue_date >= from && l.issue_date <= to)
+        .collect();
+
+    let returned: Vec<&&Loan> = in_range.iter().filter(|l| !l.is_open()).collect();
+    let total_loan_days: i64 = returned.iter()
+        .filter_map(|l| l.return_date.map(|d| d.signed_duration_since(l.issue_date).num_days()))
+        .sum();
+    let average_loan_days = if returned.is_empty() {
+        0.0
+    } else {
+        (total_loan_days as f64 / returned.len() as f64 * 10.0).round() / 10.0
+    };
+    let overdue_loans = in_range.iter().filter(|l| l.days_overdue(today) > 0).count();
+
+    let fines_in_range = |kind: EntryKind| -> i64 {
+        ledger.iter()
+            .filter(|e| e.kind == kind && e.date >= from && e.date <= to)
+            .map(|e| e.amount)
+            .sum()
+    };
+    let active_patrons: HashSet<String> = in_range.iter().map(|l| collation::normalize(&l.borrower)).collect();
+
+    let mut periods: Vec<PeriodCount> = vec![];
+    let mut sorted = in_range.clone();
+    sorted.sort_by_key(|l| l.issue_date);
+    for loan in sorted {
+        let key = period.key(loan.issue_date);
+        match periods.last_mut() {
+            Some(last) if last.period == key => last.loans += 1,
+            _ => periods.push(PeriodCount { period: key, loans: 1 })
+        }
+    }
+
+    let author_of = |title: &str| -> String {
+        books.iter()
+            .find(|(t, _)| t == title)
+            .map(|(_, a)| a.clone())
+            .unwrap_or_else(|| t!("stats.unknown_author"))
+    };
+    let titles: Vec<String> = in_range.iter().map(|l| l.book_name.clone()).collect();
+    let authors: Vec<String> = in_range.iter().map(|l| author_of(&l.book_name)).collect();
+    let mut catalogue_titles: Vec<String> = books.iter().map(|(t, _)| t.clone()).collect();
+    let mut catalogue_authors: Vec<String> = books.iter().map(|(_, a)| a.clone()).collect();
+    catalogue_titles.sort_by_cached_key(|title| collation::sort_key(title));
+    catalogue_titles.dedup();
+    catalogue_authors.sort_by_cached_key(|author| collation::sort_key(author));
+    catalogue_authors.dedup();
+
+    Report {
+        summary: Summary {
+            from: from.to_string(),
+            to: to.to_string(),
+            total_loans: in_range.len(),
+            returned_loans: returned.len(),
+            average_loan_days,
+            overdue_loans,
+            overdue_rate: ratio(overdue_loans, in_range.len()),
+            fines_charged: fines_in_range(EntryKind::Fine) - fines_in_range(EntryKind::Waiver),
+            fines_collected: fines_in_range(EntryKind::Payment) - fines_in_range(EntryKind::Refund),
+            active_patrons: active_patrons.len()
+        },
+        loans_per_period: periods,
+        most_circulated_titles: most(circulation(&titles, &catalogue_titles), top),
+        least_circulated_titles: least(circulation(&titles, &catalogue_titles), top),
+        most_circulated_authors: most(circulation(&authors, &catalogue_authors), top),
+        least_circulated_authors: least(circulation(&authors, &catalogue_authors), top)
+    }
+}
+
+fn circulation_csv(title: &str, circulation: &[Circulation], out: &mut String) {
+    out.push_str(&format!("{},loans\n", title));
+    for c in circulation {
+        out.push_str(&format!("{},{}\n", csv_field(&c.name), c.loans));
+    }
+}
+
+// one csv table per section, sections are separated by a blank line
+pub fn to_csv(report: &Report, section: &str) -> String {
+    let mut out = String::new();
+    let all = section == "all";
+    if all || section == "summary" {
+        let s = &report.summary;
+        out.push_str("metric,value\n");
+        out.push_str(&format!("from,{}\nto,{}\ntotal_loans,{}\nreturned_loans,{}\naverage_loan_days,{}\n",
+            s.from, s.to, s.total_loans, s.returned_loans, s.average_loan_days));
+        out.push_str(&format!("overdue_loans,{}\noverdue_rate,{}\nfines_charged,{}\nfines_collected,{}\nactive_patrons,{}\n",
+            s.overdue_loans, s.overdue_rate, ledger::plain_money(s.fines_charged), ledger::plain_money(s.fines_collected), s.active_patrons));
+    }
+    if all || section == "periods" {
+        if all { out.push('\n'); }
+        out.push_str("period,loans\n");
+        for p in &report.loans_per_period {
+            out.push_str(&format!("{},{}\n", p.period, p.loans));
+        }
+    }
+    if all || section == "titles" {
+        if all { out.push('\n'); }
+        circulation_csv("most circulated title", &report.most_circulated_titles, &mut out);
+        out.push('\n');
+        circulation_csv("least circulated title", &report.least_circulated_titles, &mut out);
+    }
+    if all || section == "authors" {
+        if all { out.push('\n'); }
+        circulation_csv("most circulated author", &report.most_circulated_authors, &mut out);
+        out.push('\n');
+        circulation_csv("least circulated author", &report.least_circulated_authors, &mut out);
+    }
+    out
+}
+
+pub fn to_json(report: &Report, section: &str) -> serde_json::Result<String> {
+    match section {
+        "summary" => serde_json::to_string_pretty(&report.summary),
+        "periods" => serde_json::to_string_pretty(&report.loans_per_period),
+        "titles" => serde_json::to_string_pretty(&serde_json::json!({
+            "most_circulated_titles": report.most_circulated_titles,
+            "least_circulated_titles": report.least_circulated_titles
+        })),
+        "authors" => serde_json::to_string_pretty(&serde_json::json!({
+            "most_circulated_authors": report.most_circulated_authors,
+            "least_circulated_authors": report.least_circulated_authors
+        })),
+        _ => serde_json::to_string_pretty(report)
+    }
+}
+
+pub fn to_text(report: &Report, section: &str) -> String {
+    let mut out = String::new();
+    let all = section == "all";
+    if all || section == "summary" {
+        let s = &report.summary;
+        let mut field = |label_key: &str, value: String| out.push_str(&format!("{}: {}\n", i18n::pad(&t!(label_key), 15), value));
+        field("field.period", t!("stats.period", from = s.from, to = s.to));
+        field("field.total_loans", s.total_loans.to_string());
+        field("field.returned_loans", s.returned_loans.to_string());
+        field("field.average_loan", t!("stats.days", days = s.average_loan_days));
+        field("field.overdue_loans", format!("{} ({}%)", s.overdue_loans, (s.overdue_rate * 100.0).round()));
+        field("field.fines_charged", ledger::format_money(s.fines_charged));
+        field("field.fines_collected", ledger::format_money(s.fines_collected));
+        field("field.active_patrons", s.active_patrons.to_string());
+        out.push_str("--------------------------------------------\n");
+    }
+    if all || section == "periods" {
+        for p in &report.loans_per_period {
+            out.push_str(&format!("{:<15}: {}\n", p.period, p.loans));
+        }
+        out.push_str("--------------------------------------------\n");
+    }
+    let mut list = |title: &str, circulation: &[Circulation]| {
+        out.push_str(&format!("{}\n", title));
+        for c in circulation {
+            out.push_str(&format!("  {:>4}  {}\n", c.loans, c.name));
+        }
+        out.push_str("--------------------------------------------\n");
+    };
+    if all || section == "titles" {
+        list(&t!("stats.most_titles"), &report.most_circulated_titles);
+        list(&t!("stats.least_titles"), &report.least_circulated_titles);
+    }
+    if all || section == "authors" {
+        list(&t!("stats.most_authors"), &report.most_circulated_authors);
+        list(&t!("stats.least_authors"), &report.least_circulated_authors);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::day;
+
+    fn loans() -> Vec<Loan> {
+        let mut returned = Loan::new("Ada", "Rust", day(2024, 1, 1), 14, "Lee");
+        returned.close(day(2024, 1, 11), 0, "Lee");
+        vec![
+            returned,
+            Loan::new("Kim", "Rust", day(2024, 1, 5), 14, "Lee"),
+            Loan::new("kim", "Lost, Found", day(2024, 2, 3), 14, "Lee"),
+            Loan::new("Lee", "Dune", day(2023, 12, 20), 14, "Lee")
+        ]
+    }
+
+    fn books() -> Vec<(String, String)> {
+        [("Rust", "Klabnik"), ("Dune", "Herbert"), ("Emma", "Austen")].iter()
+            .map(|(t, a)| (t.to_string(), a.to_string()))
+            .collect()
+    }
+
+    fn ledger() -> Vec<LedgerEntry> {
+        vec![
+            LedgerEntry::new("Kim", day(2024, 1, 25), EntryKind::Fine, 300, ""),
+            LedgerEntry::new("Kim", day(2024, 1, 26), EntryKind::Waiver, 100, ""),
+            LedgerEntry::new("Kim", day(2024, 2, 1), EntryKind::Payment, 150, ""),
+            LedgerEntry::new("Lee", day(2023, 12, 1), EntryKind::Payment, 500, "")
+        ]
+    }
+
+    fn sample() -> Report {
+        report(&loans(), &books(), &ledger(), day(2024, 1, 1), day(2024, 2, 29), day(2024, 2, 10), Period::Month, 2)
+    }
+
+    fn names(circulation: &[Circulation]) -> Vec<(&str, usize)> {
+        circulation.iter().map(|c| (c.name.as_str(), c.loans)).collect()
+    }
+
+    #[test]
+    fn summary_counts_only_loans_issued_in_range() {
+        let s = sample().summary;
+        assert_eq!((s.total_loans, s.returned_loans, s.average_loan_days), (3, 1, 10.0));
+        assert_eq!((s.overdue_loans, s.overdue_rate), (1, 0.33));
+        assert_eq!((s.fines_charged, s.fines_collected), (200, 150));
+        assert_eq!(s.active_patrons, 2);
+    }
+
+    #[test]
+    fn loans_are_counted_per_period() {
+        let periods: Vec<(String, usize)> = sample().loans_per_period.into_iter().map(|p| (p.period, p.loans)).collect();
+        assert_eq!(periods, [("2024-01".to_string(), 2), ("2024-02".to_string(), 1)]);
+        assert_eq!(Period::Week.key(day(2024, 1, 1)), "2024-W01");
+    }
+
+    #[test]
+    fn titles_never_lent_are_least_circulated() {
+        let report = sample();
+        assert_eq!(names(&report.most_circulated_titles), [("Rust", 2), ("Lost, Found", 1)]);
+        assert_eq!(names(&report.least_circulated_titles), [("Dune", 0), ("Emma", 0)]);
+        assert_eq!(names(&report.most_circulated_authors), [("Klabnik", 2), ("unknown", 1)]);
+        assert_eq!(names(&report.least_circulated_authors), [("Austen", 0), ("Herbert", 0)]);
+    }
+
+    #[test]
+    fn empty_range_has_no_rates() {
+        let report = report(&loans(), &books(), &[], day(2025, 1, 1), day(2025, 12, 31), day(2025, 6, 1), Period::Year, 2);
+        assert_eq!((report.summary.total_loans, report.summary.average_loan_days, report.summary.overdue_rate), (0, 0.0, 0.0));
+        assert!(report.loans_per_period.is_empty());
+    }
+
+    #[test]
+    fn csv_quotes_names_with_commas() {
+        assert_eq!(to_csv(&sample(), "titles"),
+            "most circulated title,loans\nRust,2\n\"Lost, Found\",1\n\nleast circulated title,loans\nDune,0\nEmma,0\n");
+        assert!(to_csv(&sample(), "summary").contains("fines_charged,$2.00\n"));
+    }
+}
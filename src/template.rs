@@ -0,0 +1,45 @@
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::prelude::*;
+
+/**
+ * Plain text templates with `{name}` placeholders.
+ *
+ * Templates are read from the `templates` directory next to the data files
+ * so the library can customise them, the copy built into the program is
+ * used when the file is not there.
+ */
+const TEMPLATE_DIR: &str = "templates";
+
+pub fn load(name: &str, builtin: &str) -> io::Result<String> {
+    let mut file = match File::open(Path::new(TEMPLATE_DIR).join(name)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(builtin.to_string()),
+        Err(e) => return Err(e),
+        Ok(file) => file
+    };
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    Ok(data)
+}
+
+// replace every `{key}` with its value, unknown placeholders are kept as they are
+pub fn render(template: &str, values: &[(&str, String)]) -> String {
+    let mut output = template.to_string();
+    for (key, value) in values {
+        output = output.replace(&format!("{{{}}}", key), value);
+    }
+    output
+}
+
+// text for xml and html documents
+pub fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// name of a rendered file for a patron, anything but letters and digits becomes `_`
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
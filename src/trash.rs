@@ -0,0 +1,70 @@
+use std::io;
+use std::fmt;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::library::{self, LibraryError};
+use crate::staging::{self, DataFile};
+
+/**
+ * Withdrawn copies, taken out of the library store but kept until they are purged,
+ * one per line in the `trash` file:
+ *
+ *   withdrawn date,staff,reason,name,author,year,borrowed,issue date,item type,isbn
+ *
+ * Everything after the reason is the library store line of the copy, so a
+ * restored copy goes back exactly as it was withdrawn.
+ */
+const TRASH_PATH: &str = "trash";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Withdrawn {
+    pub date: NaiveDate,
+    pub staff: String,
+    pub reason: String,
+    // library store line of the copy, without the line break
+    pub store_line: String
+}
+
+impl Withdrawn {
+    pub fn book(&self) -> Option<crate::Book<'_>> {
+        crate::to_book_list(&self.store_line).ok()?.into_iter().next()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "withdrawn_date": self.date, "staff": self.staff, "reason": self.reason, "book": self.book() })
+    }
+}
+
+impl fmt::Display for Withdrawn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{},{},{},{}", self.date, self.staff, self.reason, self.store_line)
+    }
+}
+
+fn parse_withdrawn(line: &str) -> Option<Withdrawn> {
+    let slice_data: Vec<&str> = line.splitn(4, ',').collect();
+    if slice_data.len() < 4 {
+        return None;
+    }
+    // the store line is kept as it is, `restore_book` reports it if it cannot be read
+    Some(Withdrawn {
+        date: NaiveDate::parse_from_str(slice_data[0].trim(), "%Y-%m-%d").ok()?,
+        staff: slice_data[1].trim().to_string(),
+        reason: slice_data[2].trim().to_string(),
+        store_line: slice_data[3].trim().to_string()
+    })
+}
+
+pub fn load_trash() -> Result<Vec<Withdrawn>, LibraryError> {
+    library::read_records(TRASH_PATH, parse_withdrawn)
+}
+
+// the whole `trash` file, for writes staged together with other files
+pub fn data_file(trash: &[Withdrawn]) -> DataFile {
+    DataFile::new(TRASH_PATH, trash)
+}
+
+pub fn save_trash(trash: &[Withdrawn]) -> io::Result<()> {
+    staging::write_all(&[data_file(trash)])
+}
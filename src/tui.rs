@@ -0,0 +1,522 @@
+use std::io;
+use chrono::NaiveDate;
+use ratatui::{DefaultTerminal, Frame};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap};
+use crate::{Book, DEFAULT_ITEM_TYPE};
+use crate::auth::Staff;
+use crate::clock::Clock;
+use crate::collation;
+use crate::config::Config;
+use crate::hold::{self, Hold};
+use crate::i18n::{self, t};
+use crate::import;
+use crate::ledger;
+use crate::library::{self, BookInput, LibraryError};
+use crate::loan::{self, Loan};
+use crate::patron;
+use crate::receipt::{self, Receipt};
+
+/**
+ * Full-screen terminal frontend, `tui`, over the same library operations as the menu.
+ *
+ *   up/down j/k  move          /  filter by name, author or ISBN
+ *   a  add a book              e  edit the selected book
+ *   i  issue the selected copy r  return the selected copy
+ *   d  withdraw to the trash   q  quit
+ *
+ * Forms check every field while it is typed, a field in red has to be fixed
+ * before the form can be sent. Refusals of the library rules show in the form.
+ */
+// a text field of a form, checked on every key, the label is the catalogue key of its name
+struct Field {
+    label: &'static str,
+    value: String,
+    check: fn(&str) -> Result<(), String>
+}
+
+impl Field {
+    fn new(label: &'static str, value: &str, check: fn(&str) -> Result<(), String>) -> Field {
+        Field { label, value: value.to_string(), check }
+    }
+
+    fn error(&self) -> Option<String> {
+        (self.check)(&self.value).err()
+    }
+}
+
+fn required(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err(t!("check.required"));
+    }
+    optional(value)
+}
+
+fn optional(value: &str) -> Result<(), String> {
+    if value.contains(',') {
+        return Err(t!("check.comma"));
+    }
+    Ok(())
+}
+
+fn year(value: &str) -> Result<(), String> {
+    value.trim().parse::<u32>().map(|_| ()).map_err(|_| t!("check.year"))
+}
+
+fn date(value: &str) -> Result<(), String> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map(|_| ()).map_err(|_| t!("check.date"))
+}
+
+fn isbn(value: &str) -> Result<(), String> {
+    let isbn = import::normalize_isbn(value);
+    if !isbn.is_empty() && !import::is_valid_isbn(&isbn) {
+        return Err(t!("check.isbn"));
+    }
+    Ok(())
+}
+
+// which copy a form is for, the copy number in the library store
+#[derive(Copy, Clone)]
+enum FormKind {
+    Add,
+    Edit(usize),
+    Issue(usize),
+    Return(usize),
+    Withdraw(usize)
+}
+
+struct Form {
+    kind: FormKind,
+    title: String,
+    fields: Vec<Field>,
+    focus: usize,
+    // refusal of the last save
+    error: Option<String>
+}
+
+impl Form {
+    fn value(&self, label: &str) -> &str {
+        self.fields.iter().find(|f| f.label == label).map(|f| f.value.trim()).unwrap_or("")
+    }
+}
+
+struct App<'a> {
+    staff: &'a Staff,
+    config: &'a Config,
+    clock: &'a dyn Clock,
+    // library store, parsed again for every frame
+    data: String,
+    loans: Vec<Loan>,
+    holds: Vec<Hold>,
+    filter: String,
+    filtering: bool,
+    // copy numbers of the books that match the filter
+    visible: Vec<usize>,
+    table: TableState,
+    form: Option<Form>,
+    status: String,
+    quit: bool
+}
+
+impl<'a> App<'a> {
+    // a damaged library store is refused here, so the lists below only ever see one that reads
+    fn reload(&mut self) -> Result<(), LibraryError> {
+        let data = library::read_store()?;
+        crate::to_book_list(&data)?;
+        self.data = data;
+        self.loans = loan::load_loans(self.config.loan_days(DEFAULT_ITEM_TYPE, patron::DEFAULT_CATEGORY))?;
+        self.holds = hold::load_holds()?;
+        self.apply_filter();
+        Ok(())
+    }
+
+    fn apply_filter(&mut self) {
+        let selected = self.selected();
+        let text = collation::fold(&self.filter);
+        self.visible = crate::to_book_list(&self.data).unwrap_or_default()
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| [b.name, b.author, b.isbn].iter().any(|f| collation::fold(f).contains(&text)))
+            .map(|(i, _)| i)
+            .collect();
+        // keep the same copy selected when it is still listed
+        let row = selected.and_then(|s| self.visible.iter().position(|i| *i == s)).unwrap_or(0);
+        self.table.select(if self.visible.is_empty() { None } else { Some(row.min(self.visible.len() - 1)) });
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.table.selected().and_then(|row| self.visible.get(row).copied())
+    }
+
+    fn move_selection(&mut self, rows: i64) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let row = self.table.selected().unwrap_or(0) as i64 + rows;
+        self.table.select(Some(row.clamp(0, self.visible.len() as i64 - 1) as usize));
+    }
+
+    fn open_form(&mut self, kind: FormKind) {
+        let today = self.clock.today().to_string();
+        let books = crate::to_book_list(&self.data).unwrap_or_default();
+        let book = match kind {
+            FormKind::Add => None,
+            FormKind::Edit(i) | FormKind::Issue(i) | FormKind::Return(i) | FormKind::Withdraw(i) => match books.get(i) {
+                None => return,
+                Some(book) => Some(*book)
+            }
+        };
+        let name = book.map(|b| b.name).unwrap_or("");
+        let (title, fields) = match kind {
+            FormKind::Add => (t!("form.add"), book_fields(None)),
+            FormKind::Edit(i) => (t!("form.edit", copy = i, book = name), book_fields(book)),
+            FormKind::Issue(i) => (t!("form.issue", copy = i, book = name), vec![
+                Field::new("field.borrower", "", required),
+                Field::new("field.borrow_date", &today, date)
+            ]),
+            FormKind::Return(i) => {
+                // the borrower of the first open loan of the title, it can be changed
+                let borrower = self.loans.iter()
+                    .find(|l| l.is_open() && l.book_name == name)
+                    .map(|l| l.borrower.as_str())
+                    .unwrap_or("");
+                (t!("form.return", copy = i, book = name), vec![Field::new("field.borrower", borrower, required)])
+            },
+            FormKind::Withdraw(i) => (t!("form.withdraw", copy = i, book = name), vec![
+                Field::new("field.reason", "", required)
+            ])
+        };
+        self.form = Some(Form { kind, title, fields, focus: 0, error: None });
+    }
+
+    // send the form to the library, it stays open with the refusal when it fails
+    fn save_form(&mut self) {
+        let form = match self.form.as_mut() {
+            None => return,
+            Some(form) => form
+        };
+        if form.fields.iter().any(|f| f.error().is_some()) {
+            form.error = Some(t!("form.fix_fields"));
+            return;
+        }
+        let today = self.clock.today();
+        let (staff, config) = (self.staff, self.config);
+        let result = match form.kind {
+            FormKind::Add => library::add_book(&book_input(form, None), today, staff)
+                .map(|i| t!("form.added", book = form.value("field.book_name"), copy = i)),
+            FormKind::Edit(i) => {
+                let issue_date = crate::to_book_list(&self.data).unwrap_or_default().get(i).map(|b| b.issue_date);
+                library::update_book(i, &book_input(form, issue_date), today, staff)
+                    .map(|_| t!("form.updated", copy = i, book = form.value("field.book_name")))
+            },
+            FormKind::Issue(i) => {
+                let borrow_date = NaiveDate::parse_from_str(form.value("field.borrow_date"), "%Y-%m-%d").unwrap_or(today);
+                library::issue(i, form.value("field.borrower"), borrow_date, today, config, staff)
+                    .map(|loan| {
+                        let message = t!("borrow.done", book = loan.book_name, patron = loan.borrower, due = i18n::date(loan.due_date));
+                        with_receipt(message, loan, today, config, staff)
+                    })
+            },
+            FormKind::Return(i) => library::return_book(i, form.value("field.borrower"), today, config, staff)
+                .map(|returned| {
+                    let mut message = t!("return.done", book = returned.loan.book_name, patron = returned.loan.borrower);
+                    if returned.fine > 0 {
+                        message.push_str(&t!("form.fine_on_account", fine = ledger::format_money(returned.fine)));
+                    }
+                    if let Some(hold) = returned.set_aside {
+                        message.push_str(&t!("form.to_hold_shelf", patron = hold.patron));
+                    }
+                    with_receipt(message, returned.loan, today, config, staff)
+                }),
+            FormKind::Withdraw(i) => library::withdraw_book(i, form.value("field.reason"), today, staff)
+                .map(|_| t!("form.withdrawn", copy = i))
+        };
+        match result {
+            Err(err) => form.error = Some(err.to_string()),
+            Ok(message) => {
+                self.form = None;
+                self.status = message;
+                if let Err(err) = self.reload() {
+                    self.status = t!("store.unreadable", error = err);
+                }
+            }
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+        if let Some(form) = self.form.as_mut() {
+            match key.code {
+                KeyCode::Esc => self.form = None,
+                KeyCode::Enter => self.save_form(),
+                KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % form.fields.len(),
+                KeyCode::BackTab | KeyCode::Up => form.focus = (form.focus + form.fields.len() - 1) % form.fields.len(),
+                KeyCode::Backspace => {
+                    form.fields[form.focus].value.pop();
+                },
+                KeyCode::Char(c) => form.fields[form.focus].value.push(c),
+                _ => ()
+            }
+            return;
+        }
+        if self.filtering {
+            match key.code {
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.filtering = false;
+                },
+                KeyCode::Enter => self.filtering = false,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                },
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => return
+            }
+            self.apply_filter();
+            return;
+        }
+        let selected = self.selected();
+        match (key.code, selected) {
+            (KeyCode::Char('q'), _) => self.quit = true,
+            (KeyCode::Esc, _) if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.apply_filter();
+            },
+            (KeyCode::Char('/'), _) => self.filtering = true,
+            (KeyCode::Down, _) | (KeyCode::Char('j'), _) => self.move_selection(1),
+            (KeyCode::Up, _) | (KeyCode::Char('k'), _) => self.move_selection(-1),
+            (KeyCode::PageDown, _) => self.move_selection(10),
+            (KeyCode::PageUp, _) => self.move_selection(-10),
+            (KeyCode::Home, _) | (KeyCode::Char('g'), _) => self.move_selection(i64::MIN / 2),
+            (KeyCode::End, _) | (KeyCode::Char('G'), _) => self.move_selection(i64::MAX / 2),
+            (KeyCode::Char('a'), _) => self.open_form(FormKind::Add),
+            (KeyCode::Char('e'), Some(i)) => self.open_form(FormKind::Edit(i)),
+            (KeyCode::Char('i'), Some(i)) => self.open_form(FormKind::Issue(i)),
+            (KeyCode::Char('r'), Some(i)) => self.open_form(FormKind::Return(i)),
+            (KeyCode::Char('d'), Some(i)) => self.open_form(FormKind::Withdraw(i)),
+            (KeyCode::Char('e'), None) | (KeyCode::Char('i'), None) | (KeyCode::Char('r'), None) | (KeyCode::Char('d'), None) =>
+                self.status = t!("tui.select_first"),
+            _ => ()
+        }
+    }
+}
+
+fn book_fields(book: Option<Book>) -> Vec<Field> {
+    let year = book.map(|b| b.year_published.to_string()).unwrap_or_default();
+    vec![
+        Field::new("field.book_name", book.map(|b| b.name).unwrap_or(""), required),
+        Field::new("field.author", book.map(|b| b.author).unwrap_or(""), required),
+        Field::new("field.published_year", &year, self::year),
+        Field::new("field.item_type", book.map(|b| b.item_type).unwrap_or(DEFAULT_ITEM_TYPE), optional),
+        Field::new("field.isbn", book.map(|b| b.isbn).unwrap_or(""), isbn)
+    ]
+}
+
+// write the receipt of a loan or return, the status line says where it is
+fn with_receipt(message: String, loan: Loan, today: NaiveDate, config: &Config, staff: &Staff) -> String {
+    let mut receipt = Receipt::new(&loan.borrower, today, &staff.name);
+    receipt.loans.push(loan);
+    match receipt::write(&receipt, config) {
+        Err(err) => t!("form.receipt_failed", message = message, error = err),
+        Ok(paths) => match paths.first() {
+            None => message,
+            Some(path) => t!("form.receipt_in", message = message, path = path.display())
+        }
+    }
+}
+
+fn book_input(form: &Form, issue_date: Option<NaiveDate>) -> BookInput {
+    BookInput {
+        name: form.value("field.book_name").to_string(),
+        author: form.value("field.author").to_string(),
+        year_published: form.value("field.published_year").parse().unwrap_or_default(),
+        issue_date,
+        item_type: Some(form.value("field.item_type").to_string()),
+        isbn: Some(form.value("field.isbn").to_string())
+    }
+}
+
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height)
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [title, main, status, help] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(1),
+        Constraint::Length(1)
+    ]).areas(frame.area());
+    let [list, detail] = Layout::horizontal([Constraint::Percentage(62), Constraint::Percentage(38)]).areas(main);
+
+    let books = crate::to_book_list(&app.data).unwrap_or_default();
+    frame.render_widget(Paragraph::new(Line::from(vec![
+        Span::styled(format!(" {} ", t!("tui.title")), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(format!(" {} ({})  {}  {}", app.staff.name, app.staff.role, i18n::date(app.clock.today()),
+            t!("tui.copies", shown = app.visible.len(), count = books.len())))
+    ])).style(Style::default().bg(Color::Blue).fg(Color::White)), title);
+
+    let rows: Vec<Row> = app.visible.iter()
+        .filter_map(|i| books.get(*i).map(|b| (i, b)))
+        .map(|(i, b)| {
+            let status = t!(if b.borrowed { "tui.status.on_loan" } else { "tui.status.available" });
+            Row::new(vec![
+                Cell::from(i.to_string()),
+                Cell::from(b.name),
+                Cell::from(b.author),
+                Cell::from(b.year_published.to_string()),
+                Cell::from(b.item_type),
+                Cell::from(status).style(Style::default().fg(if b.borrowed { Color::Yellow } else { Color::Green }))
+            ])
+        })
+        .collect();
+    let filter_title = if app.filtering || !app.filter.is_empty() {
+        format!(" {} ", t!("tui.books_filter", filter = format!("{}{}", app.filter, if app.filtering { "_" } else { "" })))
+    } else {
+        format!(" {} ", t!("tui.books"))
+    };
+    let table = Table::new(rows, [
+        Constraint::Length(4),
+        Constraint::Percentage(38),
+        Constraint::Percentage(26),
+        Constraint::Length(5),
+        Constraint::Length(10),
+        Constraint::Length(9)
+    ])
+        .header(Row::new(["#".to_string(), t!("tui.column.name"), t!("tui.column.author"), t!("tui.column.year"), t!("tui.column.type"),
+            t!("tui.column.status")]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title(filter_title))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, list, &mut app.table);
+
+    let detail_text = match app.selected().and_then(|i| books.get(i).map(|b| (i, b))) {
+        None => vec![Line::from(t!("tui.none_selected"))],
+        Some((i, book)) => detail_lines(app, i, book)
+    };
+    frame.render_widget(Paragraph::new(detail_text)
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} ", t!("tui.details"))))
+        .wrap(Wrap { trim: true }), detail);
+
+    frame.render_widget(Paragraph::new(app.status.as_str()).style(Style::default().fg(Color::Cyan)), status);
+    let keys = t!(if app.form.is_some() { "tui.help.form" } else if app.filtering { "tui.help.filter" } else { "tui.help" });
+    frame.render_widget(Paragraph::new(keys).style(Style::default().fg(Color::DarkGray)), help);
+
+    if let Some(form) = &app.form {
+        draw_form(frame, form);
+    }
+}
+
+fn detail_lines<'l>(app: &App, index: usize, book: &Book<'l>) -> Vec<Line<'l>> {
+    let today = app.clock.today();
+    let mut lines = vec![
+        Line::from(Span::styled(book.name, Style::default().add_modifier(Modifier::BOLD))),
+        Line::from(t!("tui.by", author = book.author, year = book.year_published)),
+        Line::from(""),
+        detail_line("field.copy", format!("[{}]", index)),
+        detail_line("field.item_type", book.item_type),
+        detail_line("field.isbn", book.isbn),
+        detail_line("field.barcode", book.barcode),
+        detail_line("field.issue_date", i18n::date(book.issue_date)),
+        Line::from("")
+    ];
+    for loan in app.loans.iter().filter(|l| l.is_open() && l.book_name == book.name) {
+        let overdue = loan.days_overdue(today);
+        let style = if overdue > 0 { Style::default().fg(Color::Red) } else { Style::default() };
+        let mut text = t!("tui.on_loan", patron = loan.borrower, due = i18n::date(loan.due_date));
+        if overdue > 0 {
+            text.push_str(&t!("tui.overdue", days = overdue));
+        }
+        lines.push(Line::from(Span::styled(text, style)));
+    }
+    let holds: Vec<&Hold> = app.holds.iter().filter(|h| h.is_for(book.name)).collect();
+    if !holds.is_empty() {
+        lines.push(Line::from(t!("tui.holds", count = holds.len())));
+        for hold in holds {
+            match hold.pickup_until {
+                Some(until) => lines.push(Line::from(format!("  {}", t!("tui.hold_pickup", patron = hold.patron, until = i18n::date(until))))),
+                None => lines.push(Line::from(format!("  {}", t!("tui.hold_since", patron = hold.patron, since = i18n::date(hold.placed_date)))))
+            }
+        }
+    }
+    lines
+}
+
+fn detail_line<'l>(label_key: &str, value: impl std::fmt::Display) -> Line<'l> {
+    Line::from(format!("{} : {}", i18n::pad(&t!(label_key), 14), value))
+}
+
+fn draw_form(frame: &mut Frame, form: &Form) {
+    let height = form.fields.len() as u16 * 2 + 4;
+    let area = centered(frame.area(), 64, height);
+    frame.render_widget(Clear, area);
+    let block = Block::default().borders(Borders::ALL).title(format!(" {} ", form.title));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let mut lines = vec![];
+    let mut cursor = None;
+    for (i, field) in form.fields.iter().enumerate() {
+        let label = format!("{}: ", i18n::pad(&t!(field.label), 15));
+        if i == form.focus {
+            cursor = Some((inner.x + (i18n::width(&label) + i18n::width(&field.value)) as u16, inner.y + lines.len() as u16));
+        }
+        let style = if i == form.focus { Style::default().add_modifier(Modifier::BOLD) } else { Style::default() };
+        lines.push(Line::from(vec![Span::styled(label, style), Span::raw(field.value.clone())]));
+        // the check of a field shows under it while it is typed
+        lines.push(match field.error() {
+            Some(err) => Line::from(Span::styled(format!("{:<17}{}", "", t!("check.field", field = t!(field.label), error = err)),
+                Style::default().fg(Color::Red))),
+            None => Line::from("")
+        });
+    }
+    if let Some(err) = &form.error {
+        lines.push(Line::from(Span::styled(err.clone(), Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))));
+    }
+    frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), inner);
+    if let Some(position) = cursor {
+        frame.set_cursor_position(position);
+    }
+}
+
+fn run(terminal: &mut DefaultTerminal, app: &mut App) -> io::Result<()> {
+    while !app.quit {
+        terminal.draw(|frame| draw(frame, app))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                app.on_key(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn start(clock: &dyn Clock, config: &Config, staff: &Staff) -> Result<(), LibraryError> {
+    let mut app = App {
+        staff,
+        config,
+        clock,
+        data: String::new(),
+        loans: vec![],
+        holds: vec![],
+        filter: String::new(),
+        filtering: false,
+        visible: vec![],
+        table: TableState::default(),
+        form: None,
+        status: t!("session.signed_in", name = staff.name, role = staff.role),
+        quit: false
+    };
+    app.reload()?;
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app);
+    ratatui::restore();
+    Ok(result?)
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::fs::{self, File};
+use std::path::Path;
+use std::io::prelude::*;
+use crate::audit::{self, AuditEntry};
+use crate::i18n::t;
+
+/**
+ * Undo and redo of the changes made in one menu session.
+ *
+ * Each menu action keeps the data files it changed as they were before and
+ * after it, undo puts the before copy back and redo the after copy. A file
+ * that was changed since (by the REST API or another session) is left alone
+ * and the undo is refused, so nobody else's work is lost. The audit trail
+ * gets an `undo` or `redo` entry for every entry the action wrote.
+ */
+const DATA_FILES: &[&str] = &["librarystore", "borrower", "ledger", "patrons", "holds", "trash"];
+
+// data file path and its content, None when the file does not exist
+type FileState = (&'static str, Option<String>);
+
+fn read_state(path: &'static str) -> io::Result<FileState> {
+    match File::open(Path::new(path)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok((path, None)),
+        Err(e) => Err(e),
+        Ok(mut file) => {
+            let mut data = String::new();
+            file.read_to_string(&mut data)?;
+            Ok((path, Some(data)))
+        }
+    }
+}
+
+fn write_state((path, content): &FileState) -> io::Result<()> {
+    match content {
+        None => match fs::remove_file(Path::new(path)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result
+        },
+        Some(data) => File::create(Path::new(path))?.write_all(data.as_bytes())
+    }
+}
+
+// the data files before a menu action, see History::record
+pub struct Pending {
+    files: Vec<FileState>,
+    audit_entries: usize
+}
+
+struct Action {
+    description: String,
+    before: Vec<FileState>,
+    after: Vec<FileState>,
+    audit: Vec<AuditEntry>
+}
+
+pub struct History {
+    done: Vec<Action>,
+    undone: Vec<Action>,
+    depth: usize
+}
+
+impl History {
+    pub fn new(depth: usize) -> History {
+        History { done: vec![], undone: vec![], depth }
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn redo_count(&self) -> usize {
+        self.undone.len()
+    }
+
+    pub fn begin(&self) -> io::Result<Pending> {
+        Ok(Pending {
+            files: DATA_FILES.iter().map(|path| read_state(path)).collect::<io::Result<_>>()?,
+            audit_entries: audit::load_entries()?.len()
+        })
+    }
+
+    // keep the files a menu action changed, actions that changed nothing are not kept
+    pub fn record(&mut self, pending: Pending, label: &str) -> io::Result<()> {
+        let mut before = vec![];
+        let mut after = vec![];
+        for state in pending.files {
+            let now = read_state(state.0)?;
+            if now != state {
+                before.push(state);
+                after.push(now);
+            }
+        }
+        if before.is_empty() {
+            return Ok(());
+        }
+        let audit: Vec<AuditEntry> = audit::load_entries()?.into_iter().skip(pending.audit_entries).collect();
+        let description = match audit.first() {
+            Some(entry) => format!("{} ({})", entry.operation, entry.record),
+            None => label.to_string()
+        };
+        self.done.push(Action { description, before, after, audit });
+        if self.done.len() > self.depth {
+            self.done.remove(0);
+        }
+        // a new change starts a new line of history
+        self.undone.clear();
+        Ok(())
+    }
+
+    // undo the last action, returns what it was
+    pub fn undo(&mut self, staff: &str) -> Result<String, String> {
+        let action = self.done.pop().ok_or_else(|| t!("undo.nothing"))?;
+        match apply(&action, true, staff) {
+            Err(err) => {
+                self.done.push(action);
+                Err(err)
+            },
+            Ok(_) => {
+                let description = action.description.clone();
+                self.undone.push(action);
+                Ok(description)
+            }
+        }
+    }
+
+    // do the last undone action again, returns what it was
+    pub fn redo(&mut self, staff: &str) -> Result<String, String> {
+        let action = self.undone.pop().ok_or_else(|| t!("redo.nothing"))?;
+        match apply(&action, false, staff) {
+            Err(err) => {
+                self.undone.push(action);
+                Err(err)
+            },
+            Ok(_) => {
+                let description = action.description.clone();
+                self.done.push(action);
+                Ok(description)
+            }
+        }
+    }
+}
+
+// move the files of an action back or forth, only when nobody changed them since
+fn apply(action: &Action, undo: bool, staff: &str) -> Result<(), String> {
+    let (from, to, operation) = if undo {
+        (&action.after, &action.before, "undo")
+    } else {
+        (&action.before, &action.after, "redo")
+    };
+    for state in from {
+        if read_state(state.0).map_err(|e| e.to_string())? != *state {
+            return Err(t!("undo.changed", file = state.0, action = action.description));
+        }
+    }
+    for state in to {
+        write_state(state).map_err(|e| t!("undo.write_failed", file = state.0, error = e))?;
+    }
+    // an undo reverts the entries of the action last first, with before and after swapped
+    let mut entries: Vec<(&AuditEntry, &Option<_>, &Option<_>)> = action.audit.iter()
+        .map(|e| if undo { (e, &e.after, &e.before) } else { (e, &e.before, &e.after) })
+        .collect();
+    if undo {
+        entries.reverse();
+    }
+    for (entry, before, after) in entries {
+        audit::record(staff, &format!("{} {}", operation, entry.operation), &entry.record, before.clone(), after.clone())
+            .map_err(|e| t!("cli.audit_failed", error = e))?;
+    }
+    Ok(())
+}